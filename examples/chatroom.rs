@@ -1,11 +1,14 @@
-use std::collections::HashMap;
+use std::time::Duration;
 use ws_hotel::{
-    CloseCode, Context, Message, Relocation, ResultRelocation, Room, RoomHandler, RoomRef,
-    RoomRefWeak,
+    Context, LeaveReason, Message, Relocation, ResultRelocation, Room, RoomHandler, RoomRef,
+    RoomRefWeak, RoomRegistry,
 };
 
+/// Chat rooms are kept for a minute after their last member left
+const ROOM_TTL: Duration = Duration::from_secs(60);
+
 #[derive(Debug, Default)]
-struct ChatRooms(HashMap<String, RoomRefWeak<ChatRoom>>);
+struct ChatRooms(RoomRegistry<String, ChatRoom>);
 
 impl ChatRooms {
     pub fn find_or_create_room(
@@ -13,25 +16,22 @@ impl ChatRooms {
         name: &str,
         lobby: &RoomRefWeak<Lobby>,
     ) -> RoomRef<ChatRoom> {
-        let rooms = &mut self.0;
-
-        if let Some(weak) = rooms.get(name) {
-            if let Some(strong) = weak.upgrade() {
-                return strong;
-            }
+        if let Some(room) = self.0.get(name) {
+            return room;
         }
 
         println!("creating new chatroom: {}", name);
 
-        let room = Room::new(ChatRoom {
-            lobby: lobby.clone(),
-            name: name.into(),
-            message: Vec::new(),
-        });
+        self.0.get_or_insert_with(String::from(name), || {
+            let room = Room::new(ChatRoom {
+                lobby: lobby.clone(),
+                name: name.into(),
+                message: Vec::new(),
+            });
 
-        rooms.insert(String::from(name), room.downgrade());
-
-        room
+            room.set_idle_ttl(ROOM_TTL);
+            room
+        })
     }
 }
 
@@ -96,30 +96,30 @@ struct ChatRoom {
 
 impl Drop for ChatRoom {
     fn drop(&mut self) {
-        println!("dropping chatroom {}", &self.name);
+        println!("dropping chatroom {}", self.name);
     }
 }
 
 impl RoomHandler for ChatRoom {
     type Guest = String;
 
-    fn on_join(&mut self, mut cx: Context<Self>) -> ResultRelocation {
-        let message = format!("[SERVER]: {} entered the room", cx.identity().as_str());
+    fn on_join(&mut self, cx: Context<Self>) -> ResultRelocation {
+        let message = format!("[SERVER]: {} entered the room", cx.identity_ref().as_str());
         cx.broadcast(message)?;
 
         Ok(None)
     }
 
-    fn on_message(&mut self, mut cx: Context<Self>, msg: Message) -> ResultRelocation {
+    fn on_message(&mut self, cx: Context<Self>, msg: Message) -> ResultRelocation {
         let msg = msg.as_text().unwrap_or_default();
 
         if msg == "/leave" {
-            let name = Some(cx.identity().clone());
+            let name = Some(cx.identity_ref().clone());
             let lobby = self.lobby.upgrade().expect("lobby was dropped");
-            return Ok(Some(Relocation::from(Relocation::new(&lobby, name))));
+            return Ok(Some(Relocation::new(&lobby, name)));
         }
 
-        let name = cx.identity().as_str();
+        let name = cx.identity_ref().as_str();
 
         let message = format!("{}: {}", name, msg);
         cx.broadcast(&*message)?;
@@ -128,17 +128,25 @@ impl RoomHandler for ChatRoom {
         Ok(None)
     }
 
-    fn on_leave(&mut self, mut cx: Context<Self>, code_and_reason: Option<(CloseCode, &str)>) {
-        let name = cx.identity().as_str();
-        let message = format!(
-            "[SERVER]: {} left the room (reason: {:?})",
-            name, code_and_reason,
-        );
+    fn on_leave(&mut self, cx: Context<Self>, reason: LeaveReason) {
+        let name = cx.identity_ref().as_str();
+        let message = match reason {
+            LeaveReason::Relocated { .. } => format!("[SERVER]: {} left the room", name),
+            LeaveReason::Disconnected { code, reason } => format!(
+                "[SERVER]: {} disconnected (code: {:?}, reason: {:?})",
+                name, code, reason,
+            ),
+            LeaveReason::Kicked => format!("[SERVER]: {} was kicked", name),
+            LeaveReason::ServerShutdown => return,
+        };
 
         cx.broadcast(message).unwrap();
     }
 }
 
 pub fn main() {
-    ws_hotel::listen("localhost:8080", Lobby::new());
+    if let Err(err) = ws_hotel::listen("localhost:8080", Lobby::new()) {
+        eprintln!("failed to start the server: {}", err);
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,89 @@
+//! The background thread waking rooms up, see [`RoomRef::set_tick_interval`] and
+//! [`RoomRef::set_lifetime`].
+//!
+//! [`RoomRef::set_tick_interval`]: crate::RoomRef::set_tick_interval
+//! [`RoomRef::set_lifetime`]: crate::RoomRef::set_lifetime
+
+use crate::scheduler::POLL;
+use crate::Clock;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::Instant;
+
+type RingFn = dyn FnMut() -> Option<Instant> + Send;
+
+struct Alarm {
+    at: Instant,
+    clock: Arc<dyn Clock>,
+    ring: Box<RingFn>,
+}
+
+/// Calls `ring` from the alarm thread once `clock` reaches `at`, then again each time it reaches
+/// the instant returned by `ring`, until it returns `None`.
+///
+/// Alarms ring one after the other, so `ring` must not block. One that panics is dropped.
+pub(crate) fn set<F>(clock: Arc<dyn Clock>, at: Instant, ring: F)
+where
+    F: FnMut() -> Option<Instant> + Send + 'static,
+{
+    static ALARMS: OnceLock<Sender<Alarm>> = OnceLock::new();
+
+    let alarms = ALARMS.get_or_init(|| {
+        let (alarms, set) = mpsc::channel();
+        thread::spawn(move || run(set));
+        alarms
+    });
+
+    let _ = alarms.send(Alarm {
+        at,
+        clock,
+        ring: Box::new(ring),
+    });
+}
+
+fn run(set: Receiver<Alarm>) {
+    let mut alarms = Vec::<Alarm>::new();
+
+    loop {
+        // Capped, so that clocks moved forward by tests are noticed
+        let wait = alarms
+            .iter()
+            .map(|alarm| {
+                alarm
+                    .at
+                    .saturating_duration_since(alarm.clock.now())
+                    .min(POLL)
+            })
+            .min();
+
+        let received = match wait {
+            Some(wait) => set.recv_timeout(wait),
+            None => set.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+
+        match received {
+            Ok(alarm) => {
+                alarms.push(alarm);
+                alarms.extend(set.try_iter());
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+
+        alarms.retain_mut(|alarm| {
+            if alarm.clock.now() < alarm.at {
+                return true;
+            }
+
+            match panic::catch_unwind(AssertUnwindSafe(&mut alarm.ring)) {
+                Ok(Some(at)) => {
+                    alarm.at = at;
+                    true
+                }
+                Ok(None) | Err(_) => false,
+            }
+        });
+    }
+}
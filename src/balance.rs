@@ -0,0 +1,95 @@
+//! Strategies to spread incoming connections across several equivalent lobbies, see
+//! [`listen_balanced`][crate::listen_balanced].
+
+use crate::{RoomHandler, RoomRef};
+
+/// Picks the lobby in which a new connection will be put.
+///
+/// It is implemented for closures taking the list of lobbies and returning an index in it, so
+/// custom strategies don't need their own type.
+pub trait Balance<R: RoomHandler> {
+    /// Returns the index of the lobby the new connection should go to. `lobbies` is never empty.
+    fn pick(&mut self, lobbies: &[RoomRef<R>]) -> usize;
+}
+
+impl<R: RoomHandler, F: FnMut(&[RoomRef<R>]) -> usize> Balance<R> for F {
+    fn pick(&mut self, lobbies: &[RoomRef<R>]) -> usize {
+        self(lobbies)
+    }
+}
+
+/// Assigns connections to each lobby in turn.
+#[derive(Clone, Debug, Default)]
+pub struct RoundRobin(usize);
+
+impl<R: RoomHandler> Balance<R> for RoundRobin {
+    fn pick(&mut self, lobbies: &[RoomRef<R>]) -> usize {
+        let index = self.0 % lobbies.len();
+        self.0 = index + 1;
+        index
+    }
+}
+
+/// Assigns connections to the lobby with the fewest members, the first one winning ties.
+#[derive(Clone, Debug, Default)]
+pub struct LeastMembers;
+
+impl<R: RoomHandler> Balance<R> for LeastMembers {
+    fn pick(&mut self, lobbies: &[RoomRef<R>]) -> usize {
+        least(lobbies.iter().map(RoomRef::len))
+    }
+}
+
+/// Index of the smallest of `lens`, the first one winning ties
+fn least(lens: impl Iterator<Item = usize>) -> usize {
+    lens.enumerate()
+        .min_by_key(|(_, len)| *len)
+        .map(|(index, _)| index)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Context, Message, ResultRelocation, Room};
+
+    struct Lobby;
+
+    impl RoomHandler for Lobby {
+        type Guest = ();
+
+        fn on_message(&mut self, _cx: Context<Self>, _msg: Message) -> ResultRelocation {
+            Ok(None)
+        }
+    }
+
+    #[test]
+    fn round_robin_cycles_through_lobbies() {
+        let lobbies = vec![Room::new(Lobby), Room::new(Lobby), Room::new(Lobby)];
+        let mut balancer = RoundRobin::default();
+
+        let picks = (0..7).map(|_| balancer.pick(&lobbies)).collect::<Vec<_>>();
+        assert_eq!(picks, [0, 1, 2, 0, 1, 2, 0]);
+
+        // Keeps cycling when lobbies are removed
+        assert_eq!(balancer.pick(&lobbies[..1]), 0);
+        assert_eq!(balancer.pick(&lobbies[..2]), 1);
+    }
+
+    #[test]
+    fn least_members_picks_the_emptiest_lobby() {
+        assert_eq!(least([3, 1, 2].iter().copied()), 1);
+        assert_eq!(least([2, 0, 0].iter().copied()), 1);
+        assert_eq!(least([5].iter().copied()), 0);
+
+        let lobbies = vec![Room::new(Lobby), Room::new(Lobby)];
+        assert_eq!(LeastMembers.pick(&lobbies), 0);
+    }
+
+    #[test]
+    fn closures_are_balancers() {
+        let lobbies = vec![Room::new(Lobby), Room::new(Lobby)];
+        let mut last = |lobbies: &[RoomRef<Lobby>]| lobbies.len() - 1;
+        assert_eq!(last.pick(&lobbies), 1);
+    }
+}
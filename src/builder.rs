@@ -0,0 +1,122 @@
+use crate::validate::ValidateFn;
+use crate::{Metadata, Room, RoomHandler, RoomRef, WhenFull};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Starts the ticks of a room, only available for rooms that can be sent to the ticking thread
+type StartTicks<R> = fn(&RoomRef<R>, Duration);
+
+/// Creates a room with its settings, see [`Room::builder`].
+///
+/// Every setting can also be changed once the room exists, with the matching method of
+/// [RoomRef], except for its [Metadata] which is immutable.
+///
+/// ```
+/// # use ws_hotel::*;
+/// # use std::time::Duration;
+/// # struct Game;
+/// # impl RoomHandler for Game {
+/// #     type Guest = String;
+/// #     fn on_message(&mut self, _: Context<Self>, _: Message) -> ResultRelocation { Ok(None) }
+/// # }
+/// let game = Room::builder(Game)
+///     .name("game #1")
+///     .tag("ranked")
+///     .capacity(4, WhenFull::Notify)
+///     .idle_ttl(Duration::from_secs(300))
+///     .tick_interval(Duration::from_millis(50))
+///     .max_message_size(1024)
+///     .build();
+/// ```
+pub struct RoomBuilder<R: RoomHandler> {
+    handler: R,
+    name: Option<String>,
+    capacity: Option<(usize, WhenFull<R::Guest>)>,
+    idle_ttl: Option<Duration>,
+    tick_interval: Option<(Duration, StartTicks<R>)>,
+    max_message_size: Option<usize>,
+    pub(crate) validate: Option<ValidateFn<R>>,
+    pub(crate) metadata: Metadata,
+}
+
+impl<R: RoomHandler> Room<R> {
+    /// Starts building a room, to create it with some of its settings at once
+    pub fn builder(handler: R) -> RoomBuilder<R> {
+        RoomBuilder {
+            handler,
+            name: None,
+            capacity: None,
+            idle_ttl: None,
+            tick_interval: None,
+            max_message_size: None,
+            validate: None,
+            metadata: Metadata::default(),
+        }
+    }
+}
+
+impl<R: RoomHandler> RoomBuilder<R> {
+    /// See [`RoomRef::set_name`]
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// See [`RoomRef::set_capacity`]
+    pub fn capacity(mut self, max: usize, when_full: WhenFull<R::Guest>) -> Self {
+        self.capacity = Some((max, when_full));
+        self
+    }
+
+    /// See [`RoomRef::set_idle_ttl`]
+    pub fn idle_ttl(mut self, ttl: Duration) -> Self {
+        self.idle_ttl = Some(ttl);
+        self
+    }
+
+    /// See [`RoomRef::set_max_message_size`]
+    pub fn max_message_size(mut self, bytes: usize) -> Self {
+        self.max_message_size = Some(bytes);
+        self
+    }
+
+    /// Creates the room
+    pub fn build(self) -> RoomRef<R> {
+        let room = Room::new(self.handler);
+        {
+            let mut lock = room.0.lock().unwrap();
+            lock.metadata = Arc::new(self.metadata);
+            lock.validate = self.validate;
+        }
+
+        if let Some(name) = self.name {
+            room.set_name(name);
+        }
+        if let Some((max, when_full)) = self.capacity {
+            room.set_capacity(max, when_full);
+        }
+        if let Some(ttl) = self.idle_ttl {
+            room.set_idle_ttl(ttl);
+        }
+        if let Some(bytes) = self.max_message_size {
+            room.set_max_message_size(bytes);
+        }
+        if let Some((interval, start)) = self.tick_interval {
+            start(&room, interval);
+        }
+
+        room
+    }
+}
+
+impl<R> RoomBuilder<R>
+where
+    R: RoomHandler + Send + 'static,
+    R::Guest: Send,
+{
+    /// See [`RoomRef::set_tick_interval`]
+    pub fn tick_interval(mut self, interval: Duration) -> Self {
+        self.tick_interval = Some((interval, RoomRef::set_tick_interval));
+        self
+    }
+}
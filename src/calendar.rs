@@ -0,0 +1,377 @@
+//! Jobs run at fixed times of the day, see [`Hotel::schedule_at`].
+
+use crate::{Clock, Hotel, Room, RoomContext, RoomHandler, RoomRef};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+
+/// When a job [scheduled at fixed times][Hotel::schedule_at] runs, in UTC.
+///
+/// It can be parsed from `"HH:MM"` for every day at that time, or from `"*:MM"` for every hour at
+/// that minute.
+///
+/// ```
+/// # use ws_hotel::Calendar;
+/// assert_eq!("04:00".parse(), Ok(Calendar::daily(4, 0)));
+/// assert_eq!("*:30".parse(), Ok(Calendar::hourly(30)));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Calendar {
+    /// Time between two runs, in seconds
+    period: u64,
+
+    /// Time of the first run after the Unix epoch, in seconds
+    offset: u64,
+}
+
+impl Calendar {
+    /// Every day at `hour:minute`
+    ///
+    /// # Panics
+    ///
+    /// If `hour` isn't lower than 24 or `minute` than 60.
+    pub fn daily(hour: u8, minute: u8) -> Self {
+        assert!(hour < 24, "invalid hour {}", hour);
+        assert!(minute < 60, "invalid minute {}", minute);
+
+        Self {
+            period: DAY,
+            offset: u64::from(hour) * HOUR + u64::from(minute) * 60,
+        }
+    }
+
+    /// Every hour at `minute`
+    ///
+    /// # Panics
+    ///
+    /// If `minute` isn't lower than 60.
+    pub fn hourly(minute: u8) -> Self {
+        assert!(minute < 60, "invalid minute {}", minute);
+
+        Self {
+            period: HOUR,
+            offset: u64::from(minute) * 60,
+        }
+    }
+
+    /// Number of runs at or before `wall`, a time since the Unix epoch
+    fn count(&self, wall: Duration) -> u64 {
+        match wall.as_secs().checked_sub(self.offset) {
+            Some(since) => since / self.period + 1,
+            None => 0,
+        }
+    }
+
+    /// The first run after `wall`, a time since the Unix epoch
+    fn next(&self, wall: Duration) -> Duration {
+        Duration::from_secs(self.offset + self.count(wall) * self.period)
+    }
+
+    /// The last run at or before `wall`, a time since the Unix epoch, if there is one
+    fn last(&self, wall: Duration) -> Option<Duration> {
+        let count = self.count(wall).checked_sub(1)?;
+        Some(Duration::from_secs(self.offset + count * self.period))
+    }
+}
+
+impl FromStr for Calendar {
+    type Err = InvalidCalendar;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (hour, minute) = s.split_once(':').ok_or(InvalidCalendar)?;
+        let digits =
+            |s: &str, len| (1..=len).contains(&s.len()) && s.bytes().all(|b| b.is_ascii_digit());
+
+        if minute.len() != 2 || !digits(minute, 2) {
+            return Err(InvalidCalendar);
+        }
+        let minute = minute.parse().map_err(|_| InvalidCalendar)?;
+        if minute >= 60 {
+            return Err(InvalidCalendar);
+        }
+
+        if hour == "*" {
+            return Ok(Self::hourly(minute));
+        }
+
+        if !digits(hour, 2) {
+            return Err(InvalidCalendar);
+        }
+        match hour.parse() {
+            Ok(hour) if hour < 24 => Ok(Self::daily(hour, minute)),
+            _ => Err(InvalidCalendar),
+        }
+    }
+}
+
+impl Display for Calendar {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let minute = self.offset % HOUR / 60;
+
+        if self.period == HOUR {
+            write!(f, "*:{:02}", minute)
+        } else {
+            write!(f, "{:02}:{:02}", self.offset / HOUR, minute)
+        }
+    }
+}
+
+/// The error returned when parsing an invalid [Calendar]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InvalidCalendar;
+
+impl Display for InvalidCalendar {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid calendar, expected HH:MM or *:MM")
+    }
+}
+
+impl Error for InvalidCalendar {}
+
+/// What a job [scheduled at fixed times][Hotel::schedule_at] does when some of its runs were
+/// missed, because the jobs before it took too long or the [Clock] of the hotel jumped forward.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Missed {
+    /// It runs once, as soon as possible, for all the missed runs
+    RunOnce,
+
+    /// It runs as many times as it missed, back to back
+    RunAll,
+
+    /// It only runs if its last run is less than the given duration late, and waits for the next
+    /// one otherwise
+    Skip(Duration),
+}
+
+/// Maps the instants of a [Clock] to times since the Unix epoch, as they were when the hotel
+/// started
+pub(crate) struct Epoch {
+    instant: Instant,
+    wall: Duration,
+}
+
+impl Epoch {
+    pub(crate) fn now(clock: &dyn Clock) -> Self {
+        Self {
+            instant: clock.now(),
+            wall: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default(),
+        }
+    }
+
+    fn wall(&self, instant: Instant) -> Duration {
+        self.wall + instant.saturating_duration_since(self.instant)
+    }
+
+    fn instant(&self, wall: Duration) -> Instant {
+        self.instant + wall.saturating_sub(self.wall)
+    }
+}
+
+impl Calendar {
+    /// The first run after `now`
+    pub(crate) fn next_after(&self, epoch: &Epoch, now: Instant) -> Instant {
+        epoch.instant(self.next(epoch.wall(now)))
+    }
+
+    /// How many times a job due at `at` runs when the scheduler gets to it at `now`
+    pub(crate) fn runs(&self, missed: Missed, epoch: &Epoch, at: Instant, now: Instant) -> u64 {
+        let (at, now) = (epoch.wall(at), epoch.wall(now));
+
+        match missed {
+            Missed::RunOnce => 1,
+            Missed::RunAll => 1 + self.count(now).saturating_sub(self.count(at)),
+            Missed::Skip(late) => {
+                let last = self.last(now).unwrap_or(at).max(at);
+                u64::from(now.saturating_sub(last) <= late)
+            }
+        }
+    }
+}
+
+impl<R: RoomHandler, S> Hotel<R, S> {
+    /// Runs `job` on `room` at the times of `calendar`, e.g. to reset a leaderboard every day at
+    /// 04:00, from the time the hotel starts until it stops.
+    ///
+    /// Like [`Hotel::schedule`], it runs in the thread of the scheduled jobs, after the others due
+    /// before it, so some of its runs can be late or missed, which `missed` decides about. Times
+    /// are in UTC, mapped to the [Clock] of the hotel when it starts: later changes of the system
+    /// time aren't followed. The job only holds a weak reference to `room`, and does nothing once
+    /// the room is dropped.
+    ///
+    /// ```no_run
+    /// # use ws_hotel::*;
+    /// # struct Lobby;
+    /// # impl RoomHandler for Lobby {
+    /// #     type Guest = ();
+    /// #     fn on_message(&mut self, _: Context<Self>, _: Message) -> ResultRelocation { Ok(None) }
+    /// # }
+    /// # #[derive(Default)]
+    /// # struct Leaderboard { scores: Vec<u32> }
+    /// # impl RoomHandler for Leaderboard {
+    /// #     type Guest = ();
+    /// #     fn on_message(&mut self, _: Context<Self>, _: Message) -> ResultRelocation { Ok(None) }
+    /// # }
+    /// let leaderboard = RoomRef::from(Leaderboard::default());
+    ///
+    /// Hotel::new(Lobby)
+    ///     .schedule_at(Calendar::daily(4, 0), Missed::RunOnce, &leaderboard, |room, cx| {
+    ///         room.scores.clear();
+    ///         let _ = cx.broadcast("leaderboard reset");
+    ///     })
+    ///     .run("127.0.0.1:8080")
+    ///     .unwrap();
+    /// ```
+    pub fn schedule_at<T, F>(
+        self,
+        calendar: Calendar,
+        missed: Missed,
+        room: &RoomRef<T>,
+        mut job: F,
+    ) -> Self
+    where
+        T: RoomHandler + Send + 'static,
+        T::Guest: Send,
+        F: FnMut(&mut T, RoomContext<T>) + Send + 'static,
+    {
+        let room = room.downgrade();
+        self.schedule_when(calendar, missed, move |_| {
+            if let Some(room) = room.upgrade() {
+                Room::dispatch_room(&room.0, |handler, cx| job(handler, cx));
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::ManualClock;
+
+    /// 2021-01-01T00:00:00Z
+    const JAN_1: u64 = 1_609_459_200;
+
+    fn at(day: u64, hour: u64, minute: u64, second: u64) -> Duration {
+        Duration::from_secs(JAN_1 + day * DAY + hour * HOUR + minute * 60 + second)
+    }
+
+    /// An epoch mapping the current instant of `clock` to `wall`
+    fn epoch(clock: &ManualClock, wall: Duration) -> Epoch {
+        Epoch {
+            instant: clock.now(),
+            wall,
+        }
+    }
+
+    #[test]
+    fn counts_runs() {
+        let daily = Calendar::daily(4, 0);
+        assert_eq!(daily.count(Duration::ZERO), 0);
+        assert_eq!(daily.count(Duration::from_secs(4 * HOUR - 1)), 0);
+        assert_eq!(daily.count(Duration::from_secs(4 * HOUR)), 1);
+
+        let before = daily.count(at(0, 3, 59, 59));
+        assert_eq!(daily.count(at(0, 4, 0, 0)), before + 1);
+        assert_eq!(daily.count(at(1, 3, 59, 59)), before + 1);
+        assert_eq!(daily.count(at(1, 4, 0, 0)), before + 2);
+
+        let hourly = Calendar::hourly(30);
+        assert_eq!(
+            hourly.count(at(0, 0, 30, 0)) + 23,
+            hourly.count(at(0, 23, 30, 0))
+        );
+    }
+
+    #[test]
+    fn finds_next_and_last_runs() {
+        let daily = Calendar::daily(4, 0);
+        assert_eq!(daily.next(at(0, 3, 59, 59)), at(0, 4, 0, 0));
+        assert_eq!(daily.next(at(0, 4, 0, 0)), at(1, 4, 0, 0));
+        assert_eq!(daily.last(at(0, 4, 0, 0)), Some(at(0, 4, 0, 0)));
+        assert_eq!(daily.last(at(1, 3, 59, 59)), Some(at(0, 4, 0, 0)));
+        assert_eq!(daily.last(Duration::from_secs(60)), None);
+
+        let hourly = Calendar::hourly(15);
+        assert_eq!(hourly.next(at(0, 23, 15, 0)), at(1, 0, 15, 0));
+        assert_eq!(hourly.last(at(0, 23, 14, 59)), Some(at(0, 22, 15, 0)));
+    }
+
+    #[test]
+    fn maps_runs_to_the_clock() {
+        let clock = ManualClock::new();
+        let epoch = epoch(&clock, at(0, 3, 59, 0));
+        let daily = Calendar::daily(4, 0);
+
+        let first = daily.next_after(&epoch, clock.now());
+        assert_eq!(first - clock.now(), Duration::from_secs(60));
+
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(clock.now(), first);
+        assert_eq!(
+            daily.next_after(&epoch, clock.now()) - first,
+            Duration::from_secs(DAY)
+        );
+    }
+
+    #[test]
+    fn runs_missed_jobs() {
+        let clock = ManualClock::new();
+        let epoch = epoch(&clock, at(0, 4, 0, 0));
+        let daily = Calendar::daily(4, 0);
+        let due = clock.now();
+
+        assert_eq!(daily.runs(Missed::RunOnce, &epoch, due, due), 1);
+        assert_eq!(daily.runs(Missed::RunAll, &epoch, due, due), 1);
+
+        clock.advance(Duration::from_secs(2 * DAY + 60));
+        let now = clock.now();
+        assert_eq!(daily.runs(Missed::RunOnce, &epoch, due, now), 1);
+        assert_eq!(daily.runs(Missed::RunAll, &epoch, due, now), 3);
+    }
+
+    #[test]
+    fn skips_late_runs() {
+        let clock = ManualClock::new();
+        let epoch = epoch(&clock, at(0, 4, 0, 0));
+        let daily = Calendar::daily(4, 0);
+        let due = clock.now();
+        let skip = Missed::Skip(Duration::from_secs(5 * 60));
+
+        clock.advance(Duration::from_secs(5 * 60));
+        assert_eq!(daily.runs(skip, &epoch, due, clock.now()), 1);
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(daily.runs(skip, &epoch, due, clock.now()), 0);
+
+        // A later run that is itself less late counts
+        clock.advance(Duration::from_secs(DAY - 5 * 60 - 1 + 3 * 60));
+        assert_eq!(daily.runs(skip, &epoch, due, clock.now()), 1);
+    }
+
+    #[test]
+    fn parses_and_displays() {
+        assert_eq!("00:00".parse(), Ok(Calendar::daily(0, 0)));
+        assert_eq!("4:05".parse(), Ok(Calendar::daily(4, 5)));
+        assert_eq!("23:59".parse(), Ok(Calendar::daily(23, 59)));
+        assert_eq!("*:00".parse(), Ok(Calendar::hourly(0)));
+
+        for invalid in [
+            "", "04", "24:00", "04:60", "04:5", "004:00", "*:5", "+4:00", "04:-1",
+        ] {
+            assert_eq!(
+                invalid.parse::<Calendar>(),
+                Err(InvalidCalendar),
+                "{}",
+                invalid
+            );
+        }
+
+        assert_eq!(Calendar::daily(4, 5).to_string(), "04:05");
+        assert_eq!(Calendar::hourly(30).to_string(), "*:30");
+    }
+}
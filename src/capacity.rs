@@ -0,0 +1,66 @@
+use crate::{Relocation, Room, RoomHandler, RoomRef};
+use std::fmt::{Debug, Formatter};
+
+type OverflowFn<G> = dyn Fn(G) -> Relocation + Send;
+
+/// What happens to a client that is relocated into a room that is
+/// [full][RoomRef::set_capacity].
+pub enum WhenFull<G> {
+    /// The relocation is cancelled and the client silently stays in the room it was in
+    Stay,
+
+    /// The relocation is cancelled, and the room the client stays in is told with
+    /// [`RoomHandler::on_relocation_refused`]
+    Notify,
+
+    /// The client is relocated elsewhere instead, for instance in an overflow room, by the
+    /// relocation built from its identity
+    Overflow(Box<OverflowFn<G>>),
+}
+
+impl<G> WhenFull<G> {
+    /// Shorthand for [`WhenFull::Overflow`]
+    pub fn overflow<F: Fn(G) -> Relocation + Send + 'static>(f: F) -> Self {
+        Self::Overflow(Box::new(f))
+    }
+}
+
+impl<G> Debug for WhenFull<G> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Stay => f.write_str("Stay"),
+            Self::Notify => f.write_str("Notify"),
+            Self::Overflow(_) => f.write_str("Overflow(_)"),
+        }
+    }
+}
+
+pub(crate) struct Capacity<G> {
+    max: usize,
+    when_full: WhenFull<G>,
+}
+
+impl<R: RoomHandler> RoomRef<R> {
+    /// Limits the number of members of the room to `max`. Relocations into the room while it is
+    /// full are handled according to `when_full`.
+    ///
+    /// Clients connecting to the room because it is their lobby are always accepted.
+    pub fn set_capacity(&self, max: usize, when_full: WhenFull<R::Guest>) {
+        self.0.lock().unwrap().capacity = Some(Capacity { max, when_full });
+    }
+
+    /// Removes the limit set by [`RoomRef::set_capacity`]
+    pub fn unset_capacity(&self) {
+        self.0.lock().unwrap().capacity = None;
+    }
+}
+
+impl<R: RoomHandler> Room<R> {
+    /// Returns the policy of the room if it is full
+    pub(crate) fn when_full(&self) -> Option<&WhenFull<R::Guest>> {
+        self.capacity
+            .as_ref()
+            .filter(|capacity| self.members.len() >= capacity.max)
+            .map(|capacity| &capacity.when_full)
+    }
+}
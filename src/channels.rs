@@ -0,0 +1,49 @@
+//! Named subsets of the members of a room, see [`Context::join_channel`].
+
+use crate::middleware::Broadcasts;
+use crate::{Context, Member, Message, RoomHandler};
+
+impl<R: RoomHandler> Context<'_, '_, R> {
+    /// Subscribes the client associated with this [Context] to the channel `name` of the room, so
+    /// it receives the messages sent with [`Context::broadcast_channel`]. Returns whether it
+    /// wasn't subscribed yet.
+    ///
+    /// Channels are lightweight: they only exist in the room, and the client stays a member of the
+    /// room like any other. Its subscriptions are dropped when it leaves the room.
+    pub fn join_channel(&mut self, name: impl Into<String>) -> bool {
+        self.members[self.me].channels.insert(name.into())
+    }
+
+    /// Unsubscribes the client associated with this [Context] from the channel `name`. Returns
+    /// whether it was subscribed.
+    pub fn leave_channel(&mut self, name: &str) -> bool {
+        self.members[self.me].channels.remove(name)
+    }
+
+    /// Iterates over the channels the client associated with this [Context] is subscribed to, in
+    /// alphabetical order
+    pub fn channels(&self) -> impl Iterator<Item = &str> {
+        self.members[self.me].channels.iter().map(String::as_str)
+    }
+
+    /// Sends a message to the members of the room subscribed to the channel `name`, including the
+    /// current client if it is. Failures are handled like with [`Context::broadcast`].
+    pub fn broadcast_channel(&self, name: &str, msg: impl Into<Message>) -> ws::Result<()> {
+        if let Some(msg) = self.middleware.layer_broadcast(msg.into()) {
+            subscribers(self.members, name)
+                .for_each(|member| self.errors.send(member, msg.clone()));
+        }
+
+        Ok(())
+    }
+}
+
+/// The members of a room subscribed to the channel `name`
+pub(crate) fn subscribers<'a, G>(
+    members: &'a [Member<G>],
+    name: &'a str,
+) -> impl Iterator<Item = &'a Member<G>> + 'a {
+    members
+        .iter()
+        .filter(move |member| member.channels.contains(name))
+}
@@ -0,0 +1,48 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+/// Source of time for a room, see [`RoomRef::set_clock`][crate::RoomRef::set_clock].
+///
+/// Handlers that read the time through [`Context::now`][crate::Context::now] instead of
+/// [`Instant::now`] can be made deterministic in tests and replays by swapping the clock. Ticks,
+/// lifetimes and idle TTLs of rooms, the jobs of a [Hotel][crate::Hotel], as well as the round-trip
+/// times and the backlog grace periods measured for the members of a room, follow it too.
+///
+/// Timers armed on connections are run by `ws` and always fire in real time: the ones armed with
+/// [`Context::set_timeout`][crate::Context::set_timeout] and its variants, inactivity timeouts
+/// (see [`RoomRef::set_inactivity_timeout`][crate::RoomRef::set_inactivity_timeout]), the pings
+/// of keepalives (see [`Builder::keepalive`][crate::Builder::keepalive]), and the timeouts of
+/// requests (see [`Calls::with_timeout`][crate::Calls::with_timeout]).
+pub trait Clock: Debug + Send + Sync {
+    /// Returns the current instant according to this clock
+    fn now(&self) -> Instant;
+
+    /// Blocks the current thread until `deadline`, according to this clock
+    fn sleep_until(&self, deadline: Instant) {
+        thread::sleep(deadline.saturating_duration_since(self.now()));
+    }
+}
+
+/// The default [Clock], that reads the system's monotonic clock.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    #[inline]
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+    #[inline]
+    fn now(&self) -> Instant {
+        C::now(self)
+    }
+
+    fn sleep_until(&self, deadline: Instant) {
+        C::sleep_until(self, deadline)
+    }
+}
@@ -0,0 +1,275 @@
+//! Configuration of a hotel read from the environment, see [HotelConfig].
+
+use crate::{Builder, Entrance, Hotel, IpRange, RoomHandler};
+use std::env;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// The settings of a hotel that operators usually change without recompiling it, with
+/// [`Hotel::from_config`].
+///
+/// Settings left to `None` keep the default of [Builder]. They can be read from environment
+/// variables with [`HotelConfig::from_env`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HotelConfig {
+    /// Address to listen on, e.g. `0.0.0.0:8080`
+    pub addr: Option<String>,
+
+    /// See [`Builder::max_connections`]
+    pub max_connections: Option<usize>,
+
+    /// See [`Builder::connection_limit`]
+    pub connection_limit: Option<usize>,
+
+    /// See [`Builder::connections_per_ip`]
+    pub connections_per_ip: Option<usize>,
+
+    /// See [`Builder::queue_size`]
+    pub queue_size: Option<usize>,
+
+    /// See [`Builder::max_fragment_size`]
+    pub max_fragment_size: Option<usize>,
+
+    /// See [`Builder::tcp_nodelay`]
+    pub tcp_nodelay: Option<bool>,
+
+    /// See [`Builder::health_check`]
+    pub health_check: Option<String>,
+
+    /// See [`Builder::trusted_proxy`]
+    pub trusted_proxies: Vec<IpRange>,
+
+    /// See [`Builder::protocol`]
+    pub protocols: Vec<String>,
+
+    /// Timeout of the graceful shutdown, see [`ServerHandle::shutdown_gracefully`]. With the
+    /// `signals` feature, applying the configuration also
+    /// [shuts the hotel down on signals][ServerHandle::shutdown_on_signals] with it.
+    ///
+    /// [`ServerHandle::shutdown_gracefully`]: crate::ServerHandle::shutdown_gracefully
+    /// [ServerHandle::shutdown_on_signals]: crate::ServerHandle::shutdown_on_signals
+    pub shutdown_timeout: Option<Duration>,
+}
+
+impl HotelConfig {
+    /// Reads the configuration from the environment variables starting with `HOTEL_`: `HOTEL_ADDR`,
+    /// `HOTEL_MAX_CONNECTIONS`, `HOTEL_CONNECTION_LIMIT`, `HOTEL_CONNECTIONS_PER_IP`,
+    /// `HOTEL_QUEUE_SIZE`, `HOTEL_MAX_FRAGMENT_SIZE`, `HOTEL_TCP_NODELAY` (`true` or `false`),
+    /// `HOTEL_HEALTH_CHECK`, `HOTEL_TRUSTED_PROXIES` and `HOTEL_PROTOCOLS` (comma-separated) and
+    /// `HOTEL_SHUTDOWN_TIMEOUT` (in seconds).
+    ///
+    /// Variables that aren't set leave their setting to `None`.
+    pub fn from_env() -> Result<Self, InvalidConfig> {
+        Self::from_env_prefixed("HOTEL_")
+    }
+
+    /// Like [`HotelConfig::from_env`], with variables starting with `prefix` instead of `HOTEL_`
+    pub fn from_env_prefixed(prefix: &str) -> Result<Self, InvalidConfig> {
+        let var = |name: &str| {
+            let name = format!("{}{}", prefix, name);
+            env::var(&name).ok().map(|value| (name, value))
+        };
+
+        Ok(Self {
+            addr: var("ADDR").map(|(_, value)| value),
+            max_connections: parse(var("MAX_CONNECTIONS"))?,
+            connection_limit: parse(var("CONNECTION_LIMIT"))?,
+            connections_per_ip: parse(var("CONNECTIONS_PER_IP"))?,
+            queue_size: parse(var("QUEUE_SIZE"))?,
+            max_fragment_size: parse(var("MAX_FRAGMENT_SIZE"))?,
+            tcp_nodelay: parse(var("TCP_NODELAY"))?,
+            health_check: var("HEALTH_CHECK").map(|(_, value)| value),
+            trusted_proxies: parse_list(var("TRUSTED_PROXIES"))?,
+            protocols: parse_list(var("PROTOCOLS"))?,
+            shutdown_timeout: parse(var("SHUTDOWN_TIMEOUT"))?.map(Duration::from_secs),
+        })
+    }
+
+    /// Applies the configuration to `builder`
+    pub fn apply(&self, mut builder: Builder) -> Builder {
+        if let Some(max) = self.max_connections {
+            builder = builder.max_connections(max);
+        }
+        if let Some(max) = self.connection_limit {
+            builder = builder.connection_limit(max);
+        }
+        if let Some(max) = self.connections_per_ip {
+            builder = builder.connections_per_ip(max);
+        }
+        if let Some(size) = self.queue_size {
+            builder = builder.queue_size(size);
+        }
+        if let Some(bytes) = self.max_fragment_size {
+            builder = builder.max_fragment_size(bytes);
+        }
+        if let Some(nodelay) = self.tcp_nodelay {
+            builder = builder.tcp_nodelay(nodelay);
+        }
+        if let Some(path) = &self.health_check {
+            builder = builder.health_check(path.clone());
+        }
+        for range in &self.trusted_proxies {
+            builder = builder.trusted_proxy(*range);
+        }
+        for protocol in &self.protocols {
+            builder = builder.protocol(protocol.clone());
+        }
+        #[cfg(all(feature = "signals", unix))]
+        if let Some(timeout) = self.shutdown_timeout {
+            builder = builder.shutdown_on_signals(timeout);
+        }
+
+        builder
+    }
+}
+
+/// Parses the value of an environment variable, if it is set
+fn parse<T: FromStr>(var: Option<(String, String)>) -> Result<Option<T>, InvalidConfig> {
+    match var {
+        Some((variable, value)) => match value.trim().parse() {
+            Ok(parsed) => Ok(Some(parsed)),
+            Err(_) => Err(InvalidConfig { variable, value }),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Parses the comma-separated values of an environment variable, if it is set
+fn parse_list<T: FromStr>(var: Option<(String, String)>) -> Result<Vec<T>, InvalidConfig> {
+    let (variable, value) = match var {
+        Some(var) => var,
+        None => return Ok(Vec::new()),
+    };
+
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            item.parse().map_err(|_| InvalidConfig {
+                variable: variable.clone(),
+                value: value.clone(),
+            })
+        })
+        .collect()
+}
+
+/// The error returned by [`HotelConfig::from_env`] when a variable has an invalid value
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvalidConfig {
+    /// Name of the environment variable
+    pub variable: String,
+
+    /// Whole value of the variable, even when a single item of a list is invalid
+    pub value: String,
+}
+
+impl Display for InvalidConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid value for {}: {:?}", self.variable, self.value)
+    }
+}
+
+impl Error for InvalidConfig {}
+
+impl<R: RoomHandler> Hotel<R> {
+    /// Creates a hotel configured with `config`. Its address still has to be given to
+    /// [`Hotel::run`], e.g. from [`HotelConfig::addr`].
+    ///
+    /// ```no_run
+    /// # use ws_hotel::*;
+    /// # struct Lobby;
+    /// # impl RoomHandler for Lobby {
+    /// #     type Guest = ();
+    /// #     fn on_message(&mut self, _: Context<Self>, _: Message) -> ResultRelocation { Ok(None) }
+    /// # }
+    /// let config = HotelConfig::from_env().unwrap();
+    /// let addr = config.addr.clone().unwrap_or_else(|| "0.0.0.0:8080".to_owned());
+    ///
+    /// Hotel::from_config(&config, Lobby).run(addr).unwrap();
+    /// ```
+    pub fn from_config(config: &HotelConfig, lobby: impl Into<Entrance<R>>) -> Self {
+        Self::new(lobby).with_builder(config.apply(Builder::new()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn var(value: &str) -> Option<(String, String)> {
+        Some(("VAR".to_owned(), value.to_owned()))
+    }
+
+    fn invalid(value: &str) -> InvalidConfig {
+        InvalidConfig {
+            variable: "VAR".to_owned(),
+            value: value.to_owned(),
+        }
+    }
+
+    #[test]
+    fn parses_values() {
+        assert_eq!(parse::<usize>(None), Ok(None));
+        assert_eq!(parse(var(" 42 ")), Ok(Some(42_usize)));
+        assert_eq!(parse(var("true")), Ok(Some(true)));
+        assert_eq!(parse::<usize>(var("-1")), Err(invalid("-1")));
+        assert_eq!(parse::<bool>(var("")), Err(invalid("")));
+    }
+
+    #[test]
+    fn parses_lists() {
+        assert_eq!(parse_list::<String>(None), Ok(Vec::new()));
+        assert_eq!(parse_list::<String>(var("")), Ok(Vec::new()));
+        assert_eq!(
+            parse_list(var("a, b,,c,")),
+            Ok(vec!["a", "b", "c"].into_iter().map(String::from).collect())
+        );
+        assert_eq!(
+            parse_list::<IpRange>(var("10.0.0.0/8, nope")),
+            Err(invalid("10.0.0.0/8, nope"))
+        );
+    }
+
+    #[test]
+    fn reads_prefixed_variables() {
+        env::set_var("CONFIG_TEST_OK_ADDR", "0.0.0.0:8080");
+        env::set_var("CONFIG_TEST_OK_MAX_CONNECTIONS", "100");
+        env::set_var("CONFIG_TEST_OK_TCP_NODELAY", "false");
+        env::set_var("CONFIG_TEST_OK_TRUSTED_PROXIES", "10.0.0.0/8,::1");
+        env::set_var("CONFIG_TEST_OK_SHUTDOWN_TIMEOUT", "30");
+
+        let config = HotelConfig::from_env_prefixed("CONFIG_TEST_OK_").unwrap();
+        assert_eq!(config.addr.as_deref(), Some("0.0.0.0:8080"));
+        assert_eq!(config.max_connections, Some(100));
+        assert_eq!(config.connection_limit, None);
+        assert_eq!(config.tcp_nodelay, Some(false));
+        assert_eq!(
+            config.trusted_proxies,
+            ["10.0.0.0/8".parse().unwrap(), "::1".parse().unwrap()]
+        );
+        assert_eq!(config.shutdown_timeout, Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn reports_invalid_variables() {
+        env::set_var("CONFIG_TEST_BAD_QUEUE_SIZE", "lots");
+        assert_eq!(
+            HotelConfig::from_env_prefixed("CONFIG_TEST_BAD_"),
+            Err(InvalidConfig {
+                variable: "CONFIG_TEST_BAD_QUEUE_SIZE".to_owned(),
+                value: "lots".to_owned(),
+            })
+        );
+
+        env::set_var("CONFIG_TEST_BAD_LIST_TRUSTED_PROXIES", "10.0.0.0/33");
+        let err = HotelConfig::from_env_prefixed("CONFIG_TEST_BAD_LIST_").unwrap_err();
+        assert_eq!(err.variable, "CONFIG_TEST_BAD_LIST_TRUSTED_PROXIES");
+        assert_eq!(
+            err.to_string(),
+            "invalid value for CONFIG_TEST_BAD_LIST_TRUSTED_PROXIES: \"10.0.0.0/33\""
+        );
+    }
+}
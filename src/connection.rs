@@ -0,0 +1,192 @@
+use crate::hotel::SharedState;
+use crate::keepalive::Heartbeat;
+use crate::member::Mailbox;
+use crate::slow::Backlog;
+use crate::timer::{Action, TimerHandle};
+use crate::{MemberId, RoomAddr};
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use ws::util::Token;
+use ws::Sender;
+
+/// A map of values attached to a connection, indexed by their type.
+///
+/// It follows the client from room to room, which makes it convenient to carry data that doesn't
+/// belong in any room's `Guest`, like authentication claims or metrics. Values are inserted with
+/// [`Context::extensions_mut`] and read back with [`Context::extensions`].
+///
+/// ```
+/// # use ws_hotel::*;
+/// struct UserId(u64);
+///
+/// struct Lobby;
+///
+/// impl RoomHandler for Lobby {
+///     type Guest = ();
+///
+///     fn on_join(&mut self, mut cx: Context<Self>) -> ResultRelocation {
+///         cx.extensions_mut().insert(UserId(42));
+///         Ok(None)
+///     }
+///
+///     fn on_message(&mut self, cx: Context<Self>, _msg: Message) -> ResultRelocation {
+///         let UserId(id) = cx.extensions().get().unwrap();
+///         cx.send(format!("hello, user {}", id))?;
+///         Ok(None)
+///     }
+/// }
+/// ```
+///
+/// [`Context::extensions_mut`]: crate::Context::extensions_mut
+/// [`Context::extensions`]: crate::Context::extensions
+#[derive(Default)]
+pub struct Extensions(HashMap<TypeId, Box<dyn Any>>);
+
+impl Extensions {
+    /// Inserts a value, returning the previous value of the same type if there was one
+    pub fn insert<T: 'static>(&mut self, value: T) -> Option<T> {
+        self.0
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .map(|previous| *previous.downcast().unwrap())
+    }
+
+    /// Returns a reference to the value of type `T`, if there is one
+    pub fn get<T: 'static>(&self) -> Option<&T> {
+        self.0
+            .get(&TypeId::of::<T>())
+            .map(|value| value.downcast_ref().unwrap())
+    }
+
+    /// Returns a mutable reference to the value of type `T`, if there is one
+    pub fn get_mut<T: 'static>(&mut self) -> Option<&mut T> {
+        self.0
+            .get_mut(&TypeId::of::<T>())
+            .map(|value| value.downcast_mut().unwrap())
+    }
+
+    /// Removes and returns the value of type `T`, if there is one
+    pub fn remove<T: 'static>(&mut self) -> Option<T> {
+        self.0
+            .remove(&TypeId::of::<T>())
+            .map(|value| *value.downcast().unwrap())
+    }
+
+    /// Whether there is a value of type `T`
+    pub fn contains<T: 'static>(&self) -> bool {
+        self.0.contains_key(&TypeId::of::<T>())
+    }
+}
+
+impl Debug for Extensions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Extensions")
+            .field("len", &self.0.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// State attached to the connection of a client rather than to the room it is in, so it survives
+/// relocations.
+pub(crate) struct Connection {
+    pub(crate) id: MemberId,
+    pub(crate) sender: Sender,
+    pub(crate) extensions: Extensions,
+
+    /// The subprotocol selected during the handshake
+    pub(crate) protocol: Option<String>,
+
+    /// The address of the client, known once the handshake is complete
+    pub(crate) remote_addr: Option<IpAddr>,
+
+    /// See [`Builder::keepalive`][crate::Builder::keepalive]
+    pub(crate) heartbeat: Heartbeat,
+
+    /// See [`Builder::slow_consumers`][crate::Builder::slow_consumers]
+    pub(crate) backlog: Backlog,
+
+    /// Requests made to the connection from outside of its handler
+    pub(crate) mailbox: Arc<Mailbox>,
+
+    /// The state of the hotel the client connected to, see
+    /// [`Context::state`][crate::Context::state]
+    pub(crate) state: Option<SharedState>,
+
+    cleanups: Vec<Box<dyn FnOnce()>>,
+
+    /// Timers armed by the rooms the client is in, indexed by the token given to `ws`
+    timers: HashMap<Token, TimerHandle>,
+}
+
+impl Connection {
+    pub(crate) fn new(id: MemberId, sender: Sender) -> Self {
+        Self {
+            id,
+            sender,
+            extensions: Extensions::default(),
+            protocol: None,
+            remote_addr: None,
+            heartbeat: Heartbeat::default(),
+            backlog: Backlog::default(),
+            mailbox: Arc::default(),
+            state: None,
+            cleanups: Vec::new(),
+            timers: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn on_disconnect(&mut self, f: Box<dyn FnOnce()>) {
+        self.cleanups.push(f);
+    }
+
+    /// Arms a timer on this connection
+    pub(crate) fn set_timeout(
+        &mut self,
+        delay: Duration,
+        action: Action,
+        room: Option<RoomAddr>,
+    ) -> ws::Result<TimerHandle> {
+        let timer = TimerHandle::new(self.sender.clone(), &self.mailbox, action, room);
+        let internal = timer.arm(delay)?;
+        self.timers.insert(internal, timer.clone());
+        Ok(timer)
+    }
+
+    /// Takes over the timers (re-)armed on this connection from outside of it, see
+    /// [`TimerHandle::reschedule`]
+    fn adopt_timers(&mut self) {
+        self.timers.extend(self.mailbox.take_timers());
+    }
+
+    /// Returns the timer that fired with the internal token `event`, if it is still armed
+    pub(crate) fn take_timer(&mut self, event: Token) -> Option<TimerHandle> {
+        self.adopt_timers();
+        self.timers.remove(&event).filter(|timer| timer.fire(event))
+    }
+
+    /// Cancels the timers bound to the room the client is leaving. Timers that
+    /// [travel][crate::Context::set_travelling_timeout] are kept.
+    pub(crate) fn clear_room_timers(&mut self) {
+        self.adopt_timers();
+        self.timers.retain(|_, timer| {
+            if !timer.travels() {
+                timer.cancel();
+            }
+            timer.travels()
+        });
+    }
+}
+
+/// `ws` drops the handler of a connection exactly once, after it has been closed, which makes it
+/// the right moment to run the cleanup closures.
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.cleanups.drain(..).for_each(|f| f());
+
+        self.mailbox.close();
+        self.timers.values().for_each(TimerHandle::close);
+    }
+}
@@ -0,0 +1,153 @@
+//! How new connections enter the hotel, see [Entrance].
+
+use crate::router::Routes;
+use crate::{Handshake, RoomAny, RoomHandler, RoomRef};
+use std::any::Any;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
+
+type GuestFactory<G> = dyn FnMut(&Handshake) -> ws::Result<G> + Send;
+
+/// Builds the identity of a client from its handshake, regardless of the type of its lobby
+pub(crate) type GuestFn = dyn FnMut(&Handshake) -> ws::Result<Box<dyn Any>>;
+
+/// Picks the lobby of each new connection, and its arrival in it
+pub(crate) type Arrivals = Box<dyn FnMut() -> (Arc<dyn RoomAny>, Arrival)>;
+
+/// When a new connection becomes a member of its lobby
+pub(crate) enum Arrival {
+    /// Right away, with this identity
+    Now(Box<dyn Any>),
+
+    /// Once its handshake is known, with the identity built from it
+    OnOpen(Rc<RefCell<GuestFn>>),
+
+    /// Once its request is known, in the lobby it is routed to
+    OnRequest(Rc<Routes>),
+}
+
+/// The room new connections are put in (the lobby), and how their identity is built.
+///
+/// By default, clients enter the lobby with the [Default] value of its
+/// [Guest][RoomHandler::Guest], as soon as they connect. With [`Entrance::with_guest`], their
+/// identity is built from their handshake instead, which also allows refusing them before they
+/// enter the lobby.
+///
+/// ```no_run
+/// # use ws_hotel::*;
+/// struct Lobby;
+///
+/// impl RoomHandler for Lobby {
+///     type Guest = String;
+///
+///     fn on_message(&mut self, cx: Context<Self>, _: Message) -> ResultRelocation {
+///         cx.send(format!("hello {}", cx.identity_ref()))?;
+///         Ok(None)
+///     }
+/// }
+///
+/// let entrance = Entrance::with_guest(Lobby, |handshake| {
+///     match handshake.request.resource().strip_prefix("/as/") {
+///         Some(name) => Ok(name.to_owned()),
+///         None => Err(ws::Error::new(ws::ErrorKind::Protocol, "anonymous")),
+///     }
+/// });
+///
+/// ws_hotel::listen("127.0.0.1:8080", entrance).unwrap();
+/// ```
+pub struct Entrance<R: RoomHandler> {
+    room: RoomRef<R>,
+    guest: Guest<R::Guest>,
+}
+
+enum Guest<G> {
+    Default(fn() -> G),
+    FromHandshake(Box<GuestFactory<G>>),
+}
+
+impl<R: RoomHandler> Entrance<R> {
+    /// Puts clients in `room` with the default identity, as soon as they connect
+    pub fn new(room: impl Into<RoomRef<R>>) -> Self
+    where
+        R::Guest: Default,
+    {
+        Self {
+            room: room.into(),
+            guest: Guest::Default(R::Guest::default),
+        }
+    }
+
+    /// Puts clients in `room` once their handshake is known, with the identity returned by
+    /// `guest`.
+    ///
+    /// Returning an error refuses the client: its connection is closed without it ever entering
+    /// the room. Since clients aren't members of the room before their handshake, its
+    /// [`on_request`][RoomHandler::on_request] isn't called.
+    pub fn with_guest<F>(room: impl Into<RoomRef<R>>, guest: F) -> Self
+    where
+        F: FnMut(&Handshake) -> ws::Result<R::Guest> + Send + 'static,
+    {
+        Self {
+            room: room.into(),
+            guest: Guest::FromHandshake(Box::new(guest)),
+        }
+    }
+}
+
+impl<R: RoomHandler> Entrance<R> {
+    pub(crate) fn room(&self) -> &RoomRef<R> {
+        &self.room
+    }
+}
+
+impl<R: RoomHandler + 'static> Entrance<R>
+where
+    R::Guest: 'static,
+{
+    /// Returns a function picking the room and the arrival of each new connection
+    pub(crate) fn into_arrivals(self) -> impl FnMut() -> (Arc<dyn RoomAny>, Arrival) {
+        let room: Arc<dyn RoomAny> = self.room.0;
+
+        enum Erased<G> {
+            Default(fn() -> G),
+            FromHandshake(Rc<RefCell<GuestFn>>),
+        }
+
+        let guest = match self.guest {
+            Guest::Default(default) => Erased::Default(default),
+            Guest::FromHandshake(mut factory) => {
+                Erased::FromHandshake(Rc::new(RefCell::new(move |shake: &Handshake| {
+                    factory(shake).map(|guest| Box::new(guest) as Box<dyn Any>)
+                })))
+            }
+        };
+
+        move || {
+            let arrival = match &guest {
+                Erased::Default(default) => Arrival::Now(Box::new(default())),
+                Erased::FromHandshake(factory) => Arrival::OnOpen(Rc::clone(factory)),
+            };
+
+            (Arc::clone(&room), arrival)
+        }
+    }
+}
+
+impl<R: RoomHandler> From<RoomRef<R>> for Entrance<R>
+where
+    R::Guest: Default,
+{
+    fn from(room: RoomRef<R>) -> Self {
+        Self::new(room)
+    }
+}
+
+impl<R: RoomHandler> From<R> for Entrance<R>
+where
+    R::Guest: Default,
+{
+    fn from(handler: R) -> Self {
+        Self::new(handler)
+    }
+}
@@ -0,0 +1,77 @@
+use crate::{CloseCode, LeaveReason, MemberId, Room, RoomAddr, RoomHandler, RoomRef};
+use std::sync::mpsc::{self, Receiver};
+
+/// Something that happened to a member of a room, sent to the observers of the room, see
+/// [`RoomRef::subscribe`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RoomEvent {
+    /// A client connected to the room, which is its lobby
+    Joined(MemberId),
+
+    /// A member arrived in the room from the room at `from`
+    RelocatedIn { member: MemberId, from: RoomAddr },
+
+    /// A member left the room for the room at `to`
+    RelocatedOut { member: MemberId, to: RoomAddr },
+
+    /// The connection of a member was closed by the client, or lost
+    Disconnected {
+        member: MemberId,
+        code: CloseCode,
+        reason: String,
+    },
+
+    /// The connection of a member was closed by the server
+    Kicked(MemberId),
+
+    /// A member left the room because the server is shutting down
+    ServerShutdown(MemberId),
+}
+
+impl RoomEvent {
+    pub(crate) fn left(member: MemberId, reason: LeaveReason) -> Self {
+        match reason {
+            LeaveReason::Relocated { to } => Self::RelocatedOut { member, to },
+            LeaveReason::Disconnected { code, reason } => Self::Disconnected {
+                member,
+                code,
+                reason: reason.to_owned(),
+            },
+            LeaveReason::Kicked => Self::Kicked(member),
+            LeaveReason::ServerShutdown => Self::ServerShutdown(member),
+        }
+    }
+
+    /// The member the event is about
+    pub fn member(&self) -> MemberId {
+        match *self {
+            Self::Joined(member)
+            | Self::RelocatedIn { member, .. }
+            | Self::RelocatedOut { member, .. }
+            | Self::Disconnected { member, .. }
+            | Self::Kicked(member)
+            | Self::ServerShutdown(member) => member,
+        }
+    }
+}
+
+impl<R: RoomHandler> RoomRef<R> {
+    /// Returns a channel receiving the [RoomEvent]s of the room from now on, so that code outside
+    /// of its handler (analytics, matchmaking…) can react to them.
+    ///
+    /// Events are sent once the matching callback of the handler has returned. The room stops
+    /// sending events to the channel once the receiver is dropped.
+    pub fn subscribe(&self) -> Receiver<RoomEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.0.lock().unwrap().subscribers.push(sender);
+        receiver
+    }
+}
+
+impl<R: RoomHandler> Room<R> {
+    /// Sends an event to the observers of the room, forgetting the ones that went away
+    pub(crate) fn emit(&mut self, event: RoomEvent) {
+        self.subscribers
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+}
@@ -0,0 +1,351 @@
+//! A hotel and everything it owns, see [Hotel].
+
+use crate::scheduler::Jobs;
+use crate::{Builder, Clock, Entrance, RoomAny, RoomHandler, RoomRef, ServerHandle, SystemClock};
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::net::ToSocketAddrs;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// A hotel: its lobby, the rooms it knows by name, the state shared by the whole application and
+/// its configuration, started with [`Hotel::run`].
+///
+/// It is the natural home of anything global: the [Rooms] and the state are behind [Arc]s, so
+/// that handlers can keep a clone of them. The state is also given to handlers by
+/// [`Context::state`][crate::Context::state].
+///
+/// ```no_run
+/// # use ws_hotel::*;
+/// # use std::sync::Arc;
+/// # use std::sync::atomic::{AtomicUsize, Ordering};
+/// # struct Game;
+/// # impl RoomHandler for Game {
+/// #     type Guest = ();
+/// #     fn on_message(&mut self, _: Context<Self>, _: Message) -> ResultRelocation { Ok(None) }
+/// # }
+/// struct App {
+///     games_played: AtomicUsize,
+/// }
+///
+/// struct Lobby {
+///     rooms: Arc<Rooms>,
+/// }
+///
+/// impl RoomHandler for Lobby {
+///     type Guest = ();
+///
+///     fn on_message(&mut self, cx: Context<Self>, msg: Message) -> ResultRelocation {
+///         let app = cx.state::<App>().expect("the hotel has an App");
+///         app.games_played.fetch_add(1, Ordering::Relaxed);
+///
+///         let name = msg.into_text()?;
+///         let game = self.rooms.get_or_insert_with(name, || Room::new(Game));
+///         Ok(Some(Relocation::new(&game, ())))
+///     }
+/// }
+///
+/// let app = Arc::new(App { games_played: AtomicUsize::new(0) });
+/// let rooms = Arc::new(Rooms::new());
+/// let lobby = Lobby { rooms: Arc::clone(&rooms) };
+///
+/// Hotel::new(lobby)
+///     .with_state(app)
+///     .with_rooms(rooms)
+///     .with_builder(Builder::new().max_connections(10_000))
+///     .run("127.0.0.1:8080")
+///     .unwrap();
+/// ```
+pub struct Hotel<R: RoomHandler, S = ()> {
+    lobby: Entrance<R>,
+    rooms: Arc<Rooms>,
+    state: Arc<S>,
+    builder: Builder,
+    clock: Arc<dyn Clock>,
+    pub(crate) jobs: Jobs,
+}
+
+impl<R: RoomHandler> Hotel<R> {
+    /// Creates a hotel with the default configuration, no rooms and no state
+    pub fn new(lobby: impl Into<Entrance<R>>) -> Self {
+        Self {
+            lobby: lobby.into(),
+            rooms: Arc::default(),
+            state: Arc::new(()),
+            builder: Builder::new(),
+            clock: Arc::new(SystemClock),
+            jobs: Jobs::default(),
+        }
+    }
+}
+
+impl<R: RoomHandler, S> Hotel<R, S> {
+    /// Replaces the state shared by the application, which handlers get with
+    /// [`Context::state`][crate::Context::state]
+    pub fn with_state<T>(self, state: Arc<T>) -> Hotel<R, T> {
+        Hotel {
+            lobby: self.lobby,
+            rooms: self.rooms,
+            state,
+            builder: self.builder,
+            clock: self.clock,
+            jobs: self.jobs,
+        }
+    }
+
+    /// Replaces the rooms of the hotel, e.g. with rooms already given to the lobby
+    pub fn with_rooms(mut self, rooms: Arc<Rooms>) -> Self {
+        self.rooms = rooms;
+        self
+    }
+
+    /// Replaces the configuration of the hotel
+    pub fn with_builder(mut self, builder: Builder) -> Self {
+        self.builder = builder;
+        self
+    }
+
+    /// Replaces the [Clock] of the hotel, which is the [SystemClock] by default, e.g. with a
+    /// [ManualClock][crate::test::ManualClock] in tests.
+    ///
+    /// It is given to the lobby and followed by the [scheduled jobs][Hotel::schedule]. Rooms
+    /// created afterwards can be given it with [`RoomRef::set_clock`] and [`Hotel::clock`].
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self.lobby.room().set_clock(Arc::clone(&self.clock));
+        self
+    }
+
+    /// The room new clients are put in
+    pub fn lobby(&self) -> &RoomRef<R> {
+        self.lobby.room()
+    }
+
+    /// The rooms the hotel knows by name
+    pub fn rooms(&self) -> &Arc<Rooms> {
+        &self.rooms
+    }
+
+    /// The state shared by the application
+    pub fn state(&self) -> &Arc<S> {
+        &self.state
+    }
+
+    /// The clock of the hotel, see [`Hotel::with_clock`]
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
+
+    /// The configuration of the hotel
+    pub fn builder(&self) -> &Builder {
+        &self.builder
+    }
+}
+
+impl<R, S> Hotel<R, S>
+where
+    R: RoomHandler + 'static,
+    R::Guest: 'static,
+    S: Send + Sync + 'static,
+{
+    /// Starts the hotel, see [`listen`][crate::listen]
+    pub fn run<A: ToSocketAddrs>(self, addr: A) -> ws::Result<()> {
+        self.run_with(addr, drop)
+    }
+
+    /// Starts the hotel, see [`listen_with`][crate::listen_with]
+    pub fn run_with<A, F>(self, addr: A, on_start: F) -> ws::Result<()>
+    where
+        A: ToSocketAddrs,
+        F: FnOnce(ServerHandle),
+    {
+        let Self {
+            lobby,
+            rooms,
+            state,
+            mut builder,
+            clock,
+            jobs,
+        } = self;
+        builder.state = Some(SharedState(state));
+
+        let registered = Registered::new(&lobby, &rooms);
+        let mut running = None;
+        let mut run = None;
+        let result = builder.listen_with(addr, lobby, |server| {
+            running = jobs.start(rooms, clock);
+            run = Some(server.run());
+            on_start(server)
+        });
+
+        drop(running);
+        if let Some(run) = run {
+            registered.shutdown(run);
+        }
+        result
+    }
+
+    /// Starts the hotel in a background thread, see [`spawn`][crate::spawn]
+    pub fn spawn<A>(self, addr: A) -> ws::Result<(ServerHandle, JoinHandle<ws::Result<()>>)>
+    where
+        A: ToSocketAddrs + Send + 'static,
+        R: Send,
+        R::Guest: Send,
+    {
+        let registered = Registered::new(&self.lobby, &self.rooms);
+        let mut builder = self.builder;
+        builder.state = Some(SharedState(self.state));
+        let (server, thread) = builder.spawn(addr, self.lobby)?;
+
+        let jobs = self.jobs.start(self.rooms, self.clock);
+        let run = server.run();
+        let thread = thread::spawn(move || {
+            let result = thread.join();
+            drop(jobs);
+            registered.shutdown(run);
+            result.unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+        });
+
+        Ok((server, thread))
+    }
+}
+
+impl<R: RoomHandler, S: Debug> Debug for Hotel<R, S> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Hotel")
+            .field("rooms", &self.rooms)
+            .field("state", &self.state)
+            .field("builder", &self.builder)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The state of a [Hotel], given to the connections of its server, see
+/// [`Context::state`][crate::Context::state]
+#[derive(Clone)]
+pub(crate) struct SharedState(pub(crate) Arc<dyn Any + Send + Sync>);
+
+impl Debug for SharedState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedState").finish_non_exhaustive()
+    }
+}
+
+/// Rooms of any [RoomHandler] type, indexed by name and shared by a whole [Hotel].
+///
+/// Unlike a [RoomRegistry][crate::RoomRegistry], it can hold rooms with different handlers, and
+/// it can be used through a shared reference.
+#[derive(Default)]
+pub struct Rooms(Mutex<HashMap<String, Entry>>);
+
+/// A room of [Rooms]: its [RoomRef] to be downcast, and the room itself to be notified when the
+/// hotel stops
+type Entry = (Box<dyn Any + Send>, Arc<dyn RoomAny + Send + Sync>);
+
+impl Rooms {
+    /// Creates an empty set of rooms
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a room under `name`, replacing the previous room of this name
+    pub fn insert<R>(&self, name: impl Into<String>, room: RoomRef<R>)
+    where
+        R: RoomHandler + Send + 'static,
+        R::Guest: Send,
+    {
+        let any = Arc::clone(&room.0) as _;
+        self.0
+            .lock()
+            .unwrap()
+            .insert(name.into(), (Box::new(room), any));
+    }
+
+    /// Returns the room called `name`, if there is one and its handler is of type `R`
+    pub fn get<R>(&self, name: &str) -> Option<RoomRef<R>>
+    where
+        R: RoomHandler + 'static,
+    {
+        let rooms = self.0.lock().unwrap();
+        rooms.get(name)?.0.downcast_ref().cloned()
+    }
+
+    /// Returns the room called `name`, creating it with `create` if there is none. Panics if there
+    /// is a room of this name whose handler isn't of type `R`.
+    pub fn get_or_insert_with<R, F>(&self, name: impl Into<String>, create: F) -> RoomRef<R>
+    where
+        R: RoomHandler + Send + 'static,
+        R::Guest: Send,
+        F: FnOnce() -> RoomRef<R>,
+    {
+        let mut rooms = self.0.lock().unwrap();
+        let (room, _) = rooms.entry(name.into()).or_insert_with(|| {
+            let room = create();
+            let any = Arc::clone(&room.0) as _;
+            (Box::new(room), any)
+        });
+        room.downcast_ref::<RoomRef<R>>()
+            .expect("room of another type")
+            .clone()
+    }
+
+    /// Removes the room called `name`, returning whether there was one
+    pub fn remove(&self, name: &str) -> bool {
+        self.0.lock().unwrap().remove(name).is_some()
+    }
+
+    /// The names of the rooms, in no particular order
+    pub fn names(&self) -> Vec<String> {
+        self.0.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Number of rooms
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+
+    /// Whether there are no rooms
+    pub fn is_empty(&self) -> bool {
+        self.0.lock().unwrap().is_empty()
+    }
+}
+
+/// The rooms a hotel notifies when it stops, see [`RoomHandler::on_shutdown`]
+struct Registered<R: RoomHandler> {
+    lobby: RoomRef<R>,
+    rooms: Arc<Rooms>,
+}
+
+impl<R: RoomHandler + 'static> Registered<R> {
+    fn new(lobby: &Entrance<R>, rooms: &Arc<Rooms>) -> Self {
+        Self {
+            lobby: lobby.room().clone(),
+            rooms: Arc::clone(rooms),
+        }
+    }
+
+    /// Notifies the rooms that weren't notified yet of the shutdown of `run`
+    fn shutdown(&self, run: u64) {
+        self.lobby.0.shutdown(run);
+
+        // Collected first, so that the rooms can use the hotel's rooms from `on_shutdown`
+        let rooms = self
+            .rooms
+            .0
+            .lock()
+            .unwrap()
+            .values()
+            .map(|(_, room)| Arc::clone(room))
+            .collect::<Vec<_>>();
+        rooms.into_iter().for_each(|room| room.shutdown(run));
+    }
+}
+
+impl Debug for Rooms {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Rooms")
+            .field("names", &self.names())
+            .finish()
+    }
+}
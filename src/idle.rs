@@ -0,0 +1,295 @@
+//! Automatic cleanup of rooms that stay empty, see [RoomRegistry].
+
+use crate::{alarm, Clock, Room, RoomHandler, RoomRef, SystemClock};
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::hash::Hash;
+use std::sync::{Arc, Mutex, TryLockError};
+use std::time::Duration;
+
+/// How often a [RoomRegistry] holding rooms evicts the idle ones
+const SWEEP: Duration = Duration::from_secs(1);
+
+impl<R: RoomHandler> RoomRef<R> {
+    /// Lets the [RoomRegistry] holding the room drop it once it has been empty for `ttl`.
+    ///
+    /// A room that was just created counts as empty, so it expires if nobody joins it within `ttl`.
+    pub fn set_idle_ttl(&self, ttl: Duration) {
+        self.0.lock().unwrap().idle_ttl = Some(ttl);
+    }
+
+    /// Removes the TTL set by [`RoomRef::set_idle_ttl`]: the room is kept until it is removed from
+    /// its registry
+    pub fn unset_idle_ttl(&self) {
+        self.0.lock().unwrap().idle_ttl = None;
+    }
+
+    /// For how long the room has been empty, according to its [Clock][crate::Clock], or `None` if
+    /// it has members
+    pub fn idle_for(&self) -> Option<Duration> {
+        let room = self.0.lock().unwrap();
+        room.empty_since
+            .map(|since| room.clock.now().saturating_duration_since(since))
+    }
+
+    /// Whether the room has been empty for longer than its [idle TTL][RoomRef::set_idle_ttl]. A
+    /// room that is locked is in use, e.g. by a callback accessing its registry, so it isn't.
+    fn is_expired(&self) -> bool {
+        let room = match self.0.try_lock() {
+            Ok(room) => room,
+            Err(TryLockError::WouldBlock) => return false,
+            Err(TryLockError::Poisoned(err)) => panic!("{}", err),
+        };
+
+        match (room.idle_ttl, room.empty_since) {
+            (Some(ttl), Some(since)) => room.clock.now().saturating_duration_since(since) >= ttl,
+            _ => false,
+        }
+    }
+}
+
+impl<R: RoomHandler> Room<R> {
+    /// Keeps track of when the room became empty, to be called after members joined or left
+    pub(crate) fn update_empty_since(&mut self) {
+        self.empty_since = match (self.members.is_empty(), self.empty_since) {
+            (true, None) => Some(self.clock.now()),
+            (true, since) => since,
+            (false, _) => None,
+        };
+    }
+}
+
+/// Named rooms created on demand, that are dropped once they have been empty for longer than their
+/// [idle TTL][RoomRef::set_idle_ttl].
+///
+/// The registry holds a strong reference to each room, so a room that nobody is in survives until
+/// it expires, unlike with a map of [RoomRefWeak][crate::RoomRefWeak]s. While it holds rooms, the
+/// registry is swept every second from a background thread, so expired rooms are dropped even if
+/// nobody accesses it. They are also dropped whenever it is accessed, or with
+/// [`RoomRegistry::evict_idle`]. Rooms that are locked, e.g. because one of their callbacks is
+/// running, are never considered expired. Members and pending relocations keep their room alive
+/// even if it was evicted.
+///
+/// ```
+/// # use ws_hotel::*;
+/// # use std::time::Duration;
+/// # struct Chat;
+/// # impl RoomHandler for Chat {
+/// #     type Guest = String;
+/// #     fn on_message(&mut self, _: Context<Self>, _: Message) -> ResultRelocation { Ok(None) }
+/// # }
+/// let rooms = RoomRegistry::new();
+///
+/// let general = rooms.get_or_insert_with("general", || {
+///     let room = Room::new(Chat);
+///     room.set_idle_ttl(Duration::from_secs(60));
+///     room
+/// });
+///
+/// assert!(rooms.get("general") == Some(general));
+/// ```
+pub struct RoomRegistry<K, R: RoomHandler> {
+    entries: Arc<Mutex<Entries<K, R>>>,
+}
+
+struct Entries<K, R: RoomHandler> {
+    rooms: HashMap<K, RoomRef<R>>,
+
+    /// Whether the alarm sweeping the registry is set
+    sweeping: bool,
+}
+
+impl<K, R: RoomHandler> Entries<K, R> {
+    fn evict_idle(&mut self) {
+        self.rooms.retain(|_, room| !room.is_expired());
+    }
+}
+
+impl<K: Eq + Hash, R: RoomHandler> RoomRegistry<K, R> {
+    /// Creates an empty registry
+    pub fn new() -> Self {
+        Self {
+            entries: Arc::new(Mutex::new(Entries {
+                rooms: HashMap::new(),
+                sweeping: false,
+            })),
+        }
+    }
+
+    /// Returns the room named `key`, if it exists and hasn't expired
+    pub fn get<Q>(&self, key: &Q) -> Option<RoomRef<R>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let mut entries = self.entries.lock().unwrap();
+        entries.evict_idle();
+        entries.rooms.get(key).cloned()
+    }
+
+    /// Removes the room named `key` from the registry, whether it expired or not
+    pub fn remove<Q>(&self, key: &Q) -> Option<RoomRef<R>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.entries.lock().unwrap().rooms.remove(key)
+    }
+
+    /// Drops the rooms that have been empty for longer than their idle TTL
+    pub fn evict_idle(&self) {
+        self.entries.lock().unwrap().evict_idle();
+    }
+
+    /// Number of rooms in the registry, including the ones that expired but weren't evicted yet
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().rooms.len()
+    }
+
+    /// Whether the registry has no rooms
+    pub fn is_empty(&self) -> bool {
+        self.entries.lock().unwrap().rooms.is_empty()
+    }
+
+    /// The rooms of the registry and their name, as of now
+    pub fn iter(&self) -> impl Iterator<Item = (K, RoomRef<R>)>
+    where
+        K: Clone,
+    {
+        let entries = self.entries.lock().unwrap();
+        let rooms = entries
+            .rooms
+            .iter()
+            .map(|(key, room)| (key.clone(), room.clone()))
+            .collect::<Vec<_>>();
+        rooms.into_iter()
+    }
+}
+
+impl<K, R> RoomRegistry<K, R>
+where
+    K: Eq + Hash + Send + 'static,
+    R: RoomHandler + Send + 'static,
+    R::Guest: Send,
+{
+    /// Returns the room named `key`, creating it with `create` if it doesn't exist or expired
+    pub fn get_or_insert_with<F>(&self, key: K, create: F) -> RoomRef<R>
+    where
+        F: FnOnce() -> RoomRef<R>,
+    {
+        let mut entries = self.entries.lock().unwrap();
+        entries.evict_idle();
+        let room = entries.rooms.entry(key).or_insert_with(create).clone();
+
+        if !entries.sweeping {
+            entries.sweeping = true;
+            self.sweep();
+        }
+        room
+    }
+
+    /// Evicts idle rooms every [SWEEP] until the registry is empty or dropped
+    fn sweep(&self) {
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+        let entries = Arc::downgrade(&self.entries);
+
+        alarm::set(Arc::clone(&clock), clock.now() + SWEEP, move || {
+            let entries = entries.upgrade()?;
+            let mut entries = entries.lock().unwrap();
+            entries.evict_idle();
+
+            if entries.rooms.is_empty() {
+                entries.sweeping = false;
+                return None;
+            }
+            Some(clock.now() + SWEEP)
+        });
+    }
+}
+
+impl<K: Eq + Hash, R: RoomHandler> Default for RoomRegistry<K, R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Debug, R: RoomHandler> Debug for RoomRegistry<K, R> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let entries = self.entries.lock().unwrap();
+        f.debug_set().entries(entries.rooms.keys()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::ManualClock;
+    use crate::{Context, Message, ResultRelocation};
+    use std::thread;
+
+    struct Chat;
+
+    impl RoomHandler for Chat {
+        type Guest = ();
+
+        fn on_message(&mut self, _cx: Context<Self>, _msg: Message) -> ResultRelocation {
+            Ok(None)
+        }
+    }
+
+    fn room(clock: &ManualClock, ttl: Duration) -> RoomRef<Chat> {
+        let room = Room::new(Chat);
+        room.set_clock(clock.clone());
+        room.set_idle_ttl(ttl);
+        room
+    }
+
+    #[test]
+    fn expires_after_the_idle_ttl() {
+        let clock = ManualClock::new();
+        let room = room(&clock, Duration::from_secs(60));
+        assert_eq!(room.idle_for(), Some(Duration::ZERO));
+        assert!(!room.is_expired());
+
+        clock.advance(Duration::from_secs(59));
+        assert_eq!(room.idle_for(), Some(Duration::from_secs(59)));
+        assert!(!room.is_expired());
+
+        clock.advance(Duration::from_secs(1));
+        assert!(room.is_expired());
+
+        // Locked rooms are in use
+        room.with(|_| assert!(!room.is_expired()));
+
+        room.unset_idle_ttl();
+        assert!(!room.is_expired());
+    }
+
+    #[test]
+    fn evicts_expired_rooms_on_access() {
+        let clock = ManualClock::new();
+        let rooms = RoomRegistry::new();
+        let short = rooms.get_or_insert_with("short", || room(&clock, Duration::from_secs(10)));
+        rooms.get_or_insert_with("long", || room(&clock, Duration::from_secs(60)));
+
+        clock.advance(Duration::from_secs(10));
+        assert!(rooms.get("short").is_none());
+        assert!(rooms.get("long").is_some());
+
+        let created = rooms.get_or_insert_with("short", || room(&clock, Duration::from_secs(10)));
+        assert!(created != short);
+        assert_eq!(rooms.len(), 2);
+    }
+
+    #[test]
+    fn sweeps_rooms_nobody_accesses() {
+        let clock = ManualClock::new();
+        let rooms = RoomRegistry::new();
+        rooms.get_or_insert_with("general", || room(&clock, Duration::from_secs(60)));
+
+        clock.advance(Duration::from_secs(60));
+        thread::sleep(SWEEP * 2);
+        assert!(rooms.is_empty());
+    }
+}
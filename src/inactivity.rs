@@ -0,0 +1,137 @@
+//! Removing members that stop talking, see [`RoomRef::set_inactivity_timeout`].
+
+use crate::timer::{Action, TimerHandle};
+use crate::{
+    member, CloseCode, Member, MemberId, Relocation, ResultRelocation, Room, RoomHandler, RoomRef,
+};
+use std::fmt::{Debug, Formatter};
+use std::time::Duration;
+
+type RelocateFn<G> = dyn Fn(&G) -> Relocation + Send;
+
+/// What happens to a member that has been inactive for too long, see
+/// [`RoomRef::set_inactivity_timeout`].
+pub enum Inactivity<G> {
+    /// Its connection is closed with [`CloseCode::Away`], and it leaves the room with
+    /// [`LeaveReason::Kicked`][crate::LeaveReason::Kicked]
+    Close,
+
+    /// It is moved to the room of the relocation built from its identity, e.g. an "away" room
+    Relocate(Box<RelocateFn<G>>),
+}
+
+impl<G> Inactivity<G> {
+    /// Shorthand for [`Inactivity::Relocate`]
+    pub fn relocate<F: Fn(&G) -> Relocation + Send + 'static>(f: F) -> Self {
+        Self::Relocate(Box::new(f))
+    }
+}
+
+impl<G> Debug for Inactivity<G> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Close => f.write_str("Close"),
+            Self::Relocate(_) => f.write_str("Relocate(_)"),
+        }
+    }
+}
+
+pub(crate) struct InactivityTimeout<G> {
+    after: Duration,
+    policy: Inactivity<G>,
+}
+
+impl<R: RoomHandler> RoomRef<R> {
+    /// Applies `policy` to the members of the room that haven't sent any message nor pong for
+    /// `after`, according to the [Clock][crate::Clock] of the room.
+    ///
+    /// Members are checked by a timer on their connection, so they are removed at most `after`
+    /// late. Members that are already in the room start being watched from now on.
+    pub fn set_inactivity_timeout(&self, after: Duration, policy: Inactivity<R::Guest>) {
+        let mut room = self.0.lock().unwrap();
+        room.inactivity = Some(InactivityTimeout { after, policy });
+
+        let now = room.clock.now();
+        let addr = room.self_ref.addr();
+        for member in &mut room.members {
+            member.last_active = now;
+
+            let (sender, mailbox) = (&member.sender, &member.mailbox);
+            let timer = member.inactivity.get_or_insert_with(|| {
+                TimerHandle::new(sender.clone(), mailbox, Action::Inactivity, Some(addr))
+            });
+            let _ = timer.reschedule(after);
+        }
+    }
+
+    /// Stops watching the inactivity of members, see [`RoomRef::set_inactivity_timeout`]
+    pub fn unset_inactivity_timeout(&self) {
+        let mut room = self.0.lock().unwrap();
+        room.inactivity = None;
+
+        for timer in room.members.iter_mut().filter_map(|m| m.inactivity.take()) {
+            timer.cancel();
+        }
+    }
+}
+
+impl<R: RoomHandler> Room<R> {
+    /// Starts watching the inactivity of a member that just joined the room, if needed
+    pub(crate) fn watch_inactivity(&self, member: &mut Member<R::Guest>) {
+        if let Some(inactivity) = &self.inactivity {
+            let room = Some(self.self_ref.addr());
+            let sender = member.sender.clone();
+            let timer = TimerHandle::new(sender, &member.mailbox, Action::Inactivity, room);
+            let _ = timer.reschedule(inactivity.after);
+            member.inactivity = Some(timer);
+        }
+    }
+
+    /// Records that the member `id` just sent something
+    pub(crate) fn touch(&mut self, id: MemberId) {
+        let now = self.clock.now();
+
+        if let Some(member) = self.members.iter_mut().find(|member| member.id == id) {
+            member.last_active = now;
+        }
+    }
+
+    /// Applies the inactivity policy to the member `id` if it has been inactive for too long, and
+    /// checks it again later
+    pub(crate) fn check_inactivity(&self, id: MemberId) -> ResultRelocation {
+        let inactivity = match &self.inactivity {
+            Some(inactivity) => inactivity,
+            None => return Ok(None),
+        };
+
+        let member = match self.members.iter().find(|member| member.id == id) {
+            Some(member) => member,
+            None => return Ok(None),
+        };
+
+        let timer = match &member.inactivity {
+            Some(timer) => timer,
+            None => return Ok(None),
+        };
+
+        let idle = self
+            .clock
+            .now()
+            .saturating_duration_since(member.last_active);
+        if idle < inactivity.after {
+            timer.reschedule(inactivity.after - idle)?;
+            return Ok(None);
+        }
+
+        // The member stays in the room if it can't be relocated
+        timer.reschedule(inactivity.after)?;
+
+        match &inactivity.policy {
+            Inactivity::Close => {
+                member::kick(&member.sender, &member.mailbox, CloseCode::Away, "inactive")?;
+                Ok(None)
+            }
+            Inactivity::Relocate(relocate) => Ok(Some(relocate(&member.guest))),
+        }
+    }
+}
@@ -0,0 +1,373 @@
+//! Pinging clients to detect dead connections and measure their round-trip time, see
+//! [`Builder::keepalive`] and [`RoomRef::set_keepalive`].
+//!
+//! [`Builder::keepalive`]: crate::Builder::keepalive
+
+use crate::connection::Connection;
+use crate::{Handler, Member, Relocation, Room, RoomHandler, RoomRef};
+use std::convert::TryFrom;
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use ws::util::Token;
+use ws::Sender;
+
+/// Timeout token used to ping a connection, see [`Handler::beat`]
+pub(crate) const PING: Token = Token(usize::MAX - 101);
+
+type RelocateFn<G> = dyn Fn(&G) -> Relocation + Send;
+
+/// How often connections are pinged, and how many pongs they can miss before being reaped
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Keepalive {
+    pub(crate) interval: Duration,
+    pub(crate) misses: u32,
+}
+
+impl Keepalive {
+    pub(crate) fn new(interval: Duration, misses: u32) -> Self {
+        assert!(
+            misses > 0,
+            "a keepalive needs to allow at least one missed pong"
+        );
+        Self { interval, misses }
+    }
+
+    /// Schedules the next ping of the connection of `sender`
+    fn arm(&self, sender: &Sender) -> ws::Result<()> {
+        let ms = u64::try_from(self.interval.as_millis()).unwrap_or(u64::MAX);
+        sender.timeout(ms, PING)
+    }
+}
+
+/// What happens to a member of a room that didn't answer enough pings, see
+/// [`RoomRef::set_keepalive`].
+pub enum Unresponsive<G> {
+    /// Its connection is dropped, like with [`Builder::keepalive`][crate::Builder::keepalive]
+    Close,
+
+    /// It is moved to the room of the relocation built from its identity, e.g. a "reconnecting"
+    /// room where it can wait for its network to come back
+    Relocate(Box<RelocateFn<G>>),
+}
+
+impl<G> Unresponsive<G> {
+    /// Shorthand for [`Unresponsive::Relocate`]
+    pub fn relocate<F: Fn(&G) -> Relocation + Send + 'static>(f: F) -> Self {
+        Self::Relocate(Box::new(f))
+    }
+}
+
+impl<G> Debug for Unresponsive<G> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Close => f.write_str("Close"),
+            Self::Relocate(_) => f.write_str("Relocate(_)"),
+        }
+    }
+}
+
+pub(crate) struct RoomKeepalive<G> {
+    keepalive: Keepalive,
+    policy: Unresponsive<G>,
+}
+
+/// The pings of a connection
+#[derive(Debug, Default)]
+pub(crate) struct Heartbeat {
+    /// Whether the handshake is complete, so that pings can be sent
+    pub(crate) open: bool,
+
+    /// Pings sent since the last pong
+    unanswered: u32,
+
+    /// Number of pings sent, which is also the payload of the last one
+    sent: u64,
+
+    /// When the last ping was sent, according to the [Clock][crate::Clock] of the room of the
+    /// client
+    last_ping: Option<Instant>,
+
+    /// Shared with the room the client is a member of
+    pub(crate) pulse: Arc<Pulse>,
+}
+
+impl Heartbeat {
+    /// Records a pong received at `now`, measuring the round-trip time if it answers the last ping
+    pub(crate) fn pong(&mut self, payload: &[u8], now: Instant) {
+        self.unanswered = 0;
+
+        if let Some(sent) = self.last_ping {
+            if payload == self.sent.to_be_bytes() {
+                self.pulse.set_rtt(now.saturating_duration_since(sent));
+                self.last_ping = None;
+            }
+        }
+    }
+}
+
+/// The state of the pings of a connection that its room can see
+#[derive(Debug)]
+pub(crate) struct Pulse {
+    /// The last round-trip time measured, in microseconds
+    rtt: AtomicU64,
+
+    /// Whether the next ping is scheduled. It is only cleared while the room of the client is
+    /// locked, so that a room enabling its keepalive doesn't miss a connection that is about to
+    /// stop being pinged.
+    armed: AtomicBool,
+}
+
+impl Pulse {
+    const UNKNOWN: u64 = u64::MAX;
+
+    pub(crate) fn rtt(&self) -> Option<Duration> {
+        match self.rtt.load(Ordering::Relaxed) {
+            Self::UNKNOWN => None,
+            micros => Some(Duration::from_micros(micros)),
+        }
+    }
+
+    fn set_rtt(&self, rtt: Duration) {
+        let micros = u64::try_from(rtt.as_micros()).unwrap_or(Self::UNKNOWN - 1);
+        self.rtt.store(micros, Ordering::Relaxed);
+    }
+
+    /// Marks the next ping as scheduled, returning whether it wasn't already
+    fn arm(&self) -> bool {
+        !self.armed.swap(true, Ordering::Relaxed)
+    }
+
+    /// Whether the connection is being pinged
+    pub(crate) fn is_armed(&self) -> bool {
+        self.armed.load(Ordering::Relaxed)
+    }
+
+    fn disarm(&self) {
+        self.armed.store(false, Ordering::Relaxed);
+    }
+}
+
+impl Default for Pulse {
+    fn default() -> Self {
+        Self {
+            rtt: AtomicU64::new(Self::UNKNOWN),
+            armed: AtomicBool::new(false),
+        }
+    }
+}
+
+/// Round-trip times of the members of a room, see [`RoomRef::rtt_stats`].
+///
+/// Only members whose round-trip time was measured are accounted for, which requires
+/// [`Builder::keepalive`][crate::Builder::keepalive] or [`RoomRef::set_keepalive`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RttStats {
+    /// Number of members whose round-trip time is known
+    pub measured: usize,
+
+    /// Lowest round-trip time
+    pub min: Option<Duration>,
+
+    /// Average round-trip time
+    pub mean: Option<Duration>,
+
+    /// Highest round-trip time
+    pub max: Option<Duration>,
+}
+
+impl RttStats {
+    pub(crate) fn of<G>(members: &[Member<G>]) -> Self {
+        Self::from_rtts(members.iter().filter_map(|member| member.pulse.rtt()))
+    }
+
+    fn from_rtts(rtts: impl Iterator<Item = Duration>) -> Self {
+        let mut stats = Self::default();
+        let mut total = Duration::ZERO;
+
+        for rtt in rtts {
+            stats.measured += 1;
+            stats.min = Some(stats.min.map_or(rtt, |min| min.min(rtt)));
+            stats.max = Some(stats.max.map_or(rtt, |max| max.max(rtt)));
+            total += rtt;
+        }
+
+        if stats.measured > 0 {
+            stats.mean = Some(total / u32::try_from(stats.measured).unwrap_or(u32::MAX));
+        }
+        stats
+    }
+}
+
+impl<R: RoomHandler> RoomRef<R> {
+    /// Summarizes the round-trip times of the members of the room, e.g. to match players with
+    /// rooms close to them
+    pub fn rtt_stats(&self) -> RttStats {
+        RttStats::of(&self.0.lock().unwrap().members)
+    }
+
+    /// Pings the members of the room every `interval`, and applies `policy` to the ones that
+    /// didn't answer the last `misses` pings. It replaces the keepalive of the hotel (see
+    /// [`Builder::keepalive`][crate::Builder::keepalive]) for the members of the room, so that
+    /// e.g. a game can be strict while a chat is tolerant.
+    ///
+    /// Members that are already in the room follow the new settings from their next ping on. The
+    /// ones that weren't pinged yet are scheduled a ping right away: if it fails for some of them,
+    /// the others are still scheduled and the first error is returned.
+    ///
+    /// # Panics
+    ///
+    /// If `misses` is 0.
+    pub fn set_keepalive(
+        &self,
+        interval: Duration,
+        misses: u32,
+        policy: Unresponsive<R::Guest>,
+    ) -> ws::Result<()> {
+        let keepalive = Keepalive::new(interval, misses);
+        let mut room = self.0.lock().unwrap();
+        room.keepalive = Some(RoomKeepalive { keepalive, policy });
+
+        let mut result = Ok(());
+        for member in &room.members {
+            if member.pulse.arm() {
+                if let Err(err) = keepalive.arm(&member.sender) {
+                    member.pulse.disarm();
+                    result = result.and(Err(err));
+                }
+            }
+        }
+        result
+    }
+
+    /// Makes the members of the room follow the keepalive of the hotel again, see
+    /// [`RoomRef::set_keepalive`]
+    pub fn unset_keepalive(&self) {
+        self.0.lock().unwrap().keepalive = None;
+    }
+}
+
+impl<R: RoomHandler> Room<R> {
+    /// The keepalive the member with `pulse` follows: the one of the room, or else `fallback`,
+    /// the one of the hotel. Its pings stop if there is none.
+    pub(crate) fn keepalive(
+        &self,
+        pulse: &Pulse,
+        fallback: Option<Keepalive>,
+    ) -> Option<Keepalive> {
+        let keepalive = self.keepalive.as_ref().map(|k| k.keepalive).or(fallback);
+        if keepalive.is_none() {
+            pulse.disarm();
+        }
+        keepalive
+    }
+
+    /// Where the unresponsive member of `connection` goes, or `None` if it is dropped
+    pub(crate) fn on_unresponsive(&self, connection: &Connection) -> Option<Relocation> {
+        match &self.keepalive.as_ref()?.policy {
+            Unresponsive::Close => None,
+            Unresponsive::Relocate(relocate) => self
+                .members
+                .iter()
+                .find(|member| member.id == connection.id)
+                .map(|member| relocate(&member.guest)),
+        }
+    }
+}
+
+impl Handler {
+    /// The keepalive the client follows, if it is pinged at all
+    fn keepalive(&self) -> Option<Keepalive> {
+        let pulse = &self.connection.heartbeat.pulse;
+
+        if self.in_room && self.connection.heartbeat.open {
+            self.room.keepalive(pulse, self.server.keepalive)
+        } else {
+            pulse.disarm();
+            None
+        }
+    }
+
+    /// Schedules the next ping of the client if it isn't already, e.g. when it enters a room that
+    /// has a keepalive
+    pub(crate) fn arm_keepalive(&mut self) -> ws::Result<()> {
+        if !self.connection.heartbeat.pulse.arm() {
+            return Ok(());
+        }
+
+        match self.keepalive() {
+            Some(keepalive) => keepalive.arm(&self.connection.sender),
+            None => Ok(()),
+        }
+    }
+
+    /// Pings the client, unless it missed too many pongs already.
+    ///
+    /// Closing a dead connection would wait forever for the client to acknowledge it, so it is
+    /// dropped right away instead, by returning an I/O error: `ws` then disconnects it and it
+    /// leaves its room as [disconnected][crate::LeaveReason::Disconnected] with
+    /// [`CloseCode::Abnormal`][crate::CloseCode::Abnormal]. Rooms can relocate it instead.
+    pub(crate) fn beat(&mut self) -> ws::Result<()> {
+        let keepalive = match self.keepalive() {
+            Some(keepalive) => keepalive,
+            None => return Ok(()),
+        };
+
+        let heartbeat = &mut self.connection.heartbeat;
+        if heartbeat.unanswered >= keepalive.misses {
+            let relocation = match self.room.on_unresponsive(&self.connection) {
+                Some(relocation) => relocation,
+                None => {
+                    let err = io::Error::new(io::ErrorKind::TimedOut, "no pong from the client");
+                    return Err(err.into());
+                }
+            };
+
+            self.connection.heartbeat.unanswered = 0;
+            self.relocate(Some(relocation))?;
+            return match self.keepalive() {
+                Some(keepalive) => keepalive.arm(&self.connection.sender),
+                None => Ok(()),
+            };
+        }
+
+        heartbeat.unanswered += 1;
+        heartbeat.sent += 1;
+        heartbeat.last_ping = Some(self.room.now());
+        let payload = heartbeat.sent.to_be_bytes().to_vec();
+        self.connection.sender.ping(payload)?;
+        keepalive.arm(&self.connection.sender)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summarizes_measured_rtts() {
+        assert_eq!(RttStats::from_rtts(std::iter::empty()), RttStats::default());
+
+        let rtts = [30, 10, 20].iter().map(|ms| Duration::from_millis(*ms));
+        assert_eq!(
+            RttStats::from_rtts(rtts),
+            RttStats {
+                measured: 3,
+                min: Some(Duration::from_millis(10)),
+                mean: Some(Duration::from_millis(20)),
+                max: Some(Duration::from_millis(30)),
+            }
+        );
+    }
+
+    #[test]
+    fn stores_rtts_in_pulses() {
+        let pulse = Pulse::default();
+        assert_eq!(pulse.rtt(), None);
+
+        pulse.set_rtt(Duration::from_micros(1500));
+        assert_eq!(pulse.rtt(), Some(Duration::from_micros(1500)));
+    }
+}
@@ -2,29 +2,115 @@
 //!
 //! _Your websocket server, with rooms._
 
-#![feature(arc_new_cyclic)]
-
+// `ws::Error` is a foreign type that every callback returns, boxing it isn't an option
+#![allow(clippy::result_large_err)]
+
+use capacity::{Capacity, WhenFull as Full};
+use connection::Connection;
+use keepalive::{Keepalive, Pulse, RoomKeepalive};
+use memory::{GuestSizeFn, MemoryLimit};
+use middleware::Broadcasts;
+use rand::SeedableRng;
 use std::any::Any;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{BTreeSet, VecDeque};
 use std::fmt::{Debug, Formatter};
 use std::hash::{Hash, Hasher};
 use std::marker::PhantomData;
 use std::net::ToSocketAddrs;
 use std::ops::Deref;
-use std::sync::{Arc, Mutex, Weak};
-use ws::util::Token;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, TryLockError, Weak};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tick::Ticker;
+use timer::Action;
 use ws::Sender;
 
-pub use ws::{self, CloseCode, Handshake, Message, Result};
+pub use ws::util::Token;
+pub use ws::{self, CloseCode, Handshake, Message, Request, Response, Result};
+
+pub use balance::{Balance, LeastMembers, RoundRobin};
+pub use builder::RoomBuilder;
+pub use calendar::{Calendar, InvalidCalendar, Missed};
+pub use capacity::WhenFull;
+pub use clock::{Clock, SystemClock};
+pub use config::{HotelConfig, InvalidConfig};
+pub use connection::Extensions;
+pub use entrance::Entrance;
+pub use events::RoomEvent;
+pub use hotel::{Hotel, Rooms};
+pub use idle::RoomRegistry;
+pub use inactivity::Inactivity;
+pub use keepalive::{RttStats, Unresponsive};
+pub use lifetime::Expiry;
+pub use member::{MemberHandle, MemberId};
+pub use memory::{Eviction, MemoryUsage};
+pub use metadata::Metadata;
+pub use middleware::Middleware;
+pub use proxy::{InvalidIpRange, IpRange};
+pub use query::Query;
+pub use rand::{self, rngs::StdRng};
+pub use room_context::RoomContext;
+pub use router::Router;
+pub use rpc::{Calls, Rpc, RpcHandler};
+pub use server::{Builder, Rejection, ServerHandle};
+pub use slow::SlowConsumer;
+pub use timer::TimerHandle;
+pub use typed::{Codec, TextCodec, TypedRoomHandler};
+pub use validate::ValidateGuest;
+
+mod alarm;
+mod balance;
+mod builder;
+mod calendar;
+mod capacity;
+mod channels;
+mod clock;
+mod config;
+mod connection;
+mod entrance;
+mod events;
+mod hotel;
+mod idle;
+mod inactivity;
+mod keepalive;
+mod lifetime;
+mod member;
+mod memory;
+mod metadata;
+mod middleware;
+mod per_ip;
+mod proxy;
+mod query;
+mod room_context;
+mod router;
+mod rpc;
+mod scheduler;
+mod server;
+#[cfg(all(feature = "signals", unix))]
+mod signals;
+mod slow;
+mod tick;
+mod timer;
+mod typed;
+mod validate;
+
+pub mod test;
 
 /// A room in which websocket clients can be moved
 ///
 /// It effectively contains a user-provided [`RoomHandler`] as R as well as a set of users that
 /// are in the room.
-pub struct RoomRef<R: RoomHandler>(Arc<Mutex<Room<R>>>);
+///
+/// Its [RoomId] is kept outside of the room's lock, so that it can be read from anywhere.
+pub struct RoomRef<R: RoomHandler>(Arc<Mutex<Room<R>>>, RoomId);
 
 impl<R: RoomHandler> RoomRef<R> {
     pub fn downgrade(&self) -> RoomRefWeak<R> {
-        RoomRefWeak(Arc::downgrade(&self.0))
+        RoomRefWeak(Arc::downgrade(&self.0), self.1)
     }
 
     /// Run code that needs access to the internal `RoomHandler`.
@@ -34,11 +120,345 @@ impl<R: RoomHandler> RoomRef<R> {
     pub fn with<F: FnOnce(&mut R) -> T, T>(&self, f: F) -> T {
         f(&mut self.0.lock().unwrap().handler)
     }
+
+    /// Replaces the handler of the room while its members stay in it, e.g. to move from a
+    /// "waiting" phase to a "playing" phase. The new handler can take over the state of the
+    /// previous one in [`RoomHandler::on_replace`], and the previous one is returned.
+    ///
+    /// Like [`RoomRef::with`], this locks the room.
+    pub fn replace_handler(&self, handler: R) -> R {
+        self.map_handler(move |_| handler)
+    }
+
+    /// Like [`RoomRef::replace_handler`], with the new handler built from the previous one
+    pub fn map_handler<F: FnOnce(&mut R) -> R>(&self, f: F) -> R {
+        Room::dispatch_room(&self.0, move |handler, cx| {
+            let next = f(handler);
+            let mut previous = std::mem::replace(handler, next);
+            handler.on_replace(cx, &mut previous);
+            previous
+        })
+    }
+
+    /// Like [`RoomRef::with`], but fails instead of blocking if the room is locked, for instance
+    /// because one of its callbacks is running (possibly on this very thread), so the caller can
+    /// retry later or [defer][Context::defer] the work.
+    pub fn try_with<F: FnOnce(&mut R) -> T, T>(&self, f: F) -> std::result::Result<T, WouldBlock> {
+        match self.0.try_lock() {
+            Ok(mut room) => Ok(f(&mut room.handler)),
+            Err(TryLockError::WouldBlock) => Err(WouldBlock),
+            Err(TryLockError::Poisoned(err)) => panic!("{}", err),
+        }
+    }
+
+    /// Sends a message to everyone in the room, from outside of its callbacks (e.g. from a
+    /// background thread).
+    ///
+    /// Like [`RoomRef::with`], this locks the room, so calling it from one of the room's own
+    /// callbacks deadlocks: use [`Context::broadcast`] there, or [`Context::send_room`] for other
+    /// rooms. Failures are handled like with [`Context::broadcast`].
+    pub fn broadcast(&self, msg: impl Into<Message>) -> ws::Result<()> {
+        let msg = msg.into();
+        Room::dispatch_room(&self.0, |_, cx| cx.broadcast(msg))
+    }
+
+    /// Number of members in the room
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap().members.len()
+    }
+
+    /// Whether the room has no members
+    pub fn is_empty(&self) -> bool {
+        self.0.lock().unwrap().members.is_empty()
+    }
+
+    /// Closes the connection of every member of the room, see [`MemberHandle::kick`]. Each of them
+    /// leaves the room with [`LeaveReason::Kicked`] once its connection is closed.
+    ///
+    /// Like [`RoomRef::with`], this locks the room. Stops at the first connection that can't be
+    /// closed.
+    pub fn kick_all(
+        &self,
+        code: CloseCode,
+        reason: impl Into<Cow<'static, str>>,
+    ) -> ws::Result<()> {
+        let reason = reason.into();
+
+        self.0
+            .lock()
+            .unwrap()
+            .members
+            .iter()
+            .try_for_each(|member| {
+                member::kick(&member.sender, &member.mailbox, code, reason.clone())
+            })
+    }
+
+    /// Moves the member of the room identified by `id` to another room, from outside of any
+    /// callback (e.g. from an admin API or a matchmaking service). See [`MemberHandle::relocate`].
+    ///
+    /// Fails if there is no such member in the room. Like [`RoomRef::with`], this locks the room,
+    /// use [`Context::relocate_other`] from the room's own callbacks.
+    pub fn relocate_member(&self, id: MemberId, relocation: Relocation) -> ws::Result<()> {
+        let room = self.0.lock().unwrap();
+
+        room.members
+            .iter()
+            .find(|member| member.id == id)
+            .map(MemberHandle::of)
+            .ok_or_else(|| member::not_found(id))?
+            .relocate(relocation)
+    }
+
+    /// Moves every member of the room into `other`, with the identity returned by `identity`.
+    ///
+    /// Like with [`MemberHandle::relocate`], members are moved asynchronously by their own
+    /// connection: each of them leaves this room and then joins `other` (with
+    /// [`RoomHandler::on_relocate_out`] and [`RoomHandler::on_relocate_in`]), one member after
+    /// the other in the order of their [MemberId], which is the order they connected in.
+    ///
+    /// Like [`RoomRef::with`], this locks the room, so it can't be called from the room's own
+    /// callbacks.
+    pub fn merge_into<O, F>(&self, other: &RoomRef<O>, identity: F) -> ws::Result<()>
+    where
+        O: RoomHandler + Send + 'static,
+        O::Guest: Send + 'static,
+        F: FnMut(&R::Guest) -> O::Guest,
+    {
+        self.0.lock().unwrap().relocate_all(other, identity)
+    }
+
+    /// Disbands the room: like with [`RoomRef::merge_into`], every member is relocated to `to` with
+    /// the identity returned by `identity`, and the room refuses any relocation into it from now
+    /// on.
+    pub fn disband<O, F>(&self, to: &RoomRef<O>, identity: F) -> ws::Result<()>
+    where
+        O: RoomHandler + Send + 'static,
+        O::Guest: Send + 'static,
+        F: FnMut(&R::Guest) -> O::Guest,
+    {
+        let mut room = self.0.lock().unwrap();
+        room.sealed = Some(Full::Stay);
+        room.relocate_all(to, identity)
+    }
+
+    /// Seals the room: relocations into it are refused from now on, and clients that were being
+    /// relocated stay in the room they were in. Its current members aren't affected.
+    pub fn seal(&self) {
+        self.0.lock().unwrap().sealed = Some(Full::Stay);
+    }
+
+    /// Like [`RoomRef::seal`], but clients that are relocated into the room are bounced to the
+    /// relocation built from their identity by `fallback` instead (e.g. a spectator room).
+    pub fn seal_with_fallback<F>(&self, fallback: F)
+    where
+        F: Fn(R::Guest) -> Relocation + Send + 'static,
+    {
+        self.0.lock().unwrap().sealed = Some(WhenFull::overflow(fallback));
+    }
+
+    /// Accepts relocations into the room again, after [`RoomRef::seal`]
+    pub fn unseal(&self) {
+        self.0.lock().unwrap().sealed = None;
+    }
+
+    /// Whether the room is [sealed][RoomRef::seal]
+    pub fn is_sealed(&self) -> bool {
+        self.0.lock().unwrap().sealed.is_some()
+    }
+
+    /// Removes every member from the room without closing their connection, and returns their
+    /// identity along with a handle to each of them, in the order they connected in.
+    ///
+    /// This is meant for migrations that [`RoomRef::merge_into`] can't express: the caller decides
+    /// where each member goes next, and moves it there with [`MemberHandle::relocate`]. Until
+    /// then, drained members aren't in any room, and the messages they send are ignored.
+    ///
+    /// No [`on_leave`][RoomHandler::on_leave] is called for drained members. Like
+    /// [`RoomRef::with`], this locks the room, so it can't be called from the room's own callbacks.
+    pub fn drain(&self) -> Vec<(R::Guest, MemberHandle)> {
+        let members = {
+            let mut room = self.0.lock().unwrap();
+            let mut members = std::mem::take(&mut room.members);
+            members.sort_by_key(|member| member.id);
+
+            for member in &members {
+                room.record(RoomChange::Left(member.id));
+            }
+            room.update_empty_since();
+
+            members
+        };
+
+        if !members.is_empty() {
+            Room::dispatch_room(&self.0, |h, cx| h.on_room_empty(cx));
+        }
+
+        members
+            .into_iter()
+            .map(|member| {
+                let handle = MemberHandle::of(&member);
+                (member.guest, handle)
+            })
+            .collect()
+    }
+
+    /// Calls `f` with each member of the room and its identity, from outside of its callbacks.
+    ///
+    /// The room is locked during the whole iteration, see [`RoomRef::with`].
+    pub fn for_each_member<F: FnMut(MemberId, &R::Guest)>(&self, mut f: F) {
+        self.0
+            .lock()
+            .unwrap()
+            .members
+            .iter()
+            .for_each(|member| f(member.id, &member.guest))
+    }
+
+    /// Like [`RoomRef::for_each_member`], but `f` can mutate the identity of each member (e.g. to
+    /// apply a correction computed outside of the room), and receives a handle to send it
+    /// messages during the same pass.
+    ///
+    /// The room is locked during the whole iteration, see [`RoomRef::with`].
+    pub fn for_each_member_mut<F: FnMut(&mut R::Guest, MemberHandle)>(&self, mut f: F) {
+        self.0
+            .lock()
+            .unwrap()
+            .members
+            .iter_mut()
+            .for_each(|member| {
+                let handle = MemberHandle::of(member);
+                f(&mut member.guest, handle)
+            })
+    }
+
+    /// Returns a copy of the list of members of the room and their identity, e.g. for a status
+    /// page. See [`RoomRef::for_each_member`] to avoid cloning the identities.
+    pub fn members(&self) -> Vec<(MemberId, R::Guest)>
+    where
+        R::Guest: Clone,
+    {
+        let mut members = Vec::new();
+        self.for_each_member(|id, guest| members.push((id, guest.clone())));
+        members
+    }
+
+    /// Sends a message to the members of the room whose identity matches `predicate`, from outside
+    /// of its callbacks, returning how many there were.
+    ///
+    /// Like [`RoomRef::with`], this locks the room. Failures are handled like with
+    /// [`Context::broadcast`], and the members the message couldn't be sent to are counted too.
+    pub fn send_where<F>(&self, predicate: F, msg: impl Into<Message>) -> ws::Result<usize>
+    where
+        F: FnMut(&R::Guest) -> bool,
+    {
+        let msg = msg.into();
+        Ok(Room::dispatch_room(&self.0, |_, cx| {
+            cx.send_where(predicate, msg)
+        }))
+    }
+
+    /// Describes how the membership of the room changed since `version`.
+    ///
+    /// This is meant to be polled, for instance by an admin dashboard: pass `0` the first time,
+    /// then the [`RoomDiff::version`] returned by the previous call. Only the last
+    /// [`MAX_ROOM_CHANGES`] changes are remembered, if `version` is older than that, the returned
+    /// diff is [truncated][RoomDiff::truncated].
+    pub fn diff_since(&self, version: u64) -> RoomDiff {
+        self.0.lock().unwrap().diff_since(version)
+    }
+
+    /// Enables compression of the messages sent with [`Context::send_compressible`] and
+    /// [`Context::broadcast_compressible`] that are at least `threshold` bytes long.
+    ///
+    /// Compressed messages are sent as binary frames containing the output of `compress`, smaller
+    /// ones are sent untouched so latency-sensitive frames don't pay for it. This is independent
+    /// of the permessage-deflate extension, and the client must know how to decompress them.
+    pub fn set_compression<F>(&self, threshold: usize, compress: F)
+    where
+        F: Fn(&[u8]) -> Vec<u8> + Send + 'static,
+    {
+        self.0.lock().unwrap().compression = Some(Compression {
+            threshold,
+            compress: Box::new(compress),
+        });
+    }
+
+    /// Disables the compression enabled by [`RoomRef::set_compression`]
+    pub fn unset_compression(&self) {
+        self.0.lock().unwrap().compression = None;
+    }
+
+    /// Replaces the [Clock] read by [`Context::now`], which is the [SystemClock] by default.
+    ///
+    /// Instants of the previous clock can't be compared to the ones of the new one, so an empty
+    /// room is considered empty since the clock was replaced (see [`RoomRef::set_idle_ttl`]).
+    pub fn set_clock(&self, clock: impl Clock + 'static) {
+        let mut room = self.0.lock().unwrap();
+        room.clock = Arc::new(clock);
+
+        if room.empty_since.is_some() {
+            room.empty_since = Some(room.clock.now());
+        }
+    }
+
+    /// Sets the function used to estimate the memory used by each guest, in bytes, on top of its
+    /// inline size. It should account for heap allocations like strings or buffers.
+    pub fn set_guest_size<F>(&self, estimate: F)
+    where
+        F: Fn(&R::Guest) -> usize + Send + 'static,
+    {
+        self.0.lock().unwrap().guest_size = Some(Box::new(estimate));
+    }
+
+    /// Estimates the memory used by the room
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.0.lock().unwrap().memory_usage()
+    }
+
+    /// Sets a ceiling on the [memory usage][RoomRef::memory_usage] of the room. Whenever it is
+    /// exceeded after a callback, members are kicked according to `eviction` (with
+    /// [`CloseCode::Again`]) until the room fits in it again.
+    pub fn set_memory_limit(&self, bytes: usize, eviction: Eviction) {
+        self.0.lock().unwrap().memory_limit = Some(MemoryLimit { bytes, eviction });
+    }
+
+    /// Removes the ceiling set by [`RoomRef::set_memory_limit`]
+    pub fn unset_memory_limit(&self) {
+        self.0.lock().unwrap().memory_limit = None;
+    }
+
+    /// Re-seeds the random number generator returned by [`Context::rng`], so the random decisions
+    /// of the handler can be reproduced. It is seeded from the system's entropy by default.
+    pub fn seed_rng(&self, seed: u64) {
+        self.0.lock().unwrap().rng = StdRng::seed_from_u64(seed);
+    }
+
+    /// Name of the room, used to tell rooms apart in logs and [Debug] output
+    pub fn name(&self) -> Option<String> {
+        self.0.lock().unwrap().name.clone()
+    }
+
+    /// Names the room, see [`RoomRef::name`]
+    pub fn set_name(&self, name: impl Into<String>) {
+        self.0.lock().unwrap().name = Some(name.into());
+    }
+
+    /// Closes the connection of members sending messages larger than `bytes` (with
+    /// [`CloseCode::Size`]), before they reach the handler.
+    pub fn set_max_message_size(&self, bytes: usize) {
+        self.0.lock().unwrap().max_message_size = Some(bytes);
+    }
+
+    /// Removes the limit set by [`RoomRef::set_max_message_size`]
+    pub fn unset_max_message_size(&self) {
+        self.0.lock().unwrap().max_message_size = None;
+    }
 }
 
 impl<R: RoomHandler> Clone for RoomRef<R> {
     fn clone(&self) -> Self {
-        Self(Arc::clone(&self.0))
+        Self(Arc::clone(&self.0), self.1)
     }
 }
 
@@ -76,17 +496,22 @@ where
 
 /// A weak [RoomRef] equivalent. Like with an [Arc], it must be [upgrade][RoomRefWeak::upgrade]d to
 /// be usable.
-pub struct RoomRefWeak<R: RoomHandler>(Weak<Mutex<Room<R>>>);
+pub struct RoomRefWeak<R: RoomHandler>(Weak<Mutex<Room<R>>>, RoomId);
 
 impl<R: RoomHandler> RoomRefWeak<R> {
     pub fn upgrade(&self) -> Option<RoomRef<R>> {
-        self.0.upgrade().map(RoomRef)
+        self.0.upgrade().map(|room| RoomRef(room, self.1))
+    }
+
+    /// Address of the room, see [RoomAddr]
+    pub(crate) fn addr(&self) -> RoomAddr {
+        RoomAddr(self.0.as_ptr() as *const () as usize)
     }
 }
 
 impl<R: RoomHandler> Clone for RoomRefWeak<R> {
     fn clone(&self) -> Self {
-        Self(self.0.clone())
+        Self(self.0.clone(), self.1)
     }
 }
 
@@ -121,76 +546,435 @@ where
     }
 }
 
+/// The address of a room, that can be compared to other addresses regardless of the type of
+/// their [RoomHandler].
+///
+/// Like [RoomRef]'s [Eq] implementation, it is based on the location of the room in memory, so
+/// the address of a room that has been dropped may be reused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RoomAddr(usize);
+
+impl RoomAddr {
+    fn of<T: ?Sized>(room: &Arc<T>) -> Self {
+        Self(Arc::as_ptr(room) as *const () as usize)
+    }
+}
+
+impl<R: RoomHandler> RoomRef<R> {
+    /// Address of the room, see [RoomAddr]
+    pub fn addr(&self) -> RoomAddr {
+        RoomAddr::of(&self.0)
+    }
+
+    /// Identifier of the room, see [RoomId]. Unlike most methods, it doesn't lock the room, so it
+    /// can be called from the room's own callbacks.
+    pub fn id(&self) -> RoomId {
+        self.1
+    }
+}
+
+/// Unique identifier of a room, assigned when it is created.
+///
+/// Unlike [RoomAddr], it is never reused during the lifetime of the process, so it can be used as
+/// a key in maps, written to logs or sent to clients to refer to a room.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RoomId(u64);
+
+impl RoomId {
+    fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// The identifier as a number, e.g. to serialize it
+    pub fn get(self) -> u64 {
+        self.0
+    }
+}
+
+impl std::fmt::Display for RoomId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "room #{}", self.0)
+    }
+}
+
+/// Error returned by [`RoomRef::try_with`] when the room is locked
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WouldBlock;
+
+impl std::fmt::Display for WouldBlock {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("room is locked")
+    }
+}
+
+impl std::error::Error for WouldBlock {}
+
+/// Why a member left a room, passed to [`RoomHandler::on_leave`]
+#[derive(Clone, Copy, Debug)]
+pub enum LeaveReason<'a> {
+    /// The member was moved to another room (or back into the same one)
+    Relocated {
+        /// Address of the destination room
+        to: RoomAddr,
+    },
+
+    /// The client closed the connection, or it was lost
+    Disconnected { code: CloseCode, reason: &'a str },
+
+    /// The connection was closed by the server, for instance with [`MemberHandle::kick`]
+    Kicked,
+
+    /// The server is shutting down
+    ServerShutdown,
+}
+
 pub struct Room<R: RoomHandler> {
     self_ref: RoomRefWeak<R>,
+    id: RoomId,
+    name: Option<String>,
+    metadata: Arc<Metadata>,
 
     handler: R,
-    members: Vec<(R::Guest, Sender)>,
+    members: Vec<Member<R::Guest>>,
+
+    version: u64,
+    changes: VecDeque<(u64, RoomChange)>,
+
+    compression: Option<Compression>,
+
+    clock: Arc<dyn Clock>,
+    rng: StdRng,
+
+    guest_size: Option<Box<GuestSizeFn<R::Guest>>>,
+    memory_limit: Option<MemoryLimit>,
+
+    ticker: Option<Arc<Ticker>>,
+    lifetime: Option<lifetime::Lifetime<R::Guest>>,
+
+    /// The [run][server::ServerState::run] of the server [`RoomHandler::on_shutdown`] was last
+    /// called for
+    shut_down: Option<u64>,
+
+    middleware: middleware::Stack<R>,
+
+    /// How relocations into the room are refused, if it is sealed
+    sealed: Option<WhenFull<R::Guest>>,
+
+    capacity: Option<Capacity<R::Guest>>,
+
+    /// See [`RoomRef::validate_guests`]
+    validate: Option<validate::ValidateFn<R>>,
+
+    idle_ttl: Option<Duration>,
+    /// When the last member left, or when the room was created
+    empty_since: Option<Instant>,
+
+    max_message_size: Option<usize>,
+
+    inactivity: Option<inactivity::InactivityTimeout<R::Guest>>,
+    keepalive: Option<RoomKeepalive<R::Guest>>,
+
+    /// Observers of the room, see [`RoomRef::subscribe`]
+    subscribers: Vec<std::sync::mpsc::Sender<RoomEvent>>,
+}
+
+#[derive(Debug)]
+struct Member<G> {
+    id: MemberId,
+    guest: G,
+    sender: Sender,
+    /// Channels of the room the member is subscribed to, see [`Context::join_channel`]
+    channels: BTreeSet<String>,
+
+    /// When the member last sent a message or a pong, see [`RoomRef::set_inactivity_timeout`]
+    last_active: Instant,
+    /// The timer checking the inactivity of the member, if the room watches it
+    inactivity: Option<TimerHandle>,
+    /// Round-trip time of the connection and whether it is pinged, see [`Context::rtt`]
+    pulse: Arc<Pulse>,
+    /// Requests made to the connection from outside of its handler
+    mailbox: Arc<member::Mailbox>,
+}
+
+/// Failures of the broadcasts made during a callback, reported to [`RoomHandler::on_send_error`]
+/// once it has returned
+#[derive(Default)]
+struct SendErrors(RefCell<Vec<(MemberId, ws::Error)>>);
+
+impl SendErrors {
+    /// Sends a message to `member`, recording the failure if there is one
+    fn send<G>(&self, member: &Member<G>, msg: Message) {
+        if let Err(err) = member.sender.send(msg) {
+            self.record(member.id, err);
+        }
+    }
+
+    fn record(&self, member: MemberId, err: ws::Error) {
+        self.0.borrow_mut().push((member, err));
+    }
+
+    fn take(&self) -> Vec<(MemberId, ws::Error)> {
+        self.0.take()
+    }
+}
+
+type CompressFn = dyn Fn(&[u8]) -> Vec<u8> + Send;
+
+struct Compression {
+    threshold: usize,
+    compress: Box<CompressFn>,
+}
+
+impl Compression {
+    fn apply(&self, msg: Message) -> Message {
+        if msg.len() < self.threshold {
+            return msg;
+        }
+
+        Message::Binary((self.compress)(&msg.into_data()))
+    }
+}
+
+/// Number of membership changes a room remembers for [`RoomRef::diff_since`]
+pub const MAX_ROOM_CHANGES: usize = 1024;
+
+enum RoomChange {
+    Joined(MemberId),
+    Left(MemberId),
+}
+
+/// Membership changes of a room between two versions, see [`RoomRef::diff_since`].
+#[derive(Clone, Debug, Default)]
+pub struct RoomDiff {
+    /// Current version of the room, to be passed to the next call to [`RoomRef::diff_since`]
+    pub version: u64,
+
+    /// Whether the requested version was too old (or unknown) for the diff to be computed. In that
+    /// case, [`joined`][RoomDiff::joined] and [`left`][RoomDiff::left] are empty and the caller
+    /// should fetch the whole state of the room again.
+    pub truncated: bool,
+
+    /// Members that entered the room and are still in it
+    pub joined: Vec<MemberId>,
+
+    /// Members that were in the room and aren't anymore
+    pub left: Vec<MemberId>,
+
+    /// Current number of members in the room
+    pub members: usize,
 }
 
 impl<R: RoomHandler> Room<R> {
-    fn with_context<F: FnOnce(&mut R, Context<R>) -> O, O>(&mut self, sender: &Sender, f: F) -> O {
-        // TODO: remove
-        //     Instead of allocating, use unsafe wrapper around HashMap that allows value mutation
-        //     but no other kind of mutation. Thus, it will be possible to use `broadcast` or
-        //     access the list of Senders in the room without preventing mutable access to the
-        //     current identity.
-        let todo = self
+    fn relocate_all<O, F>(&self, to: &RoomRef<O>, mut identity: F) -> ws::Result<()>
+    where
+        O: RoomHandler + Send + 'static,
+        O::Guest: Send + 'static,
+        F: FnMut(&R::Guest) -> O::Guest,
+    {
+        let mut members = self.members.iter().collect::<Vec<_>>();
+        members.sort_by_key(|member| member.id);
+
+        members.into_iter().try_for_each(|member| {
+            MemberHandle::of(member).relocate(Relocation::new(to, identity(&member.guest)))
+        })
+    }
+
+    fn record(&mut self, change: RoomChange) {
+        self.version += 1;
+
+        if self.changes.len() == MAX_ROOM_CHANGES {
+            self.changes.pop_front();
+        }
+
+        self.changes.push_back((self.version, change));
+    }
+
+    fn diff_since(&self, version: u64) -> RoomDiff {
+        let mut diff = RoomDiff {
+            version: self.version,
+            members: self.members.len(),
+            ..Default::default()
+        };
+
+        let oldest = self.changes.front().map_or(self.version, |(v, _)| v - 1);
+
+        if version > self.version || version < oldest {
+            diff.truncated = true;
+            return diff;
+        }
+
+        for (_, change) in self.changes.iter().filter(|(v, _)| *v > version) {
+            match change {
+                RoomChange::Joined(id) => diff.joined.push(*id),
+                RoomChange::Left(id) => {
+                    if let Some(index) = diff.joined.iter().position(|i| i == id) {
+                        diff.joined.remove(index);
+                    } else {
+                        diff.left.push(*id);
+                    }
+                }
+            }
+        }
+
+        diff
+    }
+
+    fn with_context<F: FnOnce(&mut R, Context<R>) -> O, O>(
+        &mut self,
+        connection: &mut Connection,
+        deferred: &mut Vec<Box<dyn FnOnce()>>,
+        errors: &SendErrors,
+        f: F,
+    ) -> Option<O> {
+        let me = self
             .members
             .iter()
-            .map(|(_, v)| (PhantomData, v.clone()))
-            .collect::<Vec<_>>();
+            .position(|member| member.id == connection.id)?;
 
         let cx = Context {
             room: &self.self_ref,
-            sender,
-            members: &todo,
-            members_a: &mut self.members,
-            me: (sender.token(), sender.connection_id()),
+            room_id: self.id,
+            metadata: &self.metadata,
+            connection,
+            members: &mut self.members,
+            me,
+            deferred,
+            errors,
+            middleware: &self.middleware,
+            compression: self.compression.as_ref(),
+            clock: &*self.clock,
+            rng: &mut self.rng,
+        };
+
+        Some(f(&mut self.handler, cx))
+    }
+
+    /// Locks the room and calls `f` with a [Context], then runs the closures that were
+    /// [deferred][Context::defer] during the call once the lock has been released.
+    ///
+    /// `f` isn't called if the client isn't a member of the room anymore because it was
+    /// [drained][RoomRef::drain].
+    fn dispatch<F: FnOnce(&mut R, Context<R>) -> O, O>(
+        room: &Mutex<Self>,
+        connection: &mut Connection,
+        f: F,
+    ) -> Option<O> {
+        let mut deferred = Vec::new();
+        let errors = SendErrors::default();
+        let output = {
+            let mut room = room.lock().unwrap();
+            let output = room.with_context(connection, &mut deferred, &errors, f);
+            room.report_send_errors(&mut deferred, errors);
+            room.enforce_memory_limit();
+            output
         };
 
-        f(&mut self.handler, cx)
+        deferred.into_iter().for_each(|f| f());
+
+        output
     }
 }
 
 impl<R: RoomHandler> Room<R> {
     /// Constructs a new empty [RoomRef]
     ///
-    /// Clients can be moved inside using [`Context::relocate`]:
+    /// Clients can be moved inside by returning a [Relocation] from a callback:
     ///
     /// ```no_run
-    /// // TODO this whole example is broken
-    ///
     /// use ws_hotel::*;
     ///
-    /// let room = AdHoc::new(|mut ctx: Context<String>, mut m: MembersAccess<String>, msg: Message| {
-    ///     println!("message from: {}", m.identity());
-    ///     ctx.broadcast(msg)
-    /// });
+    /// struct Chat;
+    ///
+    /// impl RoomHandler for Chat {
+    ///     type Guest = String;
+    ///
+    ///     fn on_message(&mut self, mut cx: Context<Self>, msg: Message) -> ResultRelocation {
+    ///         let (name, members) = cx.split();
+    ///         println!("message from: {} ({} other members)", name, members.len());
+    ///
+    ///         members.broadcast(msg)?;
+    ///         Ok(None)
+    ///     }
+    /// }
     ///
-    /// ws_hotel::listen(
-    ///     "127.0.0.1:8080",
-    ///     AdHoc::new(|mut ctx: Context<()>, _: MembersAccess<()>, msg: Message| {
-    ///         // Let's assume that the first messager is the client introducing themselves
-    ///         let name = msg.as_text().unwrap().clone();
+    /// struct Lobby(RoomRef<Chat>);
     ///
-    ///         ctx.relocate(&room, name);
-    ///     }),
-    /// );
+    /// impl RoomHandler for Lobby {
+    ///     type Guest = ();
     ///
-    /// room.relocate(room, username);
+    ///     fn on_message(&mut self, _: Context<Self>, msg: Message) -> ResultRelocation {
+    ///         // Let's assume that the first message is the client introducing themselves
+    ///         let name = msg.into_text()?;
+    ///
+    ///         Ok(Some(Relocation::new(&self.0, name)))
+    ///     }
+    /// }
+    ///
+    /// let room = Room::new(Chat);
+    ///
+    /// ws_hotel::listen("127.0.0.1:8080", Lobby(room)).unwrap();
     /// ```
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(handler: R) -> RoomRef<R> {
-        RoomRef(Arc::new_cyclic(|weak| {
+        ROOMS.fetch_add(1, Ordering::Relaxed);
+
+        let id = RoomId::next();
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+
+        let room = Arc::new_cyclic(|weak| {
             Mutex::new(Room {
-                self_ref: RoomRefWeak(weak.clone()),
+                self_ref: RoomRefWeak(weak.clone(), id),
+                id,
+                name: None,
+                metadata: Arc::default(),
                 handler,
                 members: Vec::new(),
+                version: 0,
+                changes: VecDeque::new(),
+                compression: None,
+                empty_since: Some(clock.now()),
+                clock,
+                rng: StdRng::from_entropy(),
+                guest_size: None,
+                memory_limit: None,
+                ticker: None,
+                lifetime: None,
+                shut_down: None,
+                middleware: RefCell::default(),
+                sealed: None,
+                capacity: None,
+                validate: None,
+                idle_ttl: None,
+                max_message_size: None,
+                inactivity: None,
+                keepalive: None,
+                subscribers: Vec::new(),
             })
-        }))
+        });
+
+        RoomRef(room, id)
+    }
+}
+
+/// Number of rooms alive in the process, see [live_rooms]
+static ROOMS: AtomicUsize = AtomicUsize::new(0);
+
+impl<R: RoomHandler> Drop for Room<R> {
+    fn drop(&mut self) {
+        ROOMS.fetch_sub(1, Ordering::Relaxed);
     }
 }
 
+/// Number of rooms alive in the process.
+///
+/// Rooms aren't owned by a server: the same room can be the lobby of several of them, or be
+/// entered by relocation from any hotel. This thus counts every room that wasn't dropped yet,
+/// whichever servers their clients are connected to, including the empty ones.
+pub fn live_rooms() -> usize {
+    ROOMS.load(Ordering::Relaxed)
+}
+
 impl<R: RoomHandler> Debug for Room<R>
 where
     R: Debug,
@@ -198,139 +982,856 @@ where
 {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Room")
+            .field("id", &self.id)
+            .field("name", &self.name)
             .field("handler", &self.handler)
             .field("members", &self.members)
             .finish()
     }
 }
 
-pub struct Relocation(Arc<dyn RoomAny>, Box<dyn Any>);
+pub struct Relocation(Arc<dyn RoomAny + Send + Sync>, Box<dyn Any + Send>);
 
 impl Relocation {
     #[must_use]
-    pub fn new<R: RoomHandler>(room: &RoomRef<R>, identity: R::Guest) -> Self
+    pub fn new<R>(room: &RoomRef<R>, identity: R::Guest) -> Self
     where
-        R: 'static,
-        R::Guest: 'static,
+        R: RoomHandler + Send + 'static,
+        R::Guest: Send + 'static,
     {
         Self(Arc::clone(&room.0) as _, Box::new(identity) as _)
     }
 }
 
+impl Debug for Relocation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Relocation")
+            .field("to", &RoomAddr::of(&self.0))
+            .finish_non_exhaustive()
+    }
+}
+
 pub type ResultRelocation = ws::Result<Option<Relocation>>;
 
-trait RoomAny {
-    fn on_join(&self, sender: &Sender) -> ResultRelocation;
-    fn on_message(&self, sender: &Sender, msg: Message) -> ResultRelocation;
-    fn on_leave(&self, sender: &Sender, code_and_reason: Option<(CloseCode, &str)>);
+/// Whether a client can be relocated into a room, see [`RoomAny::admit`]
+enum Admission {
+    /// It can, with its (normalized) identity
+    Accept(Box<dyn Any + Send>),
 
-    fn broadcast(&self, msg: Message) -> ws::Result<()>;
+    /// It silently stays in its room
+    Refuse,
 
-    fn add(&self, sender: Sender, identity: Box<dyn Any>);
-    fn remove(&self, sender: &Sender);
+    /// It stays in its room, which is told why with [`RoomHandler::on_relocation_refused`]
+    Fail(ws::Error),
+
+    /// It must be relocated elsewhere instead
+    Redirect(Relocation),
+}
+
+trait RoomAny {
+    fn on_request(&self, connection: &mut Connection, request: &Request) -> ws::Result<Response>;
+    fn on_open(&self, connection: &mut Connection, handshake: &Handshake) -> ResultRelocation;
+    fn on_message(&self, connection: &mut Connection, msg: Message) -> ResultRelocation;
+    fn on_ping(&self, connection: &mut Connection, data: &[u8]) -> ResultRelocation;
+    fn on_pong(&self, connection: &mut Connection, data: &[u8]) -> ResultRelocation;
+    fn on_leave(&self, connection: &mut Connection, reason: LeaveReason);
+    fn on_relocate_in(&self, connection: &mut Connection, from: RoomAddr) -> ResultRelocation;
+    fn on_relocate_out(&self, connection: &mut Connection, to: RoomAddr);
+    fn on_relocation_refused(
+        &self,
+        connection: &mut Connection,
+        to: RoomAddr,
+        err: ws::Error,
+    ) -> ResultRelocation;
+    fn on_error(&self, connection: &mut Connection, err: ws::Error);
+    fn on_timeout(&self, connection: &mut Connection, token: Token) -> ResultRelocation;
+    fn on_inactivity(&self, connection: &mut Connection) -> ResultRelocation;
+    fn keepalive(&self, pulse: &Pulse, fallback: Option<Keepalive>) -> Option<Keepalive>;
+    fn on_unresponsive(&self, connection: &Connection) -> Option<Relocation>;
+    fn on_slow_consumer(&self, connection: &mut Connection, backlog: usize);
+
+    fn shutdown(&self, run: u64);
+    /// The current instant according to the [Clock] of the room
+    fn now(&self) -> Instant;
+    fn broadcast(&self, msg: Message);
+
+    fn admit(&self, identity: Box<dyn Any + Send>) -> Admission;
+    fn add(&self, connection: &Connection, identity: Box<dyn Any>);
+    fn remove(&self, id: MemberId);
 }
 
 impl<R: RoomHandler + 'static> RoomAny for Mutex<Room<R>> {
-    fn on_join(&self, sender: &Sender) -> ResultRelocation {
-        self.lock()
-            .unwrap()
-            .with_context(sender, move |h, cx| h.on_join(cx))
+    fn on_request(&self, connection: &mut Connection, request: &Request) -> ws::Result<Response> {
+        Room::dispatch(self, connection, move |h, cx| h.on_request(cx, request))
+            .unwrap_or_else(|| Response::from_request(request))
     }
 
-    fn on_message(&self, sender: &Sender, msg: Message) -> ResultRelocation {
-        self.lock()
-            .unwrap()
-            .with_context(sender, move |h, cx| h.on_message(cx, msg))
+    fn on_open(&self, connection: &mut Connection, handshake: &Handshake) -> ResultRelocation {
+        let r = Room::dispatch(self, connection, move |h, mut cx| {
+            middleware::join(&mut cx)?;
+            h.on_open(cx, handshake)
+        })
+        .unwrap_or(Ok(None))?;
+
+        self.lock().unwrap().emit(RoomEvent::Joined(connection.id));
+        Ok(r)
     }
 
-    fn on_leave(&self, sender: &Sender, code_and_reason: Option<(CloseCode, &str)>) {
+    fn on_message(&self, connection: &mut Connection, msg: Message) -> ResultRelocation {
+        {
+            let mut room = self.lock().unwrap();
+
+            if let Some(max) = room.max_message_size {
+                if msg.len() > max {
+                    return Err(ws::Error::new(ws::ErrorKind::Capacity, "message too large"));
+                }
+            }
+
+            room.touch(connection.id);
+        }
+
+        Room::dispatch(
+            self,
+            connection,
+            move |h, mut cx| match middleware::message(&mut cx, msg)? {
+                Some(Message::Binary(data)) => h.on_binary(cx, data),
+                Some(msg) => h.on_message(cx, msg),
+                None => Ok(None),
+            },
+        )
+        .unwrap_or(Ok(None))
+    }
+
+    fn on_ping(&self, connection: &mut Connection, data: &[u8]) -> ResultRelocation {
+        Room::dispatch(self, connection, move |h, cx| h.on_ping(cx, data)).unwrap_or(Ok(None))
+    }
+
+    fn on_pong(&self, connection: &mut Connection, data: &[u8]) -> ResultRelocation {
+        self.lock().unwrap().touch(connection.id);
+        Room::dispatch(self, connection, move |h, cx| h.on_pong(cx, data)).unwrap_or(Ok(None))
+    }
+
+    fn on_leave(&self, connection: &mut Connection, reason: LeaveReason) {
+        Room::dispatch(self, connection, move |h, mut cx| {
+            middleware::leave(&mut cx, reason);
+            h.on_leave(cx, reason)
+        })
+        .unwrap_or_default();
+
+        let event = RoomEvent::left(connection.id, reason);
+        self.lock().unwrap().emit(event);
+    }
+
+    fn on_relocate_in(&self, connection: &mut Connection, from: RoomAddr) -> ResultRelocation {
+        let r = Room::dispatch(self, connection, move |h, mut cx| {
+            middleware::join(&mut cx)?;
+            h.on_relocate_in(cx, from)
+        });
+
+        let member = connection.id;
         self.lock()
             .unwrap()
-            .with_context(sender, move |h, cx| h.on_leave(cx, code_and_reason))
+            .emit(RoomEvent::RelocatedIn { member, from });
+        r.unwrap_or(Ok(None))
     }
 
-    fn broadcast(&self, msg: Message) -> ws::Result<()> {
+    fn on_relocate_out(&self, connection: &mut Connection, to: RoomAddr) {
+        Room::dispatch(self, connection, move |h, mut cx| {
+            middleware::leave(&mut cx, LeaveReason::Relocated { to });
+            h.on_relocate_out(cx, to)
+        })
+        .unwrap_or_default();
+
+        let member = connection.id;
         self.lock()
             .unwrap()
-            .members
-            .iter()
-            .map(|(_, sender)| sender.send(msg.clone()))
-            .collect()
+            .emit(RoomEvent::RelocatedOut { member, to });
     }
 
-    fn add(&self, sender: Sender, identity: Box<dyn Any>) {
-        let identity = *identity.downcast().unwrap();
-        self.lock().unwrap().members.push((identity, sender));
+    fn on_relocation_refused(
+        &self,
+        connection: &mut Connection,
+        to: RoomAddr,
+        err: ws::Error,
+    ) -> ResultRelocation {
+        Room::dispatch(self, connection, move |h, cx| {
+            h.on_relocation_refused(cx, to, err)
+        })
+        .unwrap_or(Ok(None))
     }
 
-    fn remove(&self, sender: &Sender) {
-        let mut lock = self.lock().unwrap();
+    fn on_error(&self, connection: &mut Connection, err: ws::Error) {
+        Room::dispatch(self, connection, move |h, cx| h.on_error(cx, err)).unwrap_or_default()
+    }
 
-        let index = lock
-            .members
-            .iter()
-            .position(|(_, s)| s == sender)
-            .expect("attempted to remove member, but it wasn't here");
+    fn on_timeout(&self, connection: &mut Connection, token: Token) -> ResultRelocation {
+        Room::dispatch(self, connection, move |h, cx| h.on_timeout(cx, token)).unwrap_or(Ok(None))
+    }
+
+    fn on_inactivity(&self, connection: &mut Connection) -> ResultRelocation {
+        self.lock().unwrap().check_inactivity(connection.id)
+    }
+
+    fn keepalive(&self, pulse: &Pulse, fallback: Option<Keepalive>) -> Option<Keepalive> {
+        self.lock().unwrap().keepalive(pulse, fallback)
+    }
+
+    fn on_unresponsive(&self, connection: &Connection) -> Option<Relocation> {
+        self.lock().unwrap().on_unresponsive(connection)
+    }
+
+    fn on_slow_consumer(&self, connection: &mut Connection, backlog: usize) {
+        Room::dispatch(self, connection, move |h, cx| {
+            h.on_slow_consumer(cx, backlog)
+        })
+        .unwrap_or_default()
+    }
+
+    fn now(&self) -> Instant {
+        self.lock().unwrap().clock.now()
+    }
+
+    fn shutdown(&self, run: u64) {
+        let first = self.lock().unwrap().shut_down.replace(run) != Some(run);
+
+        if first {
+            Room::dispatch_room(self, |h, cx| h.on_shutdown(cx));
+        }
+    }
+
+    /// Broadcasts like [`RoomContext::broadcast`], through the middleware of the room and with
+    /// the failures reported to [`RoomHandler::on_send_error`]
+    fn broadcast(&self, msg: Message) {
+        let _ = Room::dispatch_room(self, |_, cx| cx.broadcast(msg));
+    }
+
+    fn admit(&self, mut identity: Box<dyn Any + Send>) -> Admission {
+        let mut room = self.lock().unwrap();
+
+        let (refusal, reason) = match &room.sealed {
+            Some(sealed) => (Some(sealed), "room is sealed"),
+            None => (room.when_full(), "room is full"),
+        };
+
+        match refusal {
+            None => {}
+            Some(Full::Stay) => return Admission::Refuse,
+            Some(Full::Notify) => {
+                return Admission::Fail(ws::Error::new(ws::ErrorKind::Capacity, reason))
+            }
+            Some(Full::Overflow(overflow)) => {
+                return Admission::Redirect(overflow(*identity.downcast().unwrap()))
+            }
+        }
+
+        let validated = match room.validate {
+            Some(validate) => validate(&mut room.handler, identity.downcast_mut().unwrap()),
+            None => Ok(()),
+        };
+
+        match validated {
+            Ok(()) => Admission::Accept(identity),
+            Err(err) => Admission::Fail(err),
+        }
+    }
+
+    fn add(&self, connection: &Connection, identity: Box<dyn Any>) {
+        let guest = *identity.downcast().unwrap();
+        let id = connection.id;
+
+        let first = {
+            let mut lock = self.lock().unwrap();
+            lock.record(RoomChange::Joined(id));
+
+            let mut member = Member {
+                id,
+                guest,
+                sender: connection.sender.clone(),
+                channels: BTreeSet::new(),
+                last_active: lock.clock.now(),
+                inactivity: None,
+                pulse: Arc::clone(&connection.heartbeat.pulse),
+                mailbox: Arc::clone(&connection.mailbox),
+            };
+            lock.watch_inactivity(&mut member);
+            lock.members.push(member);
+            lock.update_empty_since();
+            lock.members.len() == 1
+        };
 
-        lock.members.swap_remove(index);
+        if first {
+            Room::dispatch_room(self, |h, cx| h.on_first_join(cx));
+        }
+    }
+
+    fn remove(&self, id: MemberId) {
+        let empty = {
+            let mut lock = self.lock().unwrap();
+
+            // The member isn't here anymore if the room was drained
+            let index = match lock.members.iter().position(|member| member.id == id) {
+                Some(index) => index,
+                None => return,
+            };
+
+            lock.members.swap_remove(index);
+            lock.record(RoomChange::Left(id));
+            lock.update_empty_since();
+            lock.members.is_empty()
+        };
+
+        if empty {
+            Room::dispatch_room(self, |h, cx| h.on_room_empty(cx));
+        }
     }
 }
 
 pub struct Context<'a, 'm, R: RoomHandler> {
     room: &'a RoomRefWeak<R>,
-
-    sender: &'a Sender,
-    members: &'a [(PhantomData<R::Guest>, Sender)],
-    members_a: &'m mut [(R::Guest, Sender)],
-    me: (Token, u32),
+    room_id: RoomId,
+    metadata: &'a Metadata,
+
+    connection: &'m mut Connection,
+    members: &'m mut [Member<R::Guest>],
+    /// Index of the current client in `members`
+    me: usize,
+    deferred: &'m mut Vec<Box<dyn FnOnce()>>,
+    errors: &'m SendErrors,
+    middleware: &'a middleware::Stack<R>,
+    compression: Option<&'a Compression>,
+    clock: &'a dyn Clock,
+    rng: &'m mut StdRng,
 }
 
 impl<R: RoomHandler> Context<'_, '_, R> {
+    /// Identifier of the client associated with this [Context]
+    pub fn id(&self) -> MemberId {
+        self.members[self.me].id
+    }
+
     /// Weak reference to the current room
     pub fn room(&self) -> &RoomRefWeak<R> {
         self.room
     }
 
+    /// Identifier of the current room
+    pub fn room_id(&self) -> RoomId {
+        self.room_id
+    }
+
+    /// Metadata the current room was created with
+    pub fn metadata(&self) -> &Metadata {
+        self.metadata
+    }
+
+    /// Strong reference to the current room, that can for instance be stored in a directory of
+    /// rooms or moved to a background task.
+    ///
+    /// Keep in mind that a room isn't dropped as long as a strong reference to it exists.
+    pub fn room_ref(&self) -> RoomRef<R> {
+        self.room
+            .upgrade()
+            .expect("room is alive while one of its callbacks is running")
+    }
+
     /// Returns the identity of the client associated with this [Context]
     pub fn identity(&mut self) -> &mut R::Guest {
-        // TODO memoize this function ? probably requires unsafe code
+        &mut self.members[self.me].guest
+    }
+
+    /// Returns a shared reference to the identity of the client associated with this [Context].
+    ///
+    /// Unlike [`Context::identity`], it doesn't borrow the context mutably, so it can be held
+    /// while calling other methods that only need `&self`, like [`Context::broadcast_with`].
+    pub fn identity_ref(&self) -> &R::Guest {
+        &self.members[self.me].guest
+    }
 
-        let sender = self.me;
+    /// Returns the current instant according to the [Clock] of the room.
+    ///
+    /// Handlers should prefer it to [`Instant::now`](std::time::Instant::now), so they can be
+    /// tested deterministically with [`RoomRef::set_clock`].
+    #[inline]
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
 
-        &mut self
-            .members_a
-            .iter_mut()
-            .find(move |(_, s)| (s.token(), s.connection_id()) == sender)
-            .expect("guest not in room")
-            .0
+    /// Returns the random number generator of the room, that can be seeded with
+    /// [`RoomRef::seed_rng`] to make shuffles and other random decisions reproducible.
+    #[inline]
+    pub fn rng(&mut self) -> &mut StdRng {
+        self.rng
     }
 
     /// Sends a message to the client associated to this [Context], that is, the one who received
     /// the message.
     #[inline]
     pub fn send(&self, msg: impl Into<Message>) -> ws::Result<()> {
-        self.sender.send(msg)
+        self.connection.sender.send(msg)
     }
 
-    /// Sends a message to everyone in the same room
-    pub fn broadcast(&self, msg: impl Into<Message>) -> ws::Result<()> {
+    /// Arms a timer that calls [`RoomHandler::on_timeout`] with `token` after `delay`, in the
+    /// [Context] of this client.
+    ///
+    /// Tokens are chosen freely by the room, and several timers can share the same token. Timers
+    /// are tied to the room they were armed in: they are silently dropped if the client is
+    /// relocated or disconnected before they fire.
+    ///
+    /// The returned handle can [cancel][TimerHandle::cancel] the timer or
+    /// [reschedule][TimerHandle::reschedule] it, e.g. to stop a countdown early.
+    pub fn set_timeout(&mut self, delay: Duration, token: Token) -> ws::Result<TimerHandle> {
+        let room = Some(self.room.addr());
+        self.connection
+            .set_timeout(delay, Action::Timeout(token), room)
+    }
+
+    /// Like [`Context::set_timeout`], but the timer follows the client when it is relocated, and
+    /// [`RoomHandler::on_timeout`] is called by the room the client is in when it fires.
+    ///
+    /// This is meant for timers about the client rather than about the room, like an inactivity
+    /// kick that shouldn't be reset every time the client moves. Every room the client can be
+    /// relocated to must thus know the token. The timer is still dropped if the client disconnects.
+    pub fn set_travelling_timeout(
+        &mut self,
+        delay: Duration,
+        token: Token,
+    ) -> ws::Result<TimerHandle> {
+        self.connection
+            .set_timeout(delay, Action::Timeout(token), None)
+    }
+
+    /// Sends a message to the client associated to this [Context] after `delay`, without going
+    /// through [`RoomHandler::on_timeout`].
+    ///
+    /// Like a timer armed with [`Context::set_timeout`], the message is dropped if the client
+    /// leaves the room before then, and the returned handle can cancel it or delay it further.
+    pub fn send_later(
+        &mut self,
+        msg: impl Into<Message>,
+        delay: Duration,
+    ) -> ws::Result<TimerHandle> {
+        let room = Some(self.room.addr());
+        self.connection
+            .set_timeout(delay, Action::Send(msg.into()), room)
+    }
+
+    /// Sends a message to everyone in the room after `delay`, like [`Context::send_later`].
+    ///
+    /// The message is broadcast from the connection of the client associated to this [Context], so
+    /// it is dropped if that client leaves the room before then. Like with [`Context::broadcast`],
+    /// it goes through the [middleware][Middleware] of the room, and the members it can't be sent
+    /// to are reported to [`RoomHandler::on_send_error`].
+    pub fn broadcast_later(
+        &mut self,
+        msg: impl Into<Message>,
+        delay: Duration,
+    ) -> ws::Result<TimerHandle> {
+        let room = Some(self.room.addr());
+        self.connection
+            .set_timeout(delay, Action::Broadcast(msg.into()), room)
+    }
+
+    /// Arms a timer on the member of the room identified by `id`, that calls
+    /// [`RoomHandler::on_timeout`] with `token` in the [Context] of that member after `delay`, e.g.
+    /// to give each player of a game a clock of their own.
+    ///
+    /// Like with [`Context::set_timeout`], the timer is dropped if the member leaves the room
+    /// before it fires. Fails if there is no such member in the room.
+    pub fn set_member_timeout(
+        &self,
+        id: MemberId,
+        delay: Duration,
+        token: Token,
+    ) -> ws::Result<TimerHandle> {
+        self.member_or_err(id)?
+            .set_timeout(delay, Action::Timeout(token), Some(self.room.addr()))
+    }
+
+    /// Closes the connection of the member of the room identified by `member`, which may be the
+    /// current client, but only after every message that was previously sent to it has been
+    /// flushed.
+    ///
+    /// Returning an [`Err`] from a callback makes `ws` close the connection right away, which races
+    /// with messages that are still queued (e.g. a final "you were kicked because…" payload). The
+    /// close frame sent by this method goes through the same queue as [`Context::send`], so it is
+    /// always written after them. Fails if there is no such member in the room.
+    pub fn close_after_flush(
+        &self,
+        member: MemberId,
+        code: CloseCode,
+        reason: impl Into<Cow<'static, str>>,
+    ) -> ws::Result<()> {
+        self.member_or_err(member)?.kick(code, reason)
+    }
+
+    /// Registers a closure that will run exactly once, when the connection of the client
+    /// associated to this [Context] is closed.
+    ///
+    /// Unlike [`RoomHandler::on_leave`], which is also called when the client is relocated, it
+    /// runs regardless of the room the client is in at that time. This makes it suitable to
+    /// release external resources, like presence records.
+    pub fn on_disconnect(&mut self, f: impl FnOnce() + 'static) {
+        self.connection.on_disconnect(Box::new(f));
+    }
+
+    /// Values attached to the connection of the client associated to this [Context], that are
+    /// kept when it is relocated
+    pub fn extensions(&self) -> &Extensions {
+        &self.connection.extensions
+    }
+
+    /// The address of the client associated to this [Context], or the address it was forwarded for
+    /// by a [trusted proxy][Builder::trusted_proxy]. It is `None` before the handshake is complete,
+    /// i.e. in [`RoomHandler::on_request`].
+    pub fn remote_addr(&self) -> Option<std::net::IpAddr> {
+        self.connection.remote_addr
+    }
+
+    /// The subprotocol selected for the connection of the client associated to this [Context]
+    /// during its handshake, see [`Builder::protocol`]
+    pub fn protocol(&self) -> Option<&str> {
+        self.connection.protocol.as_deref()
+    }
+
+    /// The state of the [Hotel] the client associated to this [Context] connected to, if it is of
+    /// type `T`, see [`Hotel::with_state`]
+    pub fn state<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        let state = self.connection.state.as_ref()?;
+        Arc::clone(&state.0).downcast().ok()
+    }
+
+    /// Mutable access to the values attached to the connection of the client associated to this
+    /// [Context], see [`Context::extensions`]
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.connection.extensions
+    }
+
+    /// Queues a closure that will run once the handler has returned and the room has been unlocked.
+    ///
+    /// The room is locked for the whole duration of a [RoomHandler] callback, so accessing it
+    /// again (e.g. with [`RoomRef::with`]) from inside the callback deadlocks. Deferred closures
+    /// don't have this problem and are thus the right place for cross-room work. They run in the
+    /// order they were queued.
+    pub fn defer(&mut self, f: impl FnOnce() + 'static) {
+        self.deferred.push(Box::new(f));
+    }
+
+    /// Sends a message to everyone in another room.
+    ///
+    /// Locking another room from a callback can deadlock, for instance if that room is the current
+    /// one, or if it is itself trying to send a message to this room. The message is thus
+    /// [deferred][Context::defer] until the current room is unlocked, and then broadcast like with
+    /// [`RoomRef::broadcast`]: the members of `room` it can't be sent to are reported to its
+    /// [`RoomHandler::on_send_error`]. It never fails, see [`Context::broadcast`].
+    pub fn send_room<O>(&mut self, room: &RoomRef<O>, msg: impl Into<Message>) -> ws::Result<()>
+    where
+        O: RoomHandler + 'static,
+    {
         let msg = msg.into();
+        let room: Arc<dyn RoomAny> = Arc::clone(&room.0) as _;
+        self.defer(move || room.broadcast(msg));
+
+        Ok(())
+    }
 
+    /// Sends a message to everyone in the same room.
+    ///
+    /// The message is sent to every member even if it can't be sent to some of them, and the
+    /// failures are reported to [`RoomHandler::on_send_error`] after the callback has returned.
+    /// It thus never fails, and only returns a [Result] so that it can be used like
+    /// [`Context::send`].
+    pub fn broadcast(&self, msg: impl Into<Message>) -> ws::Result<()> {
+        if let Some(msg) = self.middleware.layer_broadcast(msg.into()) {
+            self.send_all(msg);
+        }
+
+        Ok(())
+    }
+
+    fn send_all(&self, msg: Message) {
         self.members
             .iter()
-            .map(|(_, sender)| sender.send(msg.clone()))
-            .collect()
+            .for_each(|member| self.errors.send(member, msg.clone()));
+    }
+
+    /// Like [`Context::send`], but the message is compressed if it is large enough and the room
+    /// has [compression enabled][RoomRef::set_compression].
+    pub fn send_compressible(&self, msg: impl Into<Message>) -> ws::Result<()> {
+        self.send(self.compress(msg.into()))
+    }
+
+    /// Like [`Context::broadcast`], but the message is compressed (once) if it is large enough and
+    /// the room has [compression enabled][RoomRef::set_compression].
+    pub fn broadcast_compressible(&self, msg: impl Into<Message>) -> ws::Result<()> {
+        if let Some(msg) = self.middleware.layer_broadcast(msg.into()) {
+            self.send_all(self.compress(msg));
+        }
+
+        Ok(())
+    }
+
+    fn compress(&self, msg: Message) -> Message {
+        match self.compression {
+            Some(compression) => compression.apply(msg),
+            None => msg,
+        }
+    }
+
+    /// Finds another member of the room whose identity matches `predicate`, so it can be sent
+    /// messages, kicked or relocated.
+    ///
+    /// The client associated with this [Context] is never returned.
+    pub fn find_member<F: FnMut(&R::Guest) -> bool>(
+        &self,
+        mut predicate: F,
+    ) -> Option<MemberHandle> {
+        self.members
+            .iter()
+            .enumerate()
+            .find(|(index, member)| *index != self.me && predicate(&member.guest))
+            .map(|(_, member)| MemberHandle::of(member))
+    }
+
+    /// Returns a handle to the member of the room identified by `id`, if it is still in the room
+    pub fn member(&self, id: MemberId) -> Option<MemberHandle> {
+        self.members
+            .iter()
+            .find(|member| member.id == id)
+            .map(MemberHandle::of)
+    }
+
+    /// Returns the last round-trip time measured between the server and `member`, if it is in
+    /// the room. It is only measured with [`Builder::keepalive`] or
+    /// [`RoomRef::set_keepalive`], once the member answered a ping.
+    pub fn rtt(&self, member: &MemberHandle) -> Option<Duration> {
+        self.members
+            .iter()
+            .find(|m| m.id == member.id())
+            .and_then(|m| m.pulse.rtt())
+    }
+
+    /// Iterates over the members of the room (including the current one) and their identity
+    pub fn members(&self) -> impl Iterator<Item = (MemberId, &R::Guest)> {
+        self.members.iter().map(|member| (member.id, &member.guest))
+    }
+
+    /// Sends a message to the member of the room identified by `id`.
+    ///
+    /// Fails if there is no such member in the room.
+    pub fn send_to(&self, id: MemberId, msg: impl Into<Message>) -> ws::Result<()> {
+        self.member_or_err(id)?.send(msg)
+    }
+
+    /// Kicks the member of the room identified by `id`, see [`MemberHandle::kick`].
+    ///
+    /// Fails if there is no such member in the room.
+    pub fn kick(
+        &self,
+        id: MemberId,
+        code: CloseCode,
+        reason: impl Into<Cow<'static, str>>,
+    ) -> ws::Result<()> {
+        self.member_or_err(id)?.kick(code, reason)
+    }
+
+    /// Moves the member of the room identified by `id` to another room, see
+    /// [`MemberHandle::relocate`]. The current client must be relocated by returning a
+    /// [Relocation] from the callback instead.
+    ///
+    /// Fails if there is no such member in the room.
+    pub fn relocate_other(&self, id: MemberId, relocation: Relocation) -> ws::Result<()> {
+        self.member_or_err(id)?.relocate(relocation)
+    }
+
+    /// Moves the client associated to this [Context] to another room after `delay`, e.g. to the
+    /// results room once a game is over.
+    ///
+    /// Like a timer armed with [`Context::set_timeout`], the relocation is dropped if the client
+    /// leaves the room or disconnects before then, and the returned handle can cancel it or delay
+    /// it further. It can't be rescheduled once it has happened.
+    pub fn relocate_later(
+        &mut self,
+        relocation: Relocation,
+        delay: Duration,
+    ) -> ws::Result<TimerHandle> {
+        let action = Action::Relocate(Mutex::new(Some(relocation)));
+        let room = Some(self.room.addr());
+        self.connection.set_timeout(delay, action, room)
     }
 
-    /// Sends a message to everyone in the same room by calling a closure for each member
+    /// Moves another member of the room to another room after `delay`, see
+    /// [`RoomContext::relocate_later`].
+    ///
+    /// Fails if there is no such member in the room.
+    pub fn relocate_other_later(
+        &self,
+        id: MemberId,
+        relocation: Relocation,
+        delay: Duration,
+    ) -> ws::Result<TimerHandle> {
+        let action = Action::Relocate(Mutex::new(Some(relocation)));
+        self.member_or_err(id)?
+            .set_timeout(delay, action, Some(self.room.addr()))
+    }
+
+    fn member_or_err(&self, id: MemberId) -> ws::Result<MemberHandle> {
+        self.member(id).ok_or_else(|| member::not_found(id))
+    }
+
+    /// Splits the context into the identity of the current client and a view over the other
+    /// members of the room, so that the former can be mutated while the latter is read.
+    ///
+    /// ```
+    /// # use ws_hotel::*;
+    /// struct Scores;
+    ///
+    /// impl RoomHandler for Scores {
+    ///     type Guest = u32;
+    ///
+    ///     fn on_message(&mut self, mut cx: Context<Self>, _: Message) -> ResultRelocation {
+    ///         let (score, others) = cx.split();
+    ///         *score += 1;
+    ///
+    ///         let best = others.iter().map(|(_, score)| *score).max().unwrap_or_default();
+    ///         others.broadcast(format!("new score: {} (best of the others: {})", score, best))?;
+    ///
+    ///         Ok(None)
+    ///     }
+    /// }
+    /// ```
+    pub fn split(&mut self) -> (&mut R::Guest, MembersAccess<'_, R::Guest>) {
+        let (before, rest) = self.members.split_at_mut(self.me);
+        let (me, after) = rest.split_first_mut().expect("guest not in room");
+
+        let access = MembersAccess {
+            id: me.id,
+            sender: &me.sender,
+            before,
+            after,
+            errors: self.errors,
+            middleware: self.middleware,
+        };
+
+        (&mut me.guest, access)
+    }
+
+    /// Sends a message to everyone in the same room by calling a closure for each member. Failures
+    /// are handled like with [`Context::broadcast`].
     pub fn broadcast_with<F: FnMut(&R::Guest) -> M, M: Into<Message>>(
         &self,
         mut f: F,
     ) -> ws::Result<()> {
-        self.members_a
-            .iter()
-            .map(|(identity, sender)| sender.send(f(identity)))
-            .collect()
+        for member in self.members.iter() {
+            if let Some(msg) = self.middleware.layer_broadcast(f(&member.guest).into()) {
+                self.errors.send(member, msg);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a, R: RoomHandler> Context<'a, '_, R> {
+    /// Borrows this context for a shorter time, so it can be passed to a callback and used again
+    /// once it has returned
+    pub(crate) fn reborrow(&mut self) -> Context<'a, '_, R> {
+        Context {
+            room: self.room,
+            room_id: self.room_id,
+            metadata: self.metadata,
+            connection: self.connection,
+            members: self.members,
+            me: self.me,
+            deferred: self.deferred,
+            errors: self.errors,
+            middleware: self.middleware,
+            compression: self.compression,
+            clock: self.clock,
+            rng: self.rng,
+        }
+    }
+}
+
+/// The members of a room other than the current client, obtained with [`Context::split`].
+pub struct MembersAccess<'a, G> {
+    id: MemberId,
+    sender: &'a Sender,
+    before: &'a [Member<G>],
+    after: &'a [Member<G>],
+    errors: &'a SendErrors,
+    middleware: &'a dyn Broadcasts,
+}
+
+impl<G> MembersAccess<'_, G> {
+    /// Number of other members in the room
+    pub fn len(&self) -> usize {
+        self.before.len() + self.after.len()
+    }
+
+    /// Whether the current client is alone in the room
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Iterates over the other members of the room and their identity
+    pub fn iter(&self) -> impl Iterator<Item = (MemberId, &G)> {
+        self.others().map(|member| (member.id, &member.guest))
+    }
+
+    /// Returns the identity of another member of the room
+    pub fn get(&self, id: MemberId) -> Option<&G> {
+        self.iter()
+            .find(|(member, _)| *member == id)
+            .map(|(_, guest)| guest)
+    }
+
+    /// Finds another member of the room whose identity matches `predicate`, see
+    /// [`Context::find_member`]
+    pub fn find_member<F: FnMut(&G) -> bool>(&self, mut predicate: F) -> Option<MemberHandle> {
+        self.others()
+            .find(|member| predicate(&member.guest))
+            .map(MemberHandle::of)
+    }
+
+    /// Sends a message to everyone in the room, including the current client. Failures are
+    /// handled like with [`Context::broadcast`].
+    pub fn broadcast(&self, msg: impl Into<Message>) -> ws::Result<()> {
+        let msg = match self.middleware.layer_broadcast(msg.into()) {
+            Some(msg) => msg,
+            None => return Ok(()),
+        };
+
+        if let Err(err) = self.sender.send(msg.clone()) {
+            self.errors.record(self.id, err);
+        }
+        self.send_others(msg);
+
+        Ok(())
+    }
+
+    /// Sends a message to everyone in the room but the current client. Failures are handled like
+    /// with [`Context::broadcast`].
+    pub fn broadcast_others(&self, msg: impl Into<Message>) -> ws::Result<()> {
+        if let Some(msg) = self.middleware.layer_broadcast(msg.into()) {
+            self.send_others(msg);
+        }
+
+        Ok(())
+    }
+
+    fn send_others(&self, msg: Message) {
+        self.others()
+            .for_each(|member| self.errors.send(member, msg.clone()));
+    }
+
+    fn others(&self) -> impl Iterator<Item = &Member<G>> {
+        self.before.iter().chain(self.after)
+    }
+}
+
+impl<G: Debug> Debug for MembersAccess<'_, G> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
     }
 }
 
@@ -339,62 +1840,304 @@ where
     R::Guest: Debug,
 {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        // TODO memoize ? move into a non-mut function ? idk
-
-        let sender = self.me;
-
-        let identity = &self
-            .members_a
-            .iter()
-            .find(move |(_, s)| (s.token(), s.connection_id()) == sender)
-            .expect("guest not in room")
-            .0;
-
         f.debug_struct("Context")
-            .field("sender", &self.sender)
-            .field("[identity]", identity)
+            .field("sender", &self.connection.sender)
+            .field("[identity]", self.identity_ref())
             .finish_non_exhaustive()
     }
 }
 
 struct Handler {
-    sender: Sender,
+    connection: Connection,
     room: Arc<dyn RoomAny>,
+
+    /// Whether the client is a member of `room`. It stops being one when it is rejected by
+    /// [`RoomHandler::on_open`], when it disconnects or when the server shuts down. Connections
+    /// refused because the server [stopped accepting][ServerHandle::stop_accepting] new ones or
+    /// reached its [connection limit][Builder::connection_limit] never are, and the ones entering
+    /// through [`Entrance::with_guest`] only become one after their handshake.
+    in_room: bool,
+
+    /// Builds the identity of the client once its handshake is known, if it only enters `room`
+    /// then, see [`Entrance::with_guest`]
+    pending: Option<Rc<RefCell<entrance::GuestFn>>>,
+
+    /// Picks `room` once the request of the client is known, see [Router]
+    routes: Option<Rc<router::Routes>>,
+
+    /// The IP the connection is counted for, see [`Builder::connections_per_ip`]
+    ip: Option<std::net::IpAddr>,
+
+    server: Arc<server::ServerState>,
 }
 
 impl Handler {
+    /// Makes the client enter `room` (now, or once its handshake is known)
+    fn arrive(&mut self, arrival: entrance::Arrival) {
+        match arrival {
+            entrance::Arrival::Now(identity) => {
+                let connection = &self.connection;
+                self.room.add(connection, identity);
+                self.in_room = true;
+            }
+            entrance::Arrival::OnOpen(guest) => self.pending = Some(guest),
+            entrance::Arrival::OnRequest(routes) => self.routes = Some(routes),
+        }
+    }
+
     pub fn relocate(&mut self, mut r: Option<Relocation>) -> ws::Result<()> {
-        let sender = &self.sender;
+        let connection = &mut self.connection;
+        let mut moved = false;
 
         while let Some(Relocation(room, identity)) = r.take() {
-            self.room.on_leave(sender, None);
-            self.room.remove(sender);
+            let from = RoomAddr::of(&self.room);
+            let to = RoomAddr::of(&room);
+
+            let identity = match room.admit(identity) {
+                Admission::Accept(identity) => identity,
+                Admission::Refuse => break,
+                Admission::Fail(err) => {
+                    r = self.room.on_relocation_refused(connection, to, err)?;
+                    continue;
+                }
+                Admission::Redirect(relocation) => {
+                    r = Some(relocation);
+                    continue;
+                }
+            };
+
+            self.room.on_relocate_out(connection, to);
+            self.room.remove(connection.id);
             self.room = room;
+            connection.clear_room_timers();
 
-            self.room.add(sender.clone(), identity);
-            r = self.room.on_join(sender)?;
+            self.room.add(connection, identity);
+            moved = true;
+            r = self.room.on_relocate_in(connection, from)?;
         }
 
+        if moved {
+            self.arm_keepalive()?;
+        }
         Ok(())
     }
 }
 
 impl ws::Handler for Handler {
-    fn on_open(&mut self, _shake: Handshake) -> ws::Result<()> {
-        // TODO let user build their `Guest` from the handshake
+    fn on_request(&mut self, request: &Request) -> ws::Result<Response> {
+        if let Some(response) = self.server.http_response(request) {
+            if self.in_room {
+                self.room.remove(self.connection.id);
+            }
+            self.in_room = false;
+
+            return Ok(response);
+        }
 
-        Ok(())
+        let routes = self.routes.take();
+        if let Some(routes) = &routes {
+            match routes.resolve(request.resource()) {
+                Some((room, arrival)) => {
+                    self.room = room;
+                    self.arrive(arrival);
+                }
+                None => return Ok(server::not_found()),
+            }
+        }
+
+        let mut response = if self.pending.is_some() {
+            Response::from_request(request)?
+        } else if self.in_room {
+            self.room.on_request(&mut self.connection, request)?
+        } else {
+            return self.server.rejection.response(request);
+        };
+
+        let protocols = routes
+            .as_ref()
+            .map_or(&[][..], |routes| routes.protocols(request.resource()));
+        server::negotiate(
+            request,
+            &mut response,
+            protocols.iter().chain(&self.server.protocols),
+        )?;
+        self.connection.protocol = response.protocol()?.map(ToOwned::to_owned);
+
+        Ok(response)
+    }
+
+    fn on_open(&mut self, shake: Handshake) -> ws::Result<()> {
+        if !self.in_room && self.pending.is_none() {
+            return self.server.rejection.close(&self.connection.sender);
+        }
+
+        self.connection.remote_addr = self.server.proxies.remote_addr(&shake);
+
+        if let Some(per_ip) = &self.server.per_ip {
+            match per_ip.enter(&shake) {
+                Ok(ip) => self.ip = ip,
+                Err(_) => {
+                    if self.in_room {
+                        self.room.remove(self.connection.id);
+                    }
+                    self.in_room = false;
+                    self.pending = None;
+
+                    let reason = "too many connections";
+                    return self
+                        .connection
+                        .sender
+                        .close_with_reason(CloseCode::Policy, reason);
+                }
+            }
+        }
+
+        if let Some(guest) = self.pending.take() {
+            let identity = (guest.borrow_mut())(&shake)?;
+            let connection = &self.connection;
+            self.room.add(connection, identity);
+            self.in_room = true;
+        }
+
+        match self.room.on_open(&mut self.connection, &shake) {
+            Ok(r) => {
+                self.connection.heartbeat.open = true;
+                self.relocate(r)?;
+                self.arm_keepalive()
+            }
+            Err(err) => {
+                self.room.remove(self.connection.id);
+                self.in_room = false;
+                Err(err)
+            }
+        }
     }
 
     fn on_message(&mut self, msg: Message) -> ws::Result<()> {
         self.room
-            .on_message(&self.sender, msg)
+            .on_message(&mut self.connection, msg)
             .and_then(|r| self.relocate(r))
     }
 
+    fn on_frame(&mut self, frame: ws::Frame) -> ws::Result<Option<ws::Frame>> {
+        // Same check as the default implementation, which can't be called from here
+        if frame.has_rsv1() || frame.has_rsv2() || frame.has_rsv3() {
+            return Err(ws::Error::new(
+                ws::ErrorKind::Protocol,
+                "Encountered frame with reserved bits set.",
+            ));
+        }
+
+        // `ws` still answers pings by itself once the frame is passed on
+        let r = match frame.opcode() {
+            ws::OpCode::Ping => self.room.on_ping(&mut self.connection, frame.payload())?,
+            ws::OpCode::Pong => {
+                let now = self.room.now();
+                self.connection.heartbeat.pong(frame.payload(), now);
+                self.read_until(frame.payload())?;
+                self.room.on_pong(&mut self.connection, frame.payload())?
+            }
+            _ => None,
+        };
+        self.relocate(r)?;
+
+        Ok(Some(frame))
+    }
+
+    fn on_send_frame(&mut self, frame: ws::Frame) -> ws::Result<Option<ws::Frame>> {
+        self.queue_frame(frame)
+    }
+
+    fn on_error(&mut self, err: ws::Error) {
+        if self.in_room {
+            self.room.on_error(&mut self.connection, err);
+        }
+    }
+
+    fn on_shutdown(&mut self) {
+        if !self.in_room {
+            return;
+        }
+
+        self.connection.mailbox.take_relocation();
+
+        self.room.shutdown(self.server.run);
+        self.room
+            .on_leave(&mut self.connection, LeaveReason::ServerShutdown);
+        self.room.remove(self.connection.id);
+        self.in_room = false;
+    }
+
     fn on_close(&mut self, code: CloseCode, reason: &str) {
-        self.room.on_leave(&self.sender, Some((code, reason)));
-        self.room.remove(&self.sender);
+        if self.server.is_shutting_down() {
+            return self.on_shutdown();
+        }
+
+        if !self.in_room {
+            return;
+        }
+
+        self.connection.mailbox.take_relocation();
+
+        let reason = match self.connection.mailbox.is_kicked() {
+            true => LeaveReason::Kicked,
+            false => LeaveReason::Disconnected { code, reason },
+        };
+
+        self.room.on_leave(&mut self.connection, reason);
+        self.room.remove(self.connection.id);
+        self.in_room = false;
+    }
+
+    fn on_timeout(&mut self, event: Token) -> ws::Result<()> {
+        match event {
+            member::RELOCATE => {
+                let r = self.connection.mailbox.take_relocation();
+                self.relocate(r)
+            }
+            keepalive::PING => self.beat(),
+            slow::EVICT => self.evict(),
+            event => match self.connection.take_timer(event) {
+                Some(timer) if self.in_room && timer.fires_in(RoomAddr::of(&self.room)) => {
+                    match timer.action() {
+                        Action::Timeout(token) => self
+                            .room
+                            .on_timeout(&mut self.connection, *token)
+                            .and_then(|r| self.relocate(r)),
+                        Action::Send(msg) => self.connection.sender.send(msg.clone()),
+                        Action::Broadcast(msg) => {
+                            self.room.broadcast(msg.clone());
+                            Ok(())
+                        }
+                        Action::Relocate(relocation) => {
+                            let r = relocation.lock().unwrap().take();
+                            self.relocate(r)
+                        }
+                        Action::Inactivity => self
+                            .room
+                            .on_inactivity(&mut self.connection)
+                            .and_then(|r| self.relocate(r)),
+                    }
+                }
+                _ => Ok(()),
+            },
+        }
+    }
+}
+
+/// `ws` doesn't call [`on_close`][ws::Handler::on_close] for connections whose handshake failed or
+/// was refused, so they are removed from their lobby when their handler is dropped instead.
+impl Drop for Handler {
+    fn drop(&mut self) {
+        if self.in_room {
+            self.room.remove(self.connection.id);
+        }
+
+        if let (Some(ip), Some(per_ip)) = (self.ip, &self.server.per_ip) {
+            per_ip.leave(ip);
+        }
+
+        self.server.disconnected();
     }
 }
 
@@ -409,7 +2152,7 @@ pub trait RoomHandler: Sized {
     /// Arbitrary piece of data that can be used to store room-kind-specific data for each member of
     /// the room.
     ///
-    /// When moving someone into a new room, the caller of [`Context::relocate`] must pass an
+    /// When moving someone into a new room, the callback returning the [Relocation] must pass an
     /// initial value. Whenever a message is received, the [`RoomHandler`] implementation will
     /// receive a mutable reference to this same value.
     ///
@@ -420,13 +2163,167 @@ pub trait RoomHandler: Sized {
     /// [Guest]: RoomHandler::Guest
     type Guest;
 
+    /// Called with the HTTP request of a client that is connecting to this room because it is the
+    /// lobby, before the WebSocket handshake completes.
+    ///
+    /// The returned response can be customized, for instance to add headers or
+    /// [select a subprotocol][Response::set_protocol]. Giving it a status other than `101`
+    /// refuses the connection: the client is then silently removed from the room, and
+    /// [`on_open`][RoomHandler::on_open] is never called.
+    fn on_request(&mut self, _cx: Context<Self>, request: &Request) -> ws::Result<Response> {
+        Response::from_request(request)
+    }
+
+    /// Called when a client connects and is put in this room because it is the lobby, instead of
+    /// [`on_join`][RoomHandler::on_join].
+    ///
+    /// The client's identity can be derived from the handshake (URL path, cookies, headers…)
+    /// through [`Context::identity`], starting from its default value. Returning an error rejects
+    /// the client: it is removed from the room without [`on_leave`][RoomHandler::on_leave] being
+    /// called, and its connection is closed.
+    fn on_open(&mut self, _cx: Context<Self>, _handshake: &Handshake) -> ResultRelocation {
+        Ok(None)
+    }
+
     fn on_join(&mut self, _cx: Context<Self>) -> ResultRelocation {
         Ok(None)
     }
 
     fn on_message(&mut self, cx: Context<Self>, msg: Message) -> ResultRelocation;
 
-    fn on_leave(&mut self, _cx: Context<Self>, _code_and_reason: Option<(CloseCode, &str)>) {}
+    /// Called when a member sends a binary message.
+    ///
+    /// Forwards it to [`on_message`][RoomHandler::on_message] as a [`Message::Binary`] by default,
+    /// so only rooms speaking a binary protocol need to override it.
+    fn on_binary(&mut self, cx: Context<Self>, data: Vec<u8>) -> ResultRelocation {
+        self.on_message(cx, Message::Binary(data))
+    }
+
+    /// Called when a member sends a ping, with its payload. The pong is sent back automatically.
+    fn on_ping(&mut self, _cx: Context<Self>, _data: &[u8]) -> ResultRelocation {
+        Ok(None)
+    }
+
+    /// Called when a member sends a pong, usually in response to a ping sent by the server, with
+    /// its payload. It can be used to measure latency or to implement custom liveness checks.
+    fn on_pong(&mut self, _cx: Context<Self>, _data: &[u8]) -> ResultRelocation {
+        Ok(None)
+    }
+
+    fn on_leave(&mut self, _cx: Context<Self>, _reason: LeaveReason) {}
+
+    /// Called when a member arrives in this room because it was relocated from the room at
+    /// `from` (which may be this same room).
+    ///
+    /// Calls [`on_join`][RoomHandler::on_join] by default, so rooms that don't care where their
+    /// members come from only need to implement the latter.
+    fn on_relocate_in(&mut self, cx: Context<Self>, _from: RoomAddr) -> ResultRelocation {
+        self.on_join(cx)
+    }
+
+    /// Called when a member leaves this room because it is relocated to the room at `to` (which
+    /// may be this same room).
+    ///
+    /// Calls [`on_leave`][RoomHandler::on_leave] with [`LeaveReason::Relocated`] by default.
+    fn on_relocate_out(&mut self, cx: Context<Self>, to: RoomAddr) {
+        self.on_leave(cx, LeaveReason::Relocated { to })
+    }
+
+    /// Called when a member enters the room while it was empty, before that member's
+    /// [`on_join`][RoomHandler::on_join] (or equivalent) is called.
+    ///
+    /// It is the right place to lazily start work that is only needed while the room is in use,
+    /// like a [tick interval][RoomRef::set_tick_interval].
+    fn on_first_join(&mut self, _cx: RoomContext<Self>) {}
+
+    /// Called when the last member of the room left, after its
+    /// [`on_leave`][RoomHandler::on_leave] (or equivalent) was called.
+    ///
+    /// It is the right place to persist state or to stop work started by
+    /// [`on_first_join`][RoomHandler::on_first_join].
+    fn on_room_empty(&mut self, _cx: RoomContext<Self>) {}
+
+    /// Called once when the server shuts down, before any member of the room is removed with
+    /// [`LeaveReason::ServerShutdown`], so the room can flush its state or say goodbye.
+    ///
+    /// Rooms that have no members at that point are only notified if they are the lobby of a
+    /// [Hotel] or one of its [Rooms], once it has stopped. Rooms are notified again each time a
+    /// server they are part of is restarted and shut down.
+    fn on_shutdown(&mut self, _cx: RoomContext<Self>) {}
+
+    /// Called for every message that couldn't be sent to a member during a broadcast made from
+    /// another callback, once that callback has returned, and for every member that couldn't be
+    /// removed when the [lifetime][RoomRef::set_lifetime] of the room is over.
+    ///
+    /// It can for instance kick members whose connection is dead. The failures of the broadcasts
+    /// made from this callback itself aren't reported. Logs the error as a warning by default.
+    fn on_send_error(&mut self, _cx: RoomContext<Self>, member: MemberId, err: ws::Error) {
+        log_send_error(member, &err);
+    }
+
+    /// Called on the new handler of the room when it replaces `previous`, with
+    /// [`RoomRef::replace_handler`] or [`RoomRef::map_handler`]. State can be moved out of
+    /// `previous`, which is then given back to the caller.
+    fn on_replace(&mut self, _cx: RoomContext<Self>, _previous: &mut Self) {}
+
+    /// Called periodically once an interval has been set with [`RoomRef::set_tick_interval`],
+    /// from a background thread.
+    fn on_tick(&mut self, _cx: RoomContext<Self>) {}
+
+    /// Called when the [lifetime][RoomRef::set_lifetime] of the room is over, right before its
+    /// members are removed, e.g. to broadcast the results of a match.
+    fn on_expire(&mut self, _cx: RoomContext<Self>) {}
+
+    /// Called when a member has had more bytes queued than it read for too long, with the size of
+    /// its backlog, see [`Builder::slow_consumers`]. Its messages are then dropped or its
+    /// connection closed, depending on the [SlowConsumer] policy.
+    fn on_slow_consumer(&mut self, _cx: Context<Self>, _backlog: usize) {}
+
+    /// Called when a timer armed with [`Context::set_timeout`] fires, with the [Context] of the
+    /// member it was armed on and the token it was given. Timers armed with
+    /// [`Context::set_travelling_timeout`] may have been armed by another room.
+    fn on_timeout(&mut self, _cx: Context<Self>, _token: Token) -> ResultRelocation {
+        Ok(None)
+    }
+
+    /// Called when an error occurs on the connection of a member, including errors returned by the
+    /// other callbacks. The connection is usually closed right after, and
+    /// [`on_leave`][RoomHandler::on_leave] called.
+    ///
+    /// Like `ws`, logs every error except connection resets as a warning (see the [`log`] crate) by
+    /// default.
+    fn on_error(&mut self, cx: Context<Self>, err: ws::Error) {
+        log_error(cx.id(), &err);
+    }
+
+    /// Called when a member of this room couldn't be relocated to the room at `to`, because it was
+    /// [full][RoomRef::set_capacity] and configured with [`WhenFull::Notify`], or because it
+    /// [refused its identity][ValidateGuest]. The member stays in this room, and can for instance
+    /// be told to try again later.
+    fn on_relocation_refused(
+        &mut self,
+        _cx: Context<Self>,
+        _to: RoomAddr,
+        _err: ws::Error,
+    ) -> ResultRelocation {
+        Ok(None)
+    }
+}
+
+/// Default of [`RoomHandler::on_send_error`]
+pub(crate) fn log_send_error(member: MemberId, err: &ws::Error) {
+    log::warn!("failed to send a message to {}: {}", member, err);
+}
+
+/// Default of [`RoomHandler::on_error`]
+pub(crate) fn log_error(member: MemberId, err: &ws::Error) {
+    if let ws::ErrorKind::Io(io) = &err.kind {
+        if io.kind() == std::io::ErrorKind::ConnectionReset {
+            return;
+        }
+    }
+
+    log::warn!("{}: {}", member, err);
 }
 
 /// A simple [RoomHandler] that wraps a function or closure that will be called when receiving a
@@ -464,28 +2361,177 @@ impl<Guest, F: FnMut(Context<Self>, Message) -> ResultRelocation> RoomHandler fo
 /// Starts a WebSocket hotel given an address and a default room.
 /// This function blocks indefinitely.
 ///
-/// The default room is where clients will be put when connecting the server. Its associated
-/// [`RoomHandler::Guest`] type must implement [`Default`], so it can be built implicitly.
-pub fn listen<A, I, R>(addr: A, lobby: I)
+/// The default room is where clients will be put when connecting the server. It is given as an
+/// [Entrance], or as a room whose [`RoomHandler::Guest`] type implements [`Default`], so the
+/// identity of clients can be built implicitly.
+///
+/// # Errors
+///
+/// Fails if the server can't be started, for instance because the address is already in use.
+pub fn listen<A, I, R>(addr: A, lobby: I) -> ws::Result<()>
+where
+    A: ToSocketAddrs + std::fmt::Debug,
+    I: Into<Entrance<R>>,
+    R: RoomHandler + 'static,
+    R::Guest: 'static,
+{
+    Builder::new().listen(addr, lobby)
+}
+
+/// Like [`listen`], but `on_start` is called with a [ServerHandle] once the server is listening,
+/// before this function starts blocking. The handle can be moved to another thread to stop the
+/// hotel later on.
+///
+/// ```no_run
+/// # use ws_hotel::*;
+/// # struct Lobby;
+/// # impl RoomHandler for Lobby {
+/// #     type Guest = ();
+/// #     fn on_message(&mut self, _: Context<Self>, _: Message) -> ResultRelocation { Ok(None) }
+/// # }
+/// ws_hotel::listen_with("127.0.0.1:8080", Lobby, |server| {
+///     std::thread::spawn(move || {
+///         std::thread::sleep(std::time::Duration::from_secs(60));
+///         server.shutdown().unwrap();
+///     });
+/// })
+/// .unwrap();
+/// ```
+pub fn listen_with<A, I, R, F>(addr: A, lobby: I, on_start: F) -> ws::Result<()>
+where
+    A: ToSocketAddrs + std::fmt::Debug,
+    I: Into<Entrance<R>>,
+    R: RoomHandler + 'static,
+    R::Guest: 'static,
+    F: FnOnce(ServerHandle),
+{
+    Builder::new().listen_with(addr, lobby, on_start)
+}
+
+/// Starts a WebSocket hotel in a background thread, like [`listen`], and returns once it is
+/// listening, so it can run alongside other servers in the same program.
+///
+/// The returned [ServerHandle] can stop the hotel, after which the thread returns the outcome of
+/// the server.
+///
+/// # Errors
+///
+/// Fails if the server can't be started, see [`listen`].
+pub fn spawn<A, I, R>(addr: A, lobby: I) -> ws::Result<(ServerHandle, JoinHandle<ws::Result<()>>)>
+where
+    A: ToSocketAddrs + Send + 'static,
+    I: Into<Entrance<R>>,
+    R: RoomHandler + Send + 'static,
+    R::Guest: Send + 'static,
+{
+    Builder::new().spawn(addr, lobby)
+}
+
+/// Starts a WebSocket hotel with several equivalent default rooms (sharded lobbies).
+/// This function blocks indefinitely.
+///
+/// Every new client is put in the lobby chosen by `balancer`, for instance the one with the fewest
+/// members using [`LeastMembers`]. Like with [`listen`], the [`RoomHandler::Guest`] type of the
+/// lobbies must implement [`Default`].
+///
+/// # Errors
+///
+/// Fails if the server can't be started, see [`listen`].
+///
+/// # Panics
+///
+/// Panics if `lobbies` is empty.
+pub fn listen_balanced<A, R, B>(addr: A, lobbies: Vec<RoomRef<R>>, balancer: B) -> ws::Result<()>
 where
     A: ToSocketAddrs + std::fmt::Debug,
-    I: Into<RoomRef<R>>,
     R: RoomHandler + 'static,
     R::Guest: Default + 'static,
+    B: Balance<R>,
+{
+    Builder::new().listen_balanced(addr, lobbies, balancer)
+}
+
+/// Starts a WebSocket hotel whose clients are put in the lobby matching the path they request,
+/// see [Router]. This function blocks indefinitely.
+///
+/// # Errors
+///
+/// Fails if the server can't be started, see [`listen`].
+///
+/// # Panics
+///
+/// Panics if `router` has no routes.
+pub fn listen_routed<A>(addr: A, router: Router) -> ws::Result<()>
+where
+    A: ToSocketAddrs + std::fmt::Debug,
 {
-    let lobby = lobby.into();
+    Builder::new().listen_routed(addr, router)
+}
 
-    let lobby: Arc<dyn RoomAny> = Arc::clone(&lobby.0) as _;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    ws::listen(addr, |sender| {
-        let lobby = Arc::clone(&lobby);
+    struct Empty;
 
-        lobby.add(sender.clone(), Box::new(R::Guest::default()));
+    impl RoomHandler for Empty {
+        type Guest = ();
 
-        Handler {
-            sender,
-            room: lobby,
+        fn on_message(&mut self, _cx: Context<Self>, _msg: Message) -> ResultRelocation {
+            Ok(None)
         }
-    })
-    .unwrap()
+    }
+
+    fn record(room: &RoomRef<Empty>, change: RoomChange) {
+        room.0.lock().unwrap().record(change);
+    }
+
+    #[test]
+    fn diffs_from_the_first_and_the_current_version() {
+        let room = Room::new(Empty);
+        assert_eq!(room.diff_since(0).version, 0);
+        assert!(!room.diff_since(0).truncated);
+
+        let (alice, bob) = (MemberId::next(), MemberId::next());
+        record(&room, RoomChange::Joined(alice));
+        record(&room, RoomChange::Joined(bob));
+        record(&room, RoomChange::Left(alice));
+
+        let diff = room.diff_since(0);
+        assert_eq!(diff.version, 3);
+        assert!(!diff.truncated);
+        assert_eq!(diff.joined, [bob]);
+        assert!(diff.left.is_empty());
+
+        let diff = room.diff_since(1);
+        assert_eq!(diff.joined, [bob]);
+        assert_eq!(diff.left, [alice]);
+
+        let diff = room.diff_since(3);
+        assert_eq!(diff.version, 3);
+        assert!(!diff.truncated);
+        assert!(diff.joined.is_empty() && diff.left.is_empty());
+
+        assert!(room.diff_since(4).truncated);
+    }
+
+    #[test]
+    fn truncates_diffs_older_than_the_remembered_changes() {
+        let room = Room::new(Empty);
+        let members = (0..MAX_ROOM_CHANGES + 1)
+            .map(|_| MemberId::next())
+            .collect::<Vec<_>>();
+        members
+            .iter()
+            .for_each(|id| record(&room, RoomChange::Joined(*id)));
+
+        let diff = room.diff_since(0);
+        assert_eq!(diff.version, MAX_ROOM_CHANGES as u64 + 1);
+        assert!(diff.truncated);
+        assert!(diff.joined.is_empty() && diff.left.is_empty());
+
+        let diff = room.diff_since(1);
+        assert!(!diff.truncated);
+        assert_eq!(diff.joined, members[1..]);
+    }
 }
@@ -0,0 +1,152 @@
+//! Rooms closing on their own after some time, see [`RoomRef::set_lifetime`].
+
+use crate::capacity::WhenFull;
+use crate::{
+    alarm, member, CloseCode, MemberHandle, Relocation, Room, RoomHandler, RoomRef, SendErrors,
+};
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+type DisbandFn<G> = dyn Fn(&G) -> Relocation + Send;
+
+/// What happens to the members of a room once its [lifetime][RoomRef::set_lifetime] is over
+pub enum Expiry<G> {
+    /// Their connection is closed with [`CloseCode::Normal`], and they leave the room with
+    /// [`LeaveReason::Kicked`][crate::LeaveReason::Kicked]
+    Kick,
+
+    /// The room is [sealed][RoomRef::seal], and each of them is moved to the room of the
+    /// relocation built from its identity, like with [`RoomRef::disband`]
+    Disband(Box<DisbandFn<G>>),
+}
+
+impl<G> Expiry<G> {
+    /// Shorthand for [`Expiry::Disband`]
+    pub fn disband<F: Fn(&G) -> Relocation + Send + 'static>(f: F) -> Self {
+        Self::Disband(Box::new(f))
+    }
+}
+
+impl<G> Debug for Expiry<G> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Kick => f.write_str("Kick"),
+            Self::Disband(_) => f.write_str("Disband(_)"),
+        }
+    }
+}
+
+pub(crate) struct Lifetime<G> {
+    expiry: Expiry<G>,
+
+    /// Only written while the room is locked, so the room never expires after its lifetime was
+    /// unset or replaced
+    stopped: Arc<AtomicBool>,
+}
+
+impl<G> Lifetime<G> {
+    fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+impl<R> RoomRef<R>
+where
+    R: RoomHandler + Send + 'static,
+    R::Guest: Send,
+{
+    /// Closes the room `lifetime` from now, e.g. at the end of a timed match: its handler is told
+    /// with [`RoomHandler::on_expire`], so it can broadcast the results, then its members are
+    /// removed according to `expiry`. Replaces the previous lifetime if there was one. Members that
+    /// can't be removed are reported to [`RoomHandler::on_send_error`].
+    ///
+    /// Like ticks, the deadline is awaited by the background thread shared by all rooms, that
+    /// only holds a weak reference to the room.
+    pub fn set_lifetime(&self, lifetime: Duration, expiry: Expiry<R::Guest>) {
+        let stopped = Arc::new(AtomicBool::new(false));
+        self.replace_lifetime(Some(Lifetime {
+            expiry,
+            stopped: Arc::clone(&stopped),
+        }));
+
+        let clock = Arc::clone(&self.0.lock().unwrap().clock);
+        let deadline = clock.now() + lifetime;
+        let room = self.downgrade();
+        alarm::set(clock, deadline, move || {
+            if let Some(room) = room.upgrade() {
+                room.expire(&stopped);
+            }
+            None
+        });
+    }
+
+    /// Keeps the room open, after [`RoomRef::set_lifetime`]
+    pub fn unset_lifetime(&self) {
+        self.replace_lifetime(None);
+    }
+
+    fn replace_lifetime(&self, lifetime: Option<Lifetime<R::Guest>>) {
+        let mut room = self.0.lock().unwrap();
+
+        if let Some(previous) = std::mem::replace(&mut room.lifetime, lifetime) {
+            previous.stop();
+        }
+    }
+
+    /// Closes the room if the lifetime that `stopped` belongs to is still the current one
+    fn expire(&self, stopped: &Arc<AtomicBool>) {
+        let expired = Room::dispatch_room(&self.0, |handler, cx| {
+            if stopped.load(Ordering::Relaxed) {
+                return false;
+            }
+
+            handler.on_expire(cx);
+            true
+        });
+
+        if !expired {
+            return;
+        }
+
+        let mut room = self.0.lock().unwrap();
+        match &room.lifetime {
+            Some(lifetime) if Arc::ptr_eq(&lifetime.stopped, stopped) => {}
+            _ => return,
+        }
+        let lifetime = room.lifetime.take().unwrap();
+
+        if let Expiry::Disband(_) = lifetime.expiry {
+            room.sealed = Some(WhenFull::Stay);
+        }
+
+        let mut members = room.members.iter().collect::<Vec<_>>();
+        members.sort_by_key(|member| member.id);
+
+        let errors = SendErrors::default();
+        for member in members {
+            let closed = match &lifetime.expiry {
+                Expiry::Kick => member::kick(
+                    &member.sender,
+                    &member.mailbox,
+                    CloseCode::Normal,
+                    "room closed",
+                ),
+                Expiry::Disband(relocation) => {
+                    MemberHandle::of(member).relocate(relocation(&member.guest))
+                }
+            };
+
+            if let Err(err) = closed {
+                errors.record(member.id, err);
+            }
+        }
+
+        let mut deferred = Vec::new();
+        room.report_send_errors(&mut deferred, errors);
+        drop(room);
+
+        deferred.into_iter().for_each(|f| f());
+    }
+}
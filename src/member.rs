@@ -0,0 +1,215 @@
+use crate::timer::Action;
+use crate::{CloseCode, Member, Message, Relocation, RoomAddr, TimerHandle};
+use std::borrow::Cow;
+use std::fmt::{Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use ws::util::Token;
+use ws::Sender;
+
+/// Timeout token used to wake up a connection that has a [pending relocation][Mailbox::post]
+pub(crate) const RELOCATE: Token = Token(usize::MAX - 100);
+
+/// Requests made to a connection from outside of its handler, through the rooms it is a member of
+/// or a [MemberHandle]. They can come from other threads than the connection's, like with
+/// [`RoomRef::disband`] or [`RoomRef::kick_all`].
+///
+/// It is shared by the connection and its handles, and closed when the connection is dropped, so
+/// that nothing is kept for a connection that is gone.
+///
+/// [`RoomRef::disband`]: crate::RoomRef::disband
+/// [`RoomRef::kick_all`]: crate::RoomRef::kick_all
+#[derive(Debug, Default)]
+pub(crate) struct Mailbox {
+    inbox: Mutex<Inbox>,
+
+    /// Whether the connection is being closed by the server rather than by the client
+    kicked: AtomicBool,
+}
+
+#[derive(Debug, Default)]
+struct Inbox {
+    closed: bool,
+
+    /// The relocation requested by [`MemberHandle::relocate`], waiting for the connection's handler
+    /// to apply it
+    relocation: Option<Relocation>,
+
+    /// Timers (re-)armed from outside of the connection, waiting to be taken over by it, along
+    /// with the token given to `ws`
+    timers: Vec<(Token, TimerHandle)>,
+}
+
+impl Mailbox {
+    /// Requests the connection to apply `relocation`, replacing the one that was already pending
+    fn post(&self, relocation: Relocation) -> ws::Result<()> {
+        let mut inbox = self.inbox.lock().unwrap();
+        if inbox.closed {
+            return Err(closed());
+        }
+
+        inbox.relocation = Some(relocation);
+        Ok(())
+    }
+
+    /// Takes the relocation that was requested by [`MemberHandle::relocate`], if any
+    pub(crate) fn take_relocation(&self) -> Option<Relocation> {
+        self.inbox.lock().unwrap().relocation.take()
+    }
+
+    /// Arms `timer` on behalf of the connection, which takes it over once it fires, see
+    /// [`TimerHandle::reschedule`]
+    pub(crate) fn post_timer(&self, timer: &TimerHandle, delay: Duration) -> ws::Result<()> {
+        // Locked first, so that the connection can't miss the timer if it fires right away
+        let mut inbox = self.inbox.lock().unwrap();
+        if inbox.closed {
+            return Err(closed());
+        }
+
+        let internal = timer.arm(delay)?;
+        inbox.timers.push((internal, timer.clone()));
+        Ok(())
+    }
+
+    /// Takes the timers that were (re-)armed from outside of the connection
+    pub(crate) fn take_timers(&self) -> Vec<(Token, TimerHandle)> {
+        std::mem::take(&mut self.inbox.lock().unwrap().timers)
+    }
+
+    /// Whether the connection was closed with [kick]
+    pub(crate) fn is_kicked(&self) -> bool {
+        self.kicked.load(Ordering::Relaxed)
+    }
+
+    /// Refuses every request from now on, dropping the pending ones
+    pub(crate) fn close(&self) {
+        let mut inbox = self.inbox.lock().unwrap();
+        inbox.closed = true;
+        inbox.relocation = None;
+        inbox.timers.drain(..).for_each(|(_, timer)| timer.close());
+    }
+}
+
+/// Closes a connection on behalf of the server, so that its room is told the member was
+/// [kicked][crate::LeaveReason::Kicked] once it is closed
+pub(crate) fn kick(
+    sender: &Sender,
+    mailbox: &Mailbox,
+    code: CloseCode,
+    reason: impl Into<Cow<'static, str>>,
+) -> ws::Result<()> {
+    mailbox.kicked.store(true, Ordering::Relaxed);
+    sender.close_with_reason(code, reason)
+}
+
+pub(crate) fn closed() -> ws::Error {
+    ws::Error::new(ws::ErrorKind::Internal, "the connection is closed")
+}
+
+pub(crate) fn not_found(id: MemberId) -> ws::Error {
+    ws::Error::new(
+        ws::ErrorKind::Internal,
+        format!("{} is not a member of this room", id),
+    )
+}
+
+/// Opaque identifier of a client, assigned when it connects and kept when it is relocated from
+/// room to room.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MemberId(u64);
+
+impl MemberId {
+    pub(crate) fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl Display for MemberId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "member #{}", self.0)
+    }
+}
+
+/// A handle to a member of a room, obtained from a [Context] with [`Context::find_member`] or
+/// [`Context::member`].
+///
+/// It stays valid after the callback that produced it has returned, even if the member changes
+/// rooms, but all operations fail (or do nothing) once the client is disconnected.
+///
+/// [Context]: crate::Context
+/// [`Context::find_member`]: crate::Context::find_member
+/// [`Context::member`]: crate::Context::member
+#[derive(Clone, Debug)]
+pub struct MemberHandle {
+    id: MemberId,
+    sender: Sender,
+    mailbox: Arc<Mailbox>,
+}
+
+impl MemberHandle {
+    pub(crate) fn of<G>(member: &Member<G>) -> Self {
+        Self {
+            id: member.id,
+            sender: member.sender.clone(),
+            mailbox: Arc::clone(&member.mailbox),
+        }
+    }
+
+    /// Identifier of this member
+    #[inline]
+    pub fn id(&self) -> MemberId {
+        self.id
+    }
+
+    /// Sends a message to this member
+    #[inline]
+    pub fn send(&self, msg: impl Into<Message>) -> ws::Result<()> {
+        self.sender.send(msg)
+    }
+
+    /// Closes the connection of this member once the messages that were sent to it before have
+    /// been flushed, see [`Context::close_after_flush`][crate::Context::close_after_flush].
+    pub fn kick(&self, code: CloseCode, reason: impl Into<Cow<'static, str>>) -> ws::Result<()> {
+        kick(&self.sender, &self.mailbox, code, reason)
+    }
+
+    /// Moves this member to another room.
+    ///
+    /// The relocation is applied asynchronously by the member's own connection, once the current
+    /// callback has returned, so that its room is never locked twice. If several relocations are
+    /// requested before that, only the last one is applied. Fails once the client is
+    /// disconnected.
+    pub fn relocate(&self, relocation: Relocation) -> ws::Result<()> {
+        self.mailbox.post(relocation)?;
+        self.sender.timeout(0, RELOCATE)
+    }
+
+    /// Arms a timer on the connection of this member, that fires in its own context
+    pub(crate) fn set_timeout(
+        &self,
+        delay: Duration,
+        action: Action,
+        room: Option<RoomAddr>,
+    ) -> ws::Result<TimerHandle> {
+        let timer = TimerHandle::new(self.sender.clone(), &self.mailbox, action, room);
+        timer.reschedule(delay)?;
+        Ok(timer)
+    }
+}
+
+impl PartialEq for MemberHandle {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for MemberHandle {}
+
+impl Hash for MemberHandle {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
@@ -0,0 +1,106 @@
+use crate::{member, CloseCode, Member, Room, RoomChange, RoomHandler};
+use std::mem::size_of;
+
+pub(crate) type GuestSizeFn<G> = dyn Fn(&G) -> usize + Send;
+
+/// Approximate memory used by a room, see [`RoomRef::memory_usage`][crate::RoomRef::memory_usage].
+///
+/// Heap allocations owned by the handler or by guests are only accounted for if an estimator was
+/// given with [`RoomRef::set_guest_size`][crate::RoomRef::set_guest_size].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// Size of the [RoomHandler] itself
+    pub handler: usize,
+
+    /// Size of the members list, including the estimated size of every guest
+    pub members: usize,
+
+    /// Size of the membership history kept for [`RoomRef::diff_since`][crate::RoomRef::diff_since]
+    pub history: usize,
+}
+
+impl MemoryUsage {
+    /// Total number of bytes used by the room
+    pub fn total(&self) -> usize {
+        self.handler + self.members + self.history
+    }
+}
+
+/// Which members are kicked when a room exceeds its
+/// [memory limit][crate::RoomRef::set_memory_limit].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Eviction {
+    /// Kick the members that connected last first
+    Newest,
+
+    /// Kick the members with the largest guests first
+    Largest,
+}
+
+pub(crate) struct MemoryLimit {
+    pub(crate) bytes: usize,
+    pub(crate) eviction: Eviction,
+}
+
+impl<R: RoomHandler> Room<R> {
+    fn guest_size(&self, member: &Member<R::Guest>) -> usize {
+        self.guest_size.as_ref().map_or(0, |f| f(&member.guest))
+    }
+
+    fn member_size(&self, member: &Member<R::Guest>) -> usize {
+        size_of::<Member<R::Guest>>() + self.guest_size(member)
+    }
+
+    pub(crate) fn memory_usage(&self) -> MemoryUsage {
+        let guests: usize = self.members.iter().map(|m| self.guest_size(m)).sum();
+
+        MemoryUsage {
+            handler: size_of::<R>(),
+            members: self.members.len() * size_of::<Member<R::Guest>>() + guests,
+            history: self.changes.len() * size_of::<(u64, RoomChange)>(),
+        }
+    }
+
+    /// Kicks members according to the eviction policy until the estimated memory usage of the room
+    /// fits its limit again
+    pub(crate) fn enforce_memory_limit(&self) {
+        let limit = match &self.memory_limit {
+            Some(limit) => limit,
+            None => return,
+        };
+
+        // Members that were already kicked stay in the room until their connection is closed, and
+        // will free their memory then
+        let kicked = self.members.iter().filter(|m| m.mailbox.is_kicked());
+        let freed: usize = kicked.map(|m| self.member_size(m)).sum();
+
+        let mut usage = self.memory_usage().total().saturating_sub(freed);
+        if usage <= limit.bytes {
+            return;
+        }
+
+        let mut candidates = self
+            .members
+            .iter()
+            .filter(|m| !m.mailbox.is_kicked())
+            .collect::<Vec<_>>();
+        match limit.eviction {
+            Eviction::Newest => candidates.sort_by_key(|m| std::cmp::Reverse(m.id)),
+            Eviction::Largest => candidates.sort_by_key(|m| std::cmp::Reverse(self.guest_size(m))),
+        }
+
+        for member in candidates {
+            if usage <= limit.bytes {
+                break;
+            }
+
+            let _ = member::kick(
+                &member.sender,
+                &member.mailbox,
+                CloseCode::Again,
+                "room memory limit exceeded",
+            );
+            usage = usage.saturating_sub(self.member_size(member));
+        }
+    }
+}
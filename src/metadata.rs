@@ -0,0 +1,86 @@
+use crate::{RoomBuilder, RoomHandler, RoomRef, RoomRegistry};
+use std::any::{Any, TypeId};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{Debug, Formatter};
+use std::hash::Hash;
+use std::sync::Arc;
+
+/// Immutable information attached to a room when it is created with [`Room::builder`]: tags, and
+/// values indexed by their type.
+///
+/// It is readable from [`Context::metadata`], [`RoomContext::metadata`] and
+/// [`RoomRef::metadata`], so rooms can for instance be listed by tag with
+/// [`RoomRegistry::tagged`] without knowing anything about their handler.
+///
+/// [`Room::builder`]: crate::Room::builder
+/// [`Context::metadata`]: crate::Context::metadata
+/// [`RoomContext::metadata`]: crate::RoomContext::metadata
+#[derive(Default)]
+pub struct Metadata {
+    tags: BTreeSet<String>,
+    values: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+
+impl Metadata {
+    /// Iterates over the tags of the room, in alphabetical order
+    pub fn tags(&self) -> impl Iterator<Item = &str> {
+        self.tags.iter().map(String::as_str)
+    }
+
+    /// Whether the room was given the tag `tag`
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.contains(tag)
+    }
+
+    /// Returns the value of type `T` the room was given, if there is one
+    pub fn get<T: 'static>(&self) -> Option<&T> {
+        self.values
+            .get(&TypeId::of::<T>())
+            .map(|value| value.downcast_ref().unwrap())
+    }
+}
+
+impl Debug for Metadata {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Metadata")
+            .field("tags", &self.tags)
+            .field("values", &self.values.len())
+            .finish()
+    }
+}
+
+impl<R: RoomHandler> RoomBuilder<R> {
+    /// Tags the room, see [Metadata]
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.metadata.tags.insert(tag.into());
+        self
+    }
+
+    /// Attaches a value to the room, replacing the previous value of the same type. See
+    /// [Metadata].
+    pub fn meta<T: Any + Send + Sync>(mut self, value: T) -> Self {
+        self.metadata
+            .values
+            .insert(TypeId::of::<T>(), Box::new(value));
+        self
+    }
+}
+
+impl<R: RoomHandler> RoomRef<R> {
+    /// Metadata the room was created with
+    pub fn metadata(&self) -> Arc<Metadata> {
+        Arc::clone(&self.0.lock().unwrap().metadata)
+    }
+}
+
+impl<K: Eq + Hash + Clone, R: RoomHandler> RoomRegistry<K, R> {
+    /// The rooms of the registry that were given the tag `tag` and their name, as of now
+    pub fn tagged<'a>(&self, tag: &'a str) -> impl Iterator<Item = (K, RoomRef<R>)> + 'a
+    where
+        K: 'a,
+        R: 'a,
+    {
+        self.iter()
+            .filter(move |(_, room)| room.metadata().has_tag(tag))
+    }
+}
@@ -0,0 +1,137 @@
+//! Cross-cutting logic layered around a room's handler, see [Middleware].
+
+use crate::{Context, LeaveReason, Message, RoomHandler, RoomRef};
+use std::cell::RefCell;
+
+/// Code that runs around the [RoomHandler] of a room, to observe or rewrite what goes in and out
+/// of it: logging, authorization, metrics, rate limiting…
+///
+/// Layers are added to a room with [`RoomRef::layer`]. Every hook receives the [Context] of the
+/// room, and does nothing by default.
+///
+/// ```
+/// # use ws_hotel::*;
+/// /// Logs the messages received by any kind of room
+/// struct Logged;
+///
+/// impl<R: RoomHandler> Middleware<R> for Logged {
+///     fn on_message(&mut self, cx: &mut Context<R>, msg: Message) -> Result<Option<Message>> {
+///         println!("{} sent {:?}", cx.id(), msg);
+///         Ok(Some(msg))
+///     }
+/// }
+///
+/// # struct Chat;
+/// # impl RoomHandler for Chat {
+/// #     type Guest = ();
+/// #     fn on_message(&mut self, _: Context<Self>, _: Message) -> ResultRelocation { Ok(None) }
+/// # }
+/// let chat = Room::new(Chat);
+/// chat.layer(Logged);
+/// ```
+pub trait Middleware<R: RoomHandler>: Send {
+    /// Called when a member enters the room (when it connects to it as a lobby, or is relocated
+    /// into it), before the handler. Returning an error closes its connection without the handler
+    /// being called.
+    fn on_join(&mut self, _cx: &mut Context<R>) -> ws::Result<()> {
+        Ok(())
+    }
+
+    /// Called with every message sent by a member, before the handler. The returned message is
+    /// passed on to the next layer, or to the handler. Returning `None` drops the message.
+    fn on_message(&mut self, _cx: &mut Context<R>, msg: Message) -> ws::Result<Option<Message>> {
+        Ok(Some(msg))
+    }
+
+    /// Called when a member leaves the room, before the handler
+    fn on_leave(&mut self, _cx: &mut Context<R>, _reason: LeaveReason) {}
+
+    /// Called with every message broadcasted by the handler. The returned message is passed on to
+    /// the next layer, or sent. Returning `None` cancels the broadcast.
+    ///
+    /// The broadcasts made by middleware themselves don't go through this hook.
+    fn on_broadcast(&mut self, msg: Message) -> Option<Message> {
+        Some(msg)
+    }
+}
+
+/// The layers of a room, innermost first
+pub(crate) type Stack<R> = RefCell<Vec<Box<dyn Middleware<R>>>>;
+
+impl<R: RoomHandler> RoomRef<R> {
+    /// Wraps the handler of the room in a new [Middleware] layer.
+    ///
+    /// The last layer added is the outermost one: it sees incoming events first and outgoing
+    /// broadcasts last, so `room.layer(RateLimited).layer(Logged)` logs messages before they are
+    /// rate limited.
+    pub fn layer(&self, middleware: impl Middleware<R> + 'static) {
+        self.0
+            .lock()
+            .unwrap()
+            .middleware
+            .get_mut()
+            .push(Box::new(middleware));
+    }
+}
+
+/// Calls `f` with every layer from the outermost one, while they are taken out of the room so
+/// that the broadcasts they make don't go through them again
+fn each_layer<R, F>(cx: &mut Context<R>, mut f: F) -> ws::Result<bool>
+where
+    R: RoomHandler,
+    F: FnMut(&mut dyn Middleware<R>, &mut Context<R>) -> ws::Result<bool>,
+{
+    let mut layers = cx.middleware.take();
+
+    let mut result = Ok(true);
+    for layer in layers.iter_mut().rev() {
+        result = f(&mut **layer, cx);
+        if !matches!(result, Ok(true)) {
+            break;
+        }
+    }
+
+    *cx.middleware.borrow_mut() = layers;
+    result
+}
+
+pub(crate) fn join<R: RoomHandler>(cx: &mut Context<R>) -> ws::Result<()> {
+    each_layer(cx, |layer, cx| layer.on_join(cx).map(|()| true)).map(drop)
+}
+
+pub(crate) fn message<R: RoomHandler>(
+    cx: &mut Context<R>,
+    msg: Message,
+) -> ws::Result<Option<Message>> {
+    let mut msg = Some(msg);
+
+    each_layer(cx, |layer, cx| {
+        msg = layer.on_message(cx, msg.take().unwrap())?;
+        Ok(msg.is_some())
+    })?;
+
+    Ok(msg)
+}
+
+pub(crate) fn leave<R: RoomHandler>(cx: &mut Context<R>, reason: LeaveReason) {
+    let _ = each_layer(cx, |layer, cx| {
+        layer.on_leave(cx, reason);
+        Ok(true)
+    });
+}
+
+/// Type-erased access to the [`Middleware::on_broadcast`] hooks of a room, for the views that
+/// don't know its handler type
+pub(crate) trait Broadcasts {
+    /// Passes an outgoing broadcast through every layer, from the innermost one
+    fn layer_broadcast(&self, msg: Message) -> Option<Message>;
+}
+
+impl<R: RoomHandler> Broadcasts for Stack<R> {
+    fn layer_broadcast(&self, msg: Message) -> Option<Message> {
+        // The stack is empty while the layers themselves are being called, see `each_layer`
+        self.borrow_mut()
+            .iter_mut()
+            .try_fold(msg, |msg, layer| layer.on_broadcast(msg))
+    }
+}
@@ -0,0 +1,78 @@
+//! Limiting the number of connections of each client IP, see [`Builder::connections_per_ip`].
+//!
+//! [`Builder::connections_per_ip`]: crate::Builder::connections_per_ip
+
+use crate::Handshake;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+type IpKeyFn = dyn Fn(&Handshake) -> Option<IpAddr> + Send + Sync;
+
+/// Extracts the IP a connection is counted for from its handshake
+#[derive(Clone)]
+pub(crate) struct IpKey(Arc<IpKeyFn>);
+
+impl IpKey {
+    pub(crate) fn new<F>(key: F) -> Self
+    where
+        F: Fn(&Handshake) -> Option<IpAddr> + Send + Sync + 'static,
+    {
+        Self(Arc::new(key))
+    }
+}
+
+impl Debug for IpKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("IpKey").finish_non_exhaustive()
+    }
+}
+
+/// The number of connections of each IP of a running hotel
+#[derive(Debug)]
+pub(crate) struct PerIp {
+    max: usize,
+    key: IpKey,
+    connections: Mutex<HashMap<IpAddr, usize>>,
+}
+
+impl PerIp {
+    pub(crate) fn new(max: usize, key: IpKey) -> Self {
+        Self {
+            max,
+            key,
+            connections: Mutex::default(),
+        }
+    }
+
+    /// Counts a new connection, unless its IP already has the maximum number of connections.
+    /// Returns the IP it was counted for, if any, which must be [released][PerIp::leave] once
+    /// the connection is closed.
+    pub(crate) fn enter(&self, handshake: &Handshake) -> Result<Option<IpAddr>, IpAddr> {
+        let ip = match (self.key.0)(handshake) {
+            Some(ip) => ip,
+            None => return Ok(None),
+        };
+
+        let mut connections = self.connections.lock().unwrap();
+        let count = connections.get(&ip).copied().unwrap_or(0);
+        if count >= self.max {
+            return Err(ip);
+        }
+
+        connections.insert(ip, count + 1);
+        Ok(Some(ip))
+    }
+
+    /// Forgets about a closed connection counted for `ip`
+    pub(crate) fn leave(&self, ip: IpAddr) {
+        let mut connections = self.connections.lock().unwrap();
+        if let Some(count) = connections.get_mut(&ip) {
+            *count -= 1;
+            if *count == 0 {
+                connections.remove(&ip);
+            }
+        }
+    }
+}
@@ -0,0 +1,319 @@
+//! Finding the address of clients behind trusted proxies, see [`Builder::trusted_proxy`].
+//!
+//! [`Builder::trusted_proxy`]: crate::Builder::trusted_proxy
+
+use crate::{Handshake, Request};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// A range of IP addresses, written in CIDR notation (`10.0.0.0/8`, `fd00::/8`), or a single
+/// address.
+///
+/// ```
+/// # use ws_hotel::IpRange;
+/// let range: IpRange = "10.0.0.0/8".parse().unwrap();
+/// assert!(range.contains("10.1.2.3".parse().unwrap()));
+/// assert!(!range.contains("192.168.0.1".parse().unwrap()));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct IpRange {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpRange {
+    /// The addresses sharing their first `prefix` bits with `addr`. Returns `None` if `prefix` is
+    /// longer than the address.
+    pub fn new(addr: IpAddr, prefix: u8) -> Option<Self> {
+        (prefix <= bits(addr)).then_some(Self { addr, prefix })
+    }
+
+    /// Whether `ip` is in the range. IPv4 addresses mapped to IPv6 (`::ffff:a.b.c.d`) are
+    /// considered as their IPv4 counterpart.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            ip => ip,
+        };
+
+        let (net, ip) = match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => (u32::from(net).into(), u32::from(ip).into()),
+            (IpAddr::V6(net), IpAddr::V6(ip)) => (u128::from(net), u128::from(ip)),
+            _ => return false,
+        };
+
+        let host_bits = u32::from(bits(self.addr) - self.prefix);
+        let mask = u128::MAX.checked_shl(host_bits).unwrap_or(0);
+        net & mask == ip & mask
+    }
+}
+
+/// Number of bits of the addresses of the family of `addr`
+fn bits(addr: IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+impl From<IpAddr> for IpRange {
+    fn from(addr: IpAddr) -> Self {
+        Self {
+            addr,
+            prefix: bits(addr),
+        }
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = InvalidIpRange;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('/') {
+            Some((addr, prefix)) => {
+                let addr = addr.parse().map_err(|_| InvalidIpRange)?;
+                let prefix = prefix.parse().map_err(|_| InvalidIpRange)?;
+                Self::new(addr, prefix).ok_or(InvalidIpRange)
+            }
+            None => s.parse().map(IpAddr::into).map_err(|_| InvalidIpRange),
+        }
+    }
+}
+
+impl Display for IpRange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// The error returned when parsing an invalid [IpRange]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InvalidIpRange;
+
+impl Display for InvalidIpRange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid IP range")
+    }
+}
+
+impl Error for InvalidIpRange {}
+
+/// The proxies whose `Forwarded` and `X-Forwarded-For` headers are trusted
+#[derive(Clone, Debug, Default)]
+pub(crate) struct TrustedProxies(pub(crate) Vec<IpRange>);
+
+impl TrustedProxies {
+    fn trusts(&self, ip: IpAddr) -> bool {
+        self.0.iter().any(|range| range.contains(ip))
+    }
+
+    /// The address of the client of `handshake`: the peer of its socket, or the address it was
+    /// forwarded for if the peer is a trusted proxy.
+    ///
+    /// Forwarded addresses are read from the last one, and the first one that isn't a trusted
+    /// proxy is the client. Clients can't spoof their address by sending these headers themselves:
+    /// trusted proxies append the address they see after the ones they received.
+    pub(crate) fn remote_addr(&self, handshake: &Handshake) -> Option<IpAddr> {
+        let mut remote = handshake.peer_addr?.ip();
+
+        for hop in forwarded_for(&handshake.request).iter().rev() {
+            if !self.trusts(remote) {
+                break;
+            }
+
+            match hop {
+                Some(ip) => remote = *ip,
+                // An obfuscated or unknown address, past which the chain can't be followed
+                None => break,
+            }
+        }
+
+        Some(remote)
+    }
+}
+
+/// The addresses a request was forwarded for, from the client to the last proxy, read from its
+/// `Forwarded` headers or else from its `X-Forwarded-For` headers
+fn forwarded_for(request: &Request) -> Vec<Option<IpAddr>> {
+    let values = |name: &str| {
+        request
+            .headers()
+            .iter()
+            .filter(|(header, _)| header.eq_ignore_ascii_case(name))
+            .filter_map(|(_, value)| std::str::from_utf8(value).ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect::<Vec<_>>()
+    };
+
+    let forwarded = values("forwarded");
+    if !forwarded.is_empty() {
+        return forwarded
+            .iter()
+            .map(|element| {
+                let node = element.split(';').find_map(|pair| {
+                    let (name, value) = pair.trim().split_once('=')?;
+                    name.eq_ignore_ascii_case("for").then_some(value)
+                });
+                node.and_then(parse_node)
+            })
+            .collect();
+    }
+
+    values("x-forwarded-for")
+        .into_iter()
+        .map(parse_node)
+        .collect()
+}
+
+/// Parses a forwarded address, possibly quoted and followed by a port (`"[::1]:4711"`)
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim_matches('"');
+
+    if let Some(v6) = node.strip_prefix('[') {
+        return v6.split(']').next()?.parse().ok();
+    }
+
+    node.parse().ok().or_else(|| {
+        let (ip, _port) = node.rsplit_once(':')?;
+        ip.parse().ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Response;
+
+    fn handshake(peer: &str, headers: &[(&str, &str)]) -> Handshake {
+        let mut raw = String::from(
+            "GET / HTTP/1.1\r\nHost: localhost\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n",
+        );
+        for (name, value) in headers {
+            raw += &format!("{}: {}\r\n", name, value);
+        }
+        raw += "\r\n";
+
+        let request = Request::parse(raw.as_bytes()).unwrap().unwrap();
+        Handshake {
+            response: Response::from_request(&request).unwrap(),
+            request,
+            peer_addr: Some(peer.parse().unwrap()),
+            local_addr: None,
+        }
+    }
+
+    fn proxies(ranges: &[&str]) -> TrustedProxies {
+        TrustedProxies(ranges.iter().map(|range| range.parse().unwrap()).collect())
+    }
+
+    fn ip(ip: &str) -> Option<IpAddr> {
+        Some(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn parses_ranges() {
+        let range: IpRange = "192.168.0.0/16".parse().unwrap();
+        assert!(range.contains("192.168.255.1".parse().unwrap()));
+        assert!(!range.contains("192.169.0.1".parse().unwrap()));
+        assert!(range.contains("::ffff:192.168.1.1".parse().unwrap()));
+        assert!(!range.contains("::1".parse().unwrap()));
+
+        let v6: IpRange = "fd00::/8".parse().unwrap();
+        assert!(v6.contains("fd12::1".parse().unwrap()));
+        assert!(!v6.contains("fe80::1".parse().unwrap()));
+
+        let all: IpRange = "0.0.0.0/0".parse().unwrap();
+        assert!(all.contains("8.8.8.8".parse().unwrap()));
+
+        let single: IpRange = "10.0.0.1".parse().unwrap();
+        assert_eq!(single.to_string(), "10.0.0.1/32");
+        assert!(!single.contains("10.0.0.2".parse().unwrap()));
+
+        assert_eq!("10.0.0.0/33".parse::<IpRange>(), Err(InvalidIpRange));
+        assert_eq!("::/129".parse::<IpRange>(), Err(InvalidIpRange));
+        assert_eq!("10.0.0.0/".parse::<IpRange>(), Err(InvalidIpRange));
+        assert_eq!("nope".parse::<IpRange>(), Err(InvalidIpRange));
+    }
+
+    #[test]
+    fn ignores_headers_from_untrusted_peers() {
+        let handshake = handshake("203.0.113.7:1234", &[("X-Forwarded-For", "10.0.0.1")]);
+        assert_eq!(
+            proxies(&["10.0.0.0/8"]).remote_addr(&handshake),
+            ip("203.0.113.7")
+        );
+        assert_eq!(proxies(&[]).remote_addr(&handshake), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn follows_trusted_proxies() {
+        let handshake = handshake(
+            "10.0.0.2:1234",
+            &[("X-Forwarded-For", "198.51.100.1, 10.0.0.1")],
+        );
+        assert_eq!(
+            proxies(&["10.0.0.0/8"]).remote_addr(&handshake),
+            ip("198.51.100.1")
+        );
+        assert_eq!(
+            proxies(&["10.0.0.2"]).remote_addr(&handshake),
+            ip("10.0.0.1")
+        );
+    }
+
+    #[test]
+    fn stops_at_spoofed_addresses() {
+        // The client sent a fake chain, to which the proxy appended the address it saw
+        let handshake = handshake(
+            "10.0.0.2:1234",
+            &[
+                ("X-Forwarded-For", "127.0.0.1, 10.0.0.9"),
+                ("X-Forwarded-For", "203.0.113.7"),
+            ],
+        );
+        assert_eq!(
+            proxies(&["10.0.0.0/8"]).remote_addr(&handshake),
+            ip("203.0.113.7")
+        );
+    }
+
+    #[test]
+    fn stops_at_unknown_addresses() {
+        let handshake = handshake("10.0.0.2:1234", &[("X-Forwarded-For", "garbage, 10.0.0.1")]);
+        assert_eq!(
+            proxies(&["10.0.0.0/8"]).remote_addr(&handshake),
+            ip("10.0.0.1")
+        );
+    }
+
+    #[test]
+    fn prefers_forwarded_headers() {
+        let hidden = handshake(
+            "[::1]:1234",
+            &[
+                (
+                    "Forwarded",
+                    r#"for="[2001:db8::1]:4711";proto=https, for=_hidden"#,
+                ),
+                ("X-Forwarded-For", "198.51.100.1"),
+            ],
+        );
+        assert_eq!(proxies(&["::1"]).remote_addr(&hidden), ip("::1"));
+
+        let chain = handshake(
+            "[::1]:1234",
+            &[(
+                "Forwarded",
+                r#"for="[2001:db8::1]:4711";proto=https, For=192.0.2.60:80"#,
+            )],
+        );
+        assert_eq!(proxies(&["::1"]).remote_addr(&chain), ip("192.0.2.60"));
+        assert_eq!(
+            proxies(&["::1", "192.0.2.0/24"]).remote_addr(&chain),
+            ip("2001:db8::1")
+        );
+    }
+}
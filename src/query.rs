@@ -0,0 +1,175 @@
+//! Parameters of the query string of a handshake, see [Query].
+
+use crate::{Entrance, Handshake, RoomHandler, RoomRef};
+use std::borrow::Cow;
+
+/// The parameters of the query string of a request, e.g. `room=foo&token=…` for
+/// `wss://host/ws?room=foo&token=…`, percent-decoded and in order.
+///
+/// It is mainly used to identify clients as soon as they connect, with [`Entrance::with_query`],
+/// but can be extracted from any handshake with [`Query::of`].
+///
+/// ```
+/// # use ws_hotel::Query;
+/// let query = Query::parse("/ws?room=caf%C3%A9&name=Jane+Doe&tag=a&tag=b");
+/// assert_eq!(query.get("room"), Some("café"));
+/// assert_eq!(query.get("name"), Some("Jane Doe"));
+/// assert_eq!(query.get_all("tag").collect::<Vec<_>>(), ["a", "b"]);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Query(Vec<(String, String)>);
+
+impl Query {
+    /// Parses the query string of `resource` (the path of a request, possibly followed by `?` and
+    /// its query string). Parameters without a value (`?flag`) have an empty value.
+    pub fn parse(resource: &str) -> Self {
+        let query = match resource.split_once('?') {
+            Some((_, query)) => query,
+            None => return Self::default(),
+        };
+
+        let params = query
+            .split('&')
+            .filter(|param| !param.is_empty())
+            .map(|param| {
+                let (name, value) = param.split_once('=').unwrap_or((param, ""));
+                (decode(name), decode(value))
+            });
+
+        Self(params.collect())
+    }
+
+    /// The query string of the request of `handshake`
+    pub fn of(handshake: &Handshake) -> Self {
+        Self::parse(handshake.request.resource())
+    }
+
+    /// The value of the first parameter called `name`
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, value)| value)
+    }
+
+    /// The values of every parameter called `name`, e.g. for `?tag=a&tag=b`
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.iter()
+            .filter(move |(n, _)| *n == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Iterates over the names and values of the parameters
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    /// Whether there are no parameters
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Decodes a component of a query string (`+` and `%XX` escapes). Invalid escapes are kept as is.
+fn decode(component: &str) -> String {
+    let bytes = component.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = |at: usize| bytes.get(at).and_then(|b| (*b as char).to_digit(16));
+
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => match (hex(i + 1), hex(i + 2)) {
+                (Some(high), Some(low)) => {
+                    decoded.push((high * 16 + low) as u8);
+                    i += 2;
+                }
+                _ => decoded.push(b'%'),
+            },
+            b => decoded.push(b),
+        }
+
+        i += 1;
+    }
+
+    match String::from_utf8_lossy(&decoded) {
+        Cow::Borrowed(_) => String::from_utf8(decoded).unwrap(),
+        Cow::Owned(lossy) => lossy,
+    }
+}
+
+impl<R: RoomHandler> Entrance<R> {
+    /// Like [`Entrance::with_guest`], with the identity of clients built from the query string of
+    /// their request.
+    ///
+    /// ```no_run
+    /// # use ws_hotel::*;
+    /// # struct Lobby;
+    /// # impl RoomHandler for Lobby {
+    /// #     type Guest = (String, String);
+    /// #     fn on_message(&mut self, _: Context<Self>, _: Message) -> ResultRelocation { Ok(None) }
+    /// # }
+    /// // wss://host/ws?room=foo&token=…
+    /// let entrance = Entrance::with_query(Lobby, |query| {
+    ///     match (query.get("room"), query.get("token")) {
+    ///         (Some(room), Some(token)) => Ok((room.to_owned(), token.to_owned())),
+    ///         _ => Err(ws::Error::new(ws::ErrorKind::Protocol, "missing parameters")),
+    ///     }
+    /// });
+    ///
+    /// ws_hotel::listen("127.0.0.1:8080", entrance).unwrap();
+    /// ```
+    pub fn with_query<F>(room: impl Into<RoomRef<R>>, mut guest: F) -> Self
+    where
+        F: FnMut(&Query) -> ws::Result<R::Guest> + Send + 'static,
+    {
+        Self::with_guest(room, move |handshake| guest(&Query::of(handshake)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_escapes() {
+        assert_eq!(decode("a+b%20c"), "a b c");
+        assert_eq!(decode("caf%C3%a9"), "café");
+        assert_eq!(decode("%2B"), "+");
+    }
+
+    #[test]
+    fn keeps_invalid_escapes() {
+        assert_eq!(decode("%zz"), "%zz");
+        assert_eq!(decode("%4"), "%4");
+        assert_eq!(decode("%4g"), "%4g");
+        assert_eq!(decode("%%41"), "%A");
+    }
+
+    #[test]
+    fn keeps_trailing_percent() {
+        assert_eq!(decode("%"), "%");
+        assert_eq!(decode("100%"), "100%");
+    }
+
+    #[test]
+    fn replaces_invalid_utf8() {
+        assert_eq!(decode("%FF"), "\u{FFFD}");
+        assert_eq!(decode("a%C3"), "a\u{FFFD}");
+        assert_eq!(decode("%C3%28"), "\u{FFFD}(");
+    }
+
+    #[test]
+    fn parses_parameters() {
+        let query = Query::parse("/ws?a=1&&flag&b=x%3Dy&a=2");
+        assert_eq!(
+            query.iter().collect::<Vec<_>>(),
+            [("a", "1"), ("flag", ""), ("b", "x=y"), ("a", "2")]
+        );
+        assert!(Query::parse("/ws").is_empty());
+        assert!(Query::parse("/ws?").is_empty());
+    }
+}
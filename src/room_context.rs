@@ -0,0 +1,342 @@
+use crate::channels;
+use crate::member::{self, MemberHandle};
+use crate::middleware::{self, Broadcasts};
+use crate::timer::Action;
+use crate::{
+    Clock, CloseCode, Compression, Member, MemberId, Message, Metadata, Relocation, Room,
+    RoomHandler, RoomId, RoomRef, RoomRefWeak, RttStats, SendErrors, StdRng, TimerHandle,
+};
+use std::borrow::Cow;
+use std::fmt::{Debug, Formatter};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use ws::util::Token;
+
+/// Access to a whole room from callbacks that aren't about a specific member, like
+/// [`RoomHandler::on_tick`].
+///
+/// It is the room-wide counterpart of [Context][crate::Context]: every member can be read,
+/// mutated, sent messages, kicked or relocated.
+pub struct RoomContext<'a, 'm, R: RoomHandler> {
+    room: &'a RoomRefWeak<R>,
+    room_id: RoomId,
+    metadata: &'a Metadata,
+
+    members: &'m mut [Member<R::Guest>],
+    deferred: &'m mut Vec<Box<dyn FnOnce()>>,
+    errors: &'m SendErrors,
+    middleware: &'a middleware::Stack<R>,
+    compression: Option<&'a Compression>,
+    clock: &'a dyn Clock,
+    rng: &'m mut StdRng,
+}
+
+impl<R: RoomHandler> RoomContext<'_, '_, R> {
+    /// Weak reference to the current room
+    pub fn room(&self) -> &RoomRefWeak<R> {
+        self.room
+    }
+
+    /// Identifier of the current room
+    pub fn room_id(&self) -> RoomId {
+        self.room_id
+    }
+
+    /// Metadata the current room was created with
+    pub fn metadata(&self) -> &Metadata {
+        self.metadata
+    }
+
+    /// Strong reference to the current room, see [`Context::room_ref`][crate::Context::room_ref]
+    pub fn room_ref(&self) -> RoomRef<R> {
+        self.room
+            .upgrade()
+            .expect("room is alive while one of its callbacks is running")
+    }
+
+    /// Returns the current instant according to the [Clock] of the room
+    #[inline]
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    /// Returns the random number generator of the room, see [`RoomRef::seed_rng`]
+    #[inline]
+    pub fn rng(&mut self) -> &mut StdRng {
+        self.rng
+    }
+
+    /// Queues a closure that will run once the handler has returned and the room has been
+    /// unlocked, see [`Context::defer`][crate::Context::defer]
+    pub fn defer(&mut self, f: impl FnOnce() + 'static) {
+        self.deferred.push(Box::new(f));
+    }
+
+    /// Number of members in the room
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    /// Whether the room is empty
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    /// Iterates over the members of the room and their identity
+    pub fn members(&self) -> impl Iterator<Item = (MemberId, &R::Guest)> {
+        self.members.iter().map(|member| (member.id, &member.guest))
+    }
+
+    /// Iterates over the members of the room and a mutable reference to their identity
+    pub fn members_mut(&mut self) -> impl Iterator<Item = (MemberId, &mut R::Guest)> {
+        self.members
+            .iter_mut()
+            .map(|member| (member.id, &mut member.guest))
+    }
+
+    /// Returns the identity of the member of the room identified by `id`, if it is in the room
+    pub fn identity(&mut self, id: MemberId) -> Option<&mut R::Guest> {
+        self.members
+            .iter_mut()
+            .find(|member| member.id == id)
+            .map(|member| &mut member.guest)
+    }
+
+    /// Returns a handle to the member of the room identified by `id`, if it is in the room
+    pub fn member(&self, id: MemberId) -> Option<MemberHandle> {
+        self.members
+            .iter()
+            .find(|member| member.id == id)
+            .map(MemberHandle::of)
+    }
+
+    /// Returns the last round-trip time measured with the member `id`, see
+    /// [`Context::rtt`][crate::Context::rtt]
+    pub fn rtt(&self, id: MemberId) -> Option<Duration> {
+        self.members
+            .iter()
+            .find(|member| member.id == id)
+            .and_then(|member| member.pulse.rtt())
+    }
+
+    /// Summarizes the round-trip times of the members of the room, see [`RoomRef::rtt_stats`]
+    pub fn rtt_stats(&self) -> RttStats {
+        RttStats::of(self.members)
+    }
+
+    /// Finds a member of the room whose identity matches `predicate`
+    pub fn find_member<F: FnMut(&R::Guest) -> bool>(
+        &self,
+        mut predicate: F,
+    ) -> Option<MemberHandle> {
+        self.members
+            .iter()
+            .find(|member| predicate(&member.guest))
+            .map(MemberHandle::of)
+    }
+
+    /// Sends a message to everyone in the room. Failures are handled like with
+    /// [`Context::broadcast`][crate::Context::broadcast].
+    pub fn broadcast(&self, msg: impl Into<Message>) -> ws::Result<()> {
+        if let Some(msg) = self.middleware.layer_broadcast(msg.into()) {
+            self.send_all(msg);
+        }
+
+        Ok(())
+    }
+
+    /// Like [`RoomContext::broadcast`], but the message is compressed (once) if it is large enough
+    /// and the room has [compression enabled][RoomRef::set_compression].
+    pub fn broadcast_compressible(&self, msg: impl Into<Message>) -> ws::Result<()> {
+        if let Some(msg) = self.middleware.layer_broadcast(msg.into()) {
+            self.send_all(match self.compression {
+                Some(compression) => compression.apply(msg),
+                None => msg,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn send_all(&self, msg: Message) {
+        self.members
+            .iter()
+            .for_each(|member| self.errors.send(member, msg.clone()));
+    }
+
+    /// Sends a message to the members of the room whose identity matches `predicate`, returning
+    /// how many there were, see [`RoomRef::send_where`]
+    pub(crate) fn send_where<F>(&self, mut predicate: F, msg: Message) -> usize
+    where
+        F: FnMut(&R::Guest) -> bool,
+    {
+        let mut sent = 0;
+        for member in self.members.iter().filter(|m| predicate(&m.guest)) {
+            self.errors.send(member, msg.clone());
+            sent += 1;
+        }
+        sent
+    }
+
+    /// Sends a message to everyone in the room by calling a closure for each member. Failures are
+    /// handled like with [`Context::broadcast`][crate::Context::broadcast].
+    pub fn broadcast_with<F: FnMut(&R::Guest) -> M, M: Into<Message>>(
+        &self,
+        mut f: F,
+    ) -> ws::Result<()> {
+        for member in self.members.iter() {
+            if let Some(msg) = self.middleware.layer_broadcast(f(&member.guest).into()) {
+                self.errors.send(member, msg);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sends a message to the member of the room identified by `id`.
+    ///
+    /// Fails if there is no such member in the room.
+    pub fn send_to(&self, id: MemberId, msg: impl Into<Message>) -> ws::Result<()> {
+        self.member_or_err(id)?.send(msg)
+    }
+
+    /// Kicks the member of the room identified by `id`, see [`MemberHandle::kick`].
+    ///
+    /// Fails if there is no such member in the room.
+    pub fn kick(
+        &self,
+        id: MemberId,
+        code: CloseCode,
+        reason: impl Into<Cow<'static, str>>,
+    ) -> ws::Result<()> {
+        self.member_or_err(id)?.kick(code, reason)
+    }
+
+    /// Moves the member of the room identified by `id` to another room, see
+    /// [`MemberHandle::relocate`].
+    ///
+    /// Fails if there is no such member in the room.
+    pub fn relocate(&self, id: MemberId, relocation: Relocation) -> ws::Result<()> {
+        self.member_or_err(id)?.relocate(relocation)
+    }
+
+    /// Moves the member of the room identified by `id` to another room after `delay`, e.g. to send
+    /// every player to a results room a few seconds after the end of a game.
+    ///
+    /// Like with [`Context::relocate_later`][crate::Context::relocate_later], the relocation is
+    /// dropped if the member leaves the room before then. Fails if there is no such member in the
+    /// room.
+    pub fn relocate_later(
+        &self,
+        id: MemberId,
+        relocation: Relocation,
+        delay: Duration,
+    ) -> ws::Result<TimerHandle> {
+        let action = Action::Relocate(Mutex::new(Some(relocation)));
+        self.member_or_err(id)?
+            .set_timeout(delay, action, Some(self.room.addr()))
+    }
+
+    /// Arms a timer on the member of the room identified by `id`, see
+    /// [`Context::set_member_timeout`][crate::Context::set_member_timeout].
+    ///
+    /// Fails if there is no such member in the room.
+    pub fn set_member_timeout(
+        &self,
+        id: MemberId,
+        delay: Duration,
+        token: Token,
+    ) -> ws::Result<TimerHandle> {
+        self.member_or_err(id)?
+            .set_timeout(delay, Action::Timeout(token), Some(self.room.addr()))
+    }
+
+    /// Iterates over the members of the room subscribed to the channel `name`, see
+    /// [`Context::join_channel`][crate::Context::join_channel]
+    pub fn channel_members<'a>(&'a self, name: &'a str) -> impl Iterator<Item = MemberId> + 'a {
+        channels::subscribers(self.members, name).map(|member| member.id)
+    }
+
+    /// Sends a message to the members of the room subscribed to the channel `name`, see
+    /// [`Context::broadcast_channel`][crate::Context::broadcast_channel]
+    pub fn broadcast_channel(&self, name: &str, msg: impl Into<Message>) -> ws::Result<()> {
+        if let Some(msg) = self.middleware.layer_broadcast(msg.into()) {
+            channels::subscribers(self.members, name)
+                .for_each(|member| self.errors.send(member, msg.clone()));
+        }
+
+        Ok(())
+    }
+
+    fn member_or_err(&self, id: MemberId) -> ws::Result<MemberHandle> {
+        self.member(id).ok_or_else(|| member::not_found(id))
+    }
+}
+
+impl<R: RoomHandler> Debug for RoomContext<'_, '_, R>
+where
+    R::Guest: Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RoomContext")
+            .field("members", &self.members)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<R: RoomHandler> Room<R> {
+    fn with_room_context<F: FnOnce(&mut R, RoomContext<R>) -> O, O>(
+        &mut self,
+        deferred: &mut Vec<Box<dyn FnOnce()>>,
+        errors: &SendErrors,
+        f: F,
+    ) -> O {
+        let cx = RoomContext {
+            room: &self.self_ref,
+            room_id: self.id,
+            metadata: &self.metadata,
+            members: &mut self.members,
+            deferred,
+            errors,
+            middleware: &self.middleware,
+            compression: self.compression.as_ref(),
+            clock: &*self.clock,
+            rng: &mut self.rng,
+        };
+
+        f(&mut self.handler, cx)
+    }
+
+    /// Like [`Room::dispatch`], for callbacks taking a [RoomContext]
+    pub(crate) fn dispatch_room<F: FnOnce(&mut R, RoomContext<R>) -> O, O>(
+        room: &Mutex<Self>,
+        f: F,
+    ) -> O {
+        let mut deferred = Vec::new();
+        let errors = SendErrors::default();
+        let output = {
+            let mut room = room.lock().unwrap();
+            let output = room.with_room_context(&mut deferred, &errors, f);
+            room.report_send_errors(&mut deferred, errors);
+            room.enforce_memory_limit();
+            output
+        };
+
+        deferred.into_iter().for_each(|f| f());
+
+        output
+    }
+
+    /// Calls [`RoomHandler::on_send_error`] for each failure recorded during a callback
+    pub(crate) fn report_send_errors(
+        &mut self,
+        deferred: &mut Vec<Box<dyn FnOnce()>>,
+        errors: SendErrors,
+    ) {
+        let ignored = SendErrors::default();
+
+        for (member, err) in errors.take() {
+            self.with_room_context(deferred, &ignored, |h, cx| h.on_send_error(cx, member, err));
+        }
+    }
+}
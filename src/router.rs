@@ -0,0 +1,172 @@
+//! Choosing the lobby of new connections from the path they request, see [Router].
+
+use crate::entrance::{Arrival, Arrivals};
+use crate::{Entrance, RoomAny, RoomHandler};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::rc::Rc;
+use std::sync::Arc;
+
+type Lobby = Box<dyn FnOnce() -> Arrivals + Send>;
+
+/// Lobbies keyed by the path of the request of new connections, so that a single hotel can serve
+/// unrelated features (`/chat`, `/game`…) with distinct rooms, and even distinct
+/// [RoomHandler]s.
+///
+/// Paths are compared exactly, without the query string of the request. Clients whose path doesn't
+/// match any route are put in the [fallback][Router::fallback] lobby, or are refused with a
+/// `404 Not Found` response if there is none.
+///
+/// ```no_run
+/// # use ws_hotel::*;
+/// # struct ChatLobby;
+/// # impl RoomHandler for ChatLobby {
+/// #     type Guest = ();
+/// #     fn on_message(&mut self, _: Context<Self>, _: Message) -> ResultRelocation { Ok(None) }
+/// # }
+/// # struct GameLobby;
+/// # impl RoomHandler for GameLobby {
+/// #     type Guest = u32;
+/// #     fn on_message(&mut self, _: Context<Self>, _: Message) -> ResultRelocation { Ok(None) }
+/// # }
+/// let router = Router::new()
+///     .route("/chat", ChatLobby)
+///     .route("/game", GameLobby);
+///
+/// ws_hotel::listen_routed("127.0.0.1:8080", router).unwrap();
+/// ```
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<(String, Lobby)>,
+    fallback: Option<Lobby>,
+    protocols: HashMap<String, Vec<String>>,
+
+    /// The room connections belong to until their request is routed
+    placeholder: Option<Arc<dyn RoomAny + Send + Sync>>,
+}
+
+impl Router {
+    /// Creates a router without any route
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Puts the clients requesting `path` (e.g. `/chat`) in `lobby`, replacing the previous lobby
+    /// of this path
+    pub fn route<I, R>(mut self, path: impl Into<String>, lobby: I) -> Self
+    where
+        I: Into<Entrance<R>>,
+        R: RoomHandler + Send + 'static,
+        R::Guest: Send + 'static,
+    {
+        let path = path.into();
+        let lobby = self.lobby(lobby.into());
+
+        match self.routes.iter_mut().find(|(p, _)| *p == path) {
+            Some((_, previous)) => *previous = lobby,
+            None => self.routes.push((path, lobby)),
+        }
+
+        self
+    }
+
+    /// Puts the clients whose path doesn't match any route in `lobby`
+    pub fn fallback<I, R>(mut self, lobby: I) -> Self
+    where
+        I: Into<Entrance<R>>,
+        R: RoomHandler + Send + 'static,
+        R::Guest: Send + 'static,
+    {
+        self.fallback = Some(self.lobby(lobby.into()));
+        self
+    }
+
+    /// Supports the subprotocol `name` for the clients requesting `path`, before the ones supported
+    /// by every lobby. See [`Builder::protocol`][crate::Builder::protocol].
+    pub fn protocol(mut self, path: impl Into<String>, name: impl Into<String>) -> Self {
+        self.protocols
+            .entry(path.into())
+            .or_default()
+            .push(name.into());
+        self
+    }
+
+    fn lobby<R>(&mut self, entrance: Entrance<R>) -> Lobby
+    where
+        R: RoomHandler + Send + 'static,
+        R::Guest: Send + 'static,
+    {
+        self.placeholder
+            .get_or_insert_with(|| Arc::clone(&entrance.room().0) as _);
+
+        Box::new(move || Box::new(entrance.into_arrivals()) as Arrivals)
+    }
+
+    /// Returns a function giving the routes to every new connection
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are no routes.
+    pub(crate) fn into_arrivals(self) -> Arrivals {
+        let placeholder = self.placeholder.expect("at least one route is required");
+        let routes = Rc::new(Routes {
+            routes: self
+                .routes
+                .into_iter()
+                .map(|(path, lobby)| (path, RefCell::new(lobby())))
+                .collect(),
+            fallback: self.fallback.map(|lobby| RefCell::new(lobby())),
+            protocols: self.protocols,
+        });
+
+        Box::new(move || {
+            let placeholder: Arc<dyn RoomAny> = Arc::clone(&placeholder) as _;
+            (placeholder, Arrival::OnRequest(Rc::clone(&routes)))
+        })
+    }
+}
+
+impl Debug for Router {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Router")
+            .field(
+                "routes",
+                &self.routes.iter().map(|(path, _)| path).collect::<Vec<_>>(),
+            )
+            .field("fallback", &self.fallback.is_some())
+            .field("protocols", &self.protocols)
+            .finish()
+    }
+}
+
+/// The routes of a running hotel
+pub(crate) struct Routes {
+    routes: Vec<(String, RefCell<Arrivals>)>,
+    fallback: Option<RefCell<Arrivals>>,
+    protocols: HashMap<String, Vec<String>>,
+}
+
+/// The path of `resource`, without its query string
+fn path(resource: &str) -> &str {
+    resource.split('?').next().unwrap_or(resource)
+}
+
+impl Routes {
+    /// Picks the lobby of a connection from the resource it requested, if a route matches it
+    pub(crate) fn resolve(&self, resource: &str) -> Option<(Arc<dyn RoomAny>, Arrival)> {
+        let path = path(resource);
+
+        let lobby = self.routes.iter().find(|(p, _)| p == path);
+        let lobby = lobby.map(|(_, lobby)| lobby).or(self.fallback.as_ref())?;
+
+        Some((lobby.borrow_mut())())
+    }
+
+    /// The subprotocols specific to the path of `resource`, see [`Router::protocol`]
+    pub(crate) fn protocols(&self, resource: &str) -> &[String] {
+        self.protocols
+            .get(path(resource))
+            .map_or(&[], Vec::as_slice)
+    }
+}
@@ -0,0 +1,509 @@
+//! Request/response exchanges on top of typed rooms, see [RpcHandler].
+
+use crate::typed::{Codec, TypedRoomHandler};
+use crate::{log_error, log_send_error, member};
+use crate::{
+    Context, Handshake, LeaveReason, MemberId, Request, Response, ResultRelocation, RoomAddr,
+    RoomContext, TimerHandle, Token,
+};
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::time::Duration;
+
+/// Token of the timers armed on the members a request was sent to, see [`Calls::with_timeout`].
+/// Rooms may use it too: timers that don't belong to a request are passed to
+/// [`RpcHandler::on_timeout`].
+const EXPIRE: Token = Token(usize::MAX - 103);
+
+/// Envelope of the messages exchanged by an [RpcHandler] room, encoded by its
+/// [Codec][RpcHandler::Codec].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Rpc<M> {
+    /// A request that expects a [Response][Rpc::Response] with the same `id`
+    Request { id: u64, body: M },
+
+    /// The answer to the [Request][Rpc::Request] with the same `id`
+    Response { id: u64, body: M },
+
+    /// A one-way message, that isn't answered
+    Notification(M),
+}
+
+type OnResponse<R> =
+    dyn FnOnce(&mut R, Context<R>, <R as RpcHandler>::Msg) -> ResultRelocation + Send;
+
+/// The requests sent by the server to members of an [RpcHandler] room that haven't been answered
+/// yet, returned by [`RpcHandler::calls`].
+pub struct Calls<R: RpcHandler> {
+    next: u64,
+    timeout: Option<Duration>,
+    pending: HashMap<u64, Call<R>>,
+}
+
+struct Call<R: RpcHandler> {
+    to: MemberId,
+    on_response: Box<OnResponse<R>>,
+
+    /// Forgets the request when it fires, see [`Calls::with_timeout`]
+    timer: Option<TimerHandle>,
+}
+
+impl<R: RpcHandler> Calls<R> {
+    /// Creates an empty set of requests, that wait for a response until their member leaves the
+    /// room
+    pub fn new() -> Self {
+        Self {
+            next: 0,
+            timeout: None,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Creates an empty set of requests, that are forgotten if they aren't answered within
+    /// `timeout`. [`RpcHandler::on_call_timeout`] is then called instead of their callback.
+    ///
+    /// Like the timers of [`Context::set_timeout`], timeouts always run in real time.
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self {
+            timeout: Some(timeout),
+            ..Self::new()
+        }
+    }
+
+    /// Sends a request to the member of the room identified by `to`, returning its id.
+    ///
+    /// `on_response` is called with the [Context] of that member once it answers. It is never
+    /// called if the member leaves the room first, or if the request times out. Fails if there is
+    /// no such member in the room.
+    pub fn call<F>(
+        &mut self,
+        cx: &Context<R>,
+        to: MemberId,
+        body: R::Msg,
+        on_response: F,
+    ) -> ws::Result<u64>
+    where
+        F: FnOnce(&mut R, Context<R>, R::Msg) -> ResultRelocation + Send + 'static,
+    {
+        let member = cx.member(to).ok_or_else(|| member::not_found(to))?;
+
+        let id = self.next;
+        self.next += 1;
+
+        let msg = R::Codec::encode(&Rpc::Request { id, body })?;
+
+        // Armed first, so that the request can't be answered before its timer exists
+        let timer = match self.timeout {
+            Some(timeout) => Some(cx.set_member_timeout(to, timeout, EXPIRE)?),
+            None => None,
+        };
+
+        let call = Call {
+            to,
+            on_response: Box::new(on_response),
+            timer,
+        };
+        if let Err(err) = member.send(msg) {
+            call.cancel();
+            return Err(err);
+        }
+
+        self.pending.insert(id, call);
+        Ok(id)
+    }
+
+    /// Number of requests waiting for a response
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Whether no request is waiting for a response
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Forgets about the requests sent to `member`
+    fn forget(&mut self, member: MemberId) {
+        self.pending.retain(|_, call| {
+            if call.to != member {
+                return true;
+            }
+
+            call.cancel();
+            false
+        });
+    }
+
+    /// Takes the callback of the request `id`, if it was sent to `from`
+    fn take(&mut self, id: u64, from: MemberId) -> Option<Box<OnResponse<R>>> {
+        match self.pending.get(&id) {
+            Some(call) if call.to == from => {
+                let call = self.pending.remove(&id)?;
+                call.cancel();
+                Some(call.on_response)
+            }
+            _ => None,
+        }
+    }
+
+    /// Forgets the request sent to `member` whose timer just fired, returning its id. Timers are
+    /// disarmed when they fire, while the ones of pending requests are all still armed otherwise.
+    fn expire(&mut self, member: MemberId) -> Option<u64> {
+        let (&id, _) = self.pending.iter().find(|(_, call)| {
+            call.to == member && call.timer.as_ref().is_some_and(|timer| !timer.is_armed())
+        })?;
+
+        self.pending.remove(&id);
+        Some(id)
+    }
+}
+
+impl<R: RpcHandler> Call<R> {
+    fn cancel(&self) {
+        if let Some(timer) = &self.timer {
+            timer.cancel();
+        }
+    }
+}
+
+impl<R: RpcHandler> Default for Calls<R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<R: RpcHandler> Debug for Calls<R> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Calls")
+            .field("timeout", &self.timeout)
+            .field("pending", &self.pending.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// A room that answers requests of its members and can send requests to them, matching requests
+/// and responses with the ids of the [Rpc] envelope.
+///
+/// Every [RpcHandler] is a [TypedRoomHandler] receiving [`Rpc<Self::Msg>`][Rpc]. Its other hooks
+/// are the same as the ones of [RoomHandler][crate::RoomHandler], with the same defaults, except
+/// for [`on_request`][crate::RoomHandler::on_request] which is called
+/// [`on_http_request`][RpcHandler::on_http_request].
+///
+/// ```
+/// # use ws_hotel::*;
+/// # use std::time::Duration;
+/// struct Echo(Calls<Self>);
+///
+/// impl Echo {
+///     fn new() -> Self {
+///         Self(Calls::with_timeout(Duration::from_secs(5)))
+///     }
+/// }
+///
+/// impl RpcHandler for Echo {
+///     type Guest = ();
+///     type Msg = String;
+///     type Codec = Envelope;
+///
+///     fn calls(&mut self) -> &mut Calls<Self> {
+///         &mut self.0
+///     }
+///
+///     fn on_request(&mut self, cx: Context<Self>, body: String) -> ws::Result<String> {
+///         // Asks the client for its name before answering
+///         self.0.call(&cx, cx.id(), "name?".to_owned(), |_, cx, name| {
+///             cx.send_encoded(&Rpc::Notification(format!("hello {}", name)))?;
+///             Ok(None)
+///         })?;
+///
+///         Ok(body)
+///     }
+///
+///     fn on_call_timeout(&mut self, cx: Context<Self>, _id: u64) -> ResultRelocation {
+///         cx.send_encoded(&Rpc::Notification("hello stranger".to_owned()))?;
+///         Ok(None)
+///     }
+/// }
+///
+/// /// Envelopes written as `request <id> <body>`, `response <id> <body>` or `notification <body>`
+/// struct Envelope;
+///
+/// impl Codec<Rpc<String>> for Envelope {
+///     fn decode(msg: Message) -> ws::Result<Rpc<String>> {
+///         let invalid = || ws::Error::new(ws::ErrorKind::Protocol, "invalid envelope");
+///
+///         let text = msg.into_text()?;
+///         let (kind, rest) = text.split_once(' ').ok_or_else(invalid)?;
+///         if kind == "notification" {
+///             return Ok(Rpc::Notification(rest.to_owned()));
+///         }
+///
+///         let (id, body) = rest.split_once(' ').ok_or_else(invalid)?;
+///         let id = id.parse().map_err(|_| invalid())?;
+///         let body = body.to_owned();
+///         match kind {
+///             "request" => Ok(Rpc::Request { id, body }),
+///             "response" => Ok(Rpc::Response { id, body }),
+///             _ => Err(invalid()),
+///         }
+///     }
+///
+///     fn encode(msg: &Rpc<String>) -> ws::Result<Message> {
+///         let text = match msg {
+///             Rpc::Request { id, body } => format!("request {} {}", id, body),
+///             Rpc::Response { id, body } => format!("response {} {}", id, body),
+///             Rpc::Notification(body) => format!("notification {}", body),
+///         };
+///         Ok(Message::Text(text))
+///     }
+/// }
+///
+/// let request = Envelope::decode(Message::from("request 7 hi there"))?;
+/// assert_eq!(request, Rpc::Request { id: 7, body: "hi there".to_owned() });
+/// assert_eq!(Envelope::encode(&request)?, Message::from("request 7 hi there"));
+/// # Ok::<(), ws::Error>(())
+/// ```
+pub trait RpcHandler: Sized {
+    /// See [`RoomHandler::Guest`][crate::RoomHandler::Guest]
+    type Guest;
+
+    /// Type of the body of requests, responses and notifications
+    type Msg;
+
+    /// How envelopes are decoded and encoded
+    type Codec: Codec<Rpc<Self::Msg>>;
+
+    /// Requests sent by the server that are waiting for a response, usually stored in a field of
+    /// the handler
+    fn calls(&mut self) -> &mut Calls<Self>;
+
+    /// See [`RoomHandler::on_request`][crate::RoomHandler::on_request]
+    fn on_http_request(&mut self, _cx: Context<Self>, request: &Request) -> ws::Result<Response> {
+        Response::from_request(request)
+    }
+
+    /// See [`RoomHandler::on_open`][crate::RoomHandler::on_open]
+    fn on_open(&mut self, _cx: Context<Self>, _handshake: &Handshake) -> ResultRelocation {
+        Ok(None)
+    }
+
+    /// See [`RoomHandler::on_join`][crate::RoomHandler::on_join]
+    fn on_join(&mut self, _cx: Context<Self>) -> ResultRelocation {
+        Ok(None)
+    }
+
+    /// Called with the body of each request sent by a member. The returned body is sent back to
+    /// it as the response, with the id of the request. Errors close the connection.
+    fn on_request(&mut self, cx: Context<Self>, body: Self::Msg) -> ws::Result<Self::Msg>;
+
+    /// Called with the body of each notification sent by a member. Ignores them by default.
+    fn on_notification(&mut self, _cx: Context<Self>, _body: Self::Msg) -> ResultRelocation {
+        Ok(None)
+    }
+
+    /// Called with the [Context] of a member instead of the callback of the request `id` it was
+    /// sent, if it didn't answer in time, see [`Calls::with_timeout`]. Ignores them by default.
+    fn on_call_timeout(&mut self, _cx: Context<Self>, _id: u64) -> ResultRelocation {
+        Ok(None)
+    }
+
+    /// See [`TypedRoomHandler::accepts`]. Requests, responses and notifications are all checked.
+    fn accepts(&self, _msg: &Rpc<Self::Msg>) -> bool {
+        true
+    }
+
+    /// See [`TypedRoomHandler::on_decode_error`]
+    fn on_decode_error(&mut self, _cx: Context<Self>, err: ws::Error) -> ResultRelocation {
+        Err(err)
+    }
+
+    /// See [`RoomHandler::on_ping`][crate::RoomHandler::on_ping]
+    fn on_ping(&mut self, _cx: Context<Self>, _data: &[u8]) -> ResultRelocation {
+        Ok(None)
+    }
+
+    /// See [`RoomHandler::on_pong`][crate::RoomHandler::on_pong]
+    fn on_pong(&mut self, _cx: Context<Self>, _data: &[u8]) -> ResultRelocation {
+        Ok(None)
+    }
+
+    /// See [`RoomHandler::on_leave`][crate::RoomHandler::on_leave]. Pending requests sent to the
+    /// member are forgotten before it is called.
+    fn on_leave(&mut self, _cx: Context<Self>, _reason: LeaveReason) {}
+
+    /// See [`RoomHandler::on_relocate_in`][crate::RoomHandler::on_relocate_in]
+    fn on_relocate_in(&mut self, cx: Context<Self>, _from: RoomAddr) -> ResultRelocation {
+        RpcHandler::on_join(self, cx)
+    }
+
+    /// See [`RoomHandler::on_relocate_out`][crate::RoomHandler::on_relocate_out]. Pending
+    /// requests sent to the member are forgotten before it is called.
+    fn on_relocate_out(&mut self, cx: Context<Self>, to: RoomAddr) {
+        RpcHandler::on_leave(self, cx, LeaveReason::Relocated { to })
+    }
+
+    /// See [`RoomHandler::on_first_join`][crate::RoomHandler::on_first_join]
+    fn on_first_join(&mut self, _cx: RoomContext<Self>) {}
+
+    /// See [`RoomHandler::on_room_empty`][crate::RoomHandler::on_room_empty]
+    fn on_room_empty(&mut self, _cx: RoomContext<Self>) {}
+
+    /// See [`RoomHandler::on_shutdown`][crate::RoomHandler::on_shutdown]
+    fn on_shutdown(&mut self, _cx: RoomContext<Self>) {}
+
+    /// See [`RoomHandler::on_send_error`][crate::RoomHandler::on_send_error]
+    fn on_send_error(&mut self, _cx: RoomContext<Self>, member: MemberId, err: ws::Error) {
+        log_send_error(member, &err);
+    }
+
+    /// See [`RoomHandler::on_replace`][crate::RoomHandler::on_replace]
+    fn on_replace(&mut self, _cx: RoomContext<Self>, _previous: &mut Self) {}
+
+    /// See [`RoomHandler::on_tick`][crate::RoomHandler::on_tick]
+    fn on_tick(&mut self, _cx: RoomContext<Self>) {}
+
+    /// See [`RoomHandler::on_expire`][crate::RoomHandler::on_expire]
+    fn on_expire(&mut self, _cx: RoomContext<Self>) {}
+
+    /// See [`RoomHandler::on_slow_consumer`][crate::RoomHandler::on_slow_consumer]
+    fn on_slow_consumer(&mut self, _cx: Context<Self>, _backlog: usize) {}
+
+    /// See [`RoomHandler::on_timeout`][crate::RoomHandler::on_timeout]
+    fn on_timeout(&mut self, _cx: Context<Self>, _token: Token) -> ResultRelocation {
+        Ok(None)
+    }
+
+    /// See [`RoomHandler::on_error`][crate::RoomHandler::on_error]
+    fn on_error(&mut self, cx: Context<Self>, err: ws::Error) {
+        log_error(cx.id(), &err);
+    }
+
+    /// See [`RoomHandler::on_relocation_refused`][crate::RoomHandler::on_relocation_refused]
+    fn on_relocation_refused(
+        &mut self,
+        _cx: Context<Self>,
+        _to: RoomAddr,
+        _err: ws::Error,
+    ) -> ResultRelocation {
+        Ok(None)
+    }
+}
+
+impl<T: RpcHandler> TypedRoomHandler for T {
+    type Guest = T::Guest;
+    type Msg = Rpc<T::Msg>;
+    type Codec = T::Codec;
+
+    fn on_request(&mut self, cx: Context<Self>, request: &Request) -> ws::Result<Response> {
+        RpcHandler::on_http_request(self, cx, request)
+    }
+
+    fn on_open(&mut self, cx: Context<Self>, handshake: &Handshake) -> ResultRelocation {
+        RpcHandler::on_open(self, cx, handshake)
+    }
+
+    fn on_join(&mut self, cx: Context<Self>) -> ResultRelocation {
+        RpcHandler::on_join(self, cx)
+    }
+
+    fn on_message(&mut self, mut cx: Context<Self>, msg: Rpc<T::Msg>) -> ResultRelocation {
+        match msg {
+            Rpc::Request { id, body } => {
+                let body = RpcHandler::on_request(self, cx.reborrow(), body)?;
+                cx.send_encoded(&Rpc::Response { id, body })?;
+                Ok(None)
+            }
+            Rpc::Response { id, body } => match self.calls().take(id, cx.id()) {
+                Some(on_response) => on_response(self, cx, body),
+                None => Ok(None),
+            },
+            Rpc::Notification(body) => self.on_notification(cx, body),
+        }
+    }
+
+    fn accepts(&self, msg: &Rpc<T::Msg>) -> bool {
+        RpcHandler::accepts(self, msg)
+    }
+
+    fn on_decode_error(&mut self, cx: Context<Self>, err: ws::Error) -> ResultRelocation {
+        RpcHandler::on_decode_error(self, cx, err)
+    }
+
+    fn on_ping(&mut self, cx: Context<Self>, data: &[u8]) -> ResultRelocation {
+        RpcHandler::on_ping(self, cx, data)
+    }
+
+    fn on_pong(&mut self, cx: Context<Self>, data: &[u8]) -> ResultRelocation {
+        RpcHandler::on_pong(self, cx, data)
+    }
+
+    fn on_leave(&mut self, cx: Context<Self>, reason: LeaveReason) {
+        self.calls().forget(cx.id());
+        RpcHandler::on_leave(self, cx, reason)
+    }
+
+    fn on_relocate_in(&mut self, cx: Context<Self>, from: RoomAddr) -> ResultRelocation {
+        RpcHandler::on_relocate_in(self, cx, from)
+    }
+
+    fn on_relocate_out(&mut self, cx: Context<Self>, to: RoomAddr) {
+        self.calls().forget(cx.id());
+        RpcHandler::on_relocate_out(self, cx, to)
+    }
+
+    fn on_first_join(&mut self, cx: RoomContext<Self>) {
+        RpcHandler::on_first_join(self, cx)
+    }
+
+    fn on_room_empty(&mut self, cx: RoomContext<Self>) {
+        RpcHandler::on_room_empty(self, cx)
+    }
+
+    fn on_shutdown(&mut self, cx: RoomContext<Self>) {
+        RpcHandler::on_shutdown(self, cx)
+    }
+
+    fn on_send_error(&mut self, cx: RoomContext<Self>, member: MemberId, err: ws::Error) {
+        RpcHandler::on_send_error(self, cx, member, err)
+    }
+
+    fn on_replace(&mut self, cx: RoomContext<Self>, previous: &mut Self) {
+        RpcHandler::on_replace(self, cx, previous)
+    }
+
+    fn on_tick(&mut self, cx: RoomContext<Self>) {
+        RpcHandler::on_tick(self, cx)
+    }
+
+    fn on_expire(&mut self, cx: RoomContext<Self>) {
+        RpcHandler::on_expire(self, cx)
+    }
+
+    fn on_slow_consumer(&mut self, cx: Context<Self>, backlog: usize) {
+        RpcHandler::on_slow_consumer(self, cx, backlog)
+    }
+
+    fn on_timeout(&mut self, cx: Context<Self>, token: Token) -> ResultRelocation {
+        if token != EXPIRE {
+            return RpcHandler::on_timeout(self, cx, token);
+        }
+
+        match self.calls().expire(cx.id()) {
+            Some(id) => self.on_call_timeout(cx, id),
+            None => RpcHandler::on_timeout(self, cx, token),
+        }
+    }
+
+    fn on_error(&mut self, cx: Context<Self>, err: ws::Error) {
+        RpcHandler::on_error(self, cx, err)
+    }
+
+    fn on_relocation_refused(
+        &mut self,
+        cx: Context<Self>,
+        to: RoomAddr,
+        err: ws::Error,
+    ) -> ResultRelocation {
+        RpcHandler::on_relocation_refused(self, cx, to, err)
+    }
+}
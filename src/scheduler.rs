@@ -0,0 +1,211 @@
+//! Background jobs run by a hotel, see [`Hotel::schedule`].
+
+use crate::calendar::Epoch;
+use crate::{Calendar, Clock, Hotel, Missed, RoomHandler, Rooms};
+use std::fmt::{Debug, Formatter};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+type JobFn = dyn FnMut(&Rooms) + Send;
+
+/// How often the thread running the jobs checks whether they are due, so that it notices when the
+/// hotel stops or its [Clock] is moved forward by a test
+pub(crate) const POLL: Duration = Duration::from_millis(50);
+
+struct Job {
+    when: When,
+    run: Box<JobFn>,
+}
+
+/// When a job runs
+enum When {
+    /// At a fixed interval, see [`Hotel::schedule`]
+    Every(Duration),
+
+    /// At fixed times, see [`Hotel::schedule_at`]
+    At(Calendar, Missed),
+}
+
+impl When {
+    /// When the job runs next, if it last ran or was due at `now`
+    fn next(&self, epoch: &Epoch, now: Instant) -> Instant {
+        match self {
+            Self::Every(every) => now + *every,
+            Self::At(calendar, _) => calendar.next_after(epoch, now),
+        }
+    }
+
+    /// How many times the job due at `at` runs when the scheduler gets to it at `now`
+    fn runs(&self, epoch: &Epoch, at: Instant, now: Instant) -> u64 {
+        match self {
+            Self::Every(_) => 1,
+            Self::At(calendar, missed) => calendar.runs(*missed, epoch, at, now),
+        }
+    }
+}
+
+/// The jobs registered on a hotel with [`Hotel::schedule`]
+#[derive(Default)]
+pub(crate) struct Jobs(Vec<Job>);
+
+impl Jobs {
+    /// Runs the jobs in a background thread until the returned value is dropped, or nothing if
+    /// there are no jobs
+    pub(crate) fn start(self, rooms: Arc<Rooms>, clock: Arc<dyn Clock>) -> Option<Running> {
+        if self.0.is_empty() {
+            return None;
+        }
+
+        let (running, stopped) = mpsc::channel::<()>();
+        let mut jobs = self.0;
+
+        thread::spawn(move || {
+            let epoch = Epoch::now(&*clock);
+            let start = clock.now();
+            let mut next = jobs
+                .iter()
+                .map(|job| job.when.next(&epoch, start))
+                .collect::<Vec<_>>();
+
+            loop {
+                let (index, at) = next
+                    .iter()
+                    .copied()
+                    .enumerate()
+                    .min_by_key(|(_, at)| *at)
+                    .unwrap();
+
+                while clock.now() < at {
+                    let wait = at.saturating_duration_since(clock.now()).min(POLL);
+                    match stopped.recv_timeout(wait) {
+                        Err(RecvTimeoutError::Timeout) => {}
+                        _ => return,
+                    }
+                }
+
+                let job = &mut jobs[index];
+                let reached = clock.now();
+                for _ in 0..job.when.runs(&epoch, at, reached) {
+                    (job.run)(&rooms);
+                }
+
+                next[index] = match job.when {
+                    When::Every(_) => job.when.next(&epoch, clock.now().max(at)),
+                    When::At(..) => job.when.next(&epoch, reached),
+                };
+            }
+        });
+
+        Some(Running { _stop: running })
+    }
+}
+
+impl Debug for Jobs {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Jobs").field(&self.0.len()).finish()
+    }
+}
+
+/// Stops the thread running the jobs of a hotel when dropped
+pub(crate) struct Running {
+    _stop: mpsc::Sender<()>,
+}
+
+impl<R: RoomHandler, S> Hotel<R, S> {
+    /// Runs `job` with the [Rooms] of the hotel every `every`, from the time the hotel starts
+    /// until it stops.
+    ///
+    /// Jobs run one after the other in a thread of their own, that never holds the lock of a room
+    /// in between. Unlike room callbacks, they can thus lock any room (e.g. with
+    /// [`RoomRef::with`][crate::RoomRef::with]) without deadlocking, as long as they lock one at a
+    /// time. If a job takes longer than its interval, its next runs are delayed rather than run
+    /// back to back, and so are the other jobs.
+    ///
+    /// ```no_run
+    /// # use ws_hotel::*;
+    /// # use std::time::{Duration, Instant};
+    /// # struct Lobby;
+    /// # impl RoomHandler for Lobby {
+    /// #     type Guest = ();
+    /// #     fn on_message(&mut self, _: Context<Self>, _: Message) -> ResultRelocation { Ok(None) }
+    /// # }
+    /// Hotel::new(Lobby)
+    ///     .schedule(Duration::from_secs(5), |rooms| {
+    ///         println!("{} rooms: {:?}", rooms.len(), rooms.names());
+    ///     })
+    ///     .run("127.0.0.1:8080")
+    ///     .unwrap();
+    /// ```
+    pub fn schedule<F>(mut self, every: Duration, job: F) -> Self
+    where
+        F: FnMut(&Rooms) + Send + 'static,
+    {
+        self.jobs.0.push(Job {
+            when: When::Every(every),
+            run: Box::new(job),
+        });
+        self
+    }
+
+    /// Like [`Hotel::schedule`], at the times of `calendar`, see [`Hotel::schedule_at`]
+    pub(crate) fn schedule_when<F>(mut self, calendar: Calendar, missed: Missed, job: F) -> Self
+    where
+        F: FnMut(&Rooms) + Send + 'static,
+    {
+        self.jobs.0.push(Job {
+            when: When::At(calendar, missed),
+            run: Box::new(job),
+        });
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::ManualClock;
+    use crate::{Context, Message, ResultRelocation, Room};
+
+    struct Lobby;
+
+    impl RoomHandler for Lobby {
+        type Guest = ();
+
+        fn on_message(&mut self, _cx: Context<Self>, _msg: Message) -> ResultRelocation {
+            Ok(None)
+        }
+    }
+
+    /// Long enough for the thread running the jobs to notice that the clock moved
+    const SETTLE: Duration = Duration::from_millis(4 * POLL.as_millis() as u64);
+
+    #[test]
+    fn runs_jobs_as_the_clock_advances() {
+        let clock = ManualClock::new();
+        let (ran, runs) = mpsc::channel();
+        let hotel = Hotel::new(Room::new(Lobby)).schedule(Duration::from_secs(60), move |_| {
+            ran.send(()).unwrap();
+        });
+        let running = hotel.jobs.start(Arc::default(), Arc::new(clock.clone()));
+
+        assert!(runs.recv_timeout(SETTLE).is_err());
+
+        clock.advance(Duration::from_secs(59));
+        assert!(runs.recv_timeout(SETTLE).is_err());
+
+        clock.advance(Duration::from_secs(1));
+        assert!(runs.recv_timeout(SETTLE).is_ok());
+        assert!(runs.recv_timeout(SETTLE).is_err());
+
+        // Missed intervals are delayed rather than run back to back
+        clock.advance(Duration::from_secs(180));
+        assert!(runs.recv_timeout(SETTLE).is_ok());
+        assert!(runs.recv_timeout(SETTLE).is_err());
+
+        drop(running);
+        clock.advance(Duration::from_secs(60));
+        assert!(runs.recv_timeout(SETTLE).is_err());
+    }
+}
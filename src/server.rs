@@ -0,0 +1,840 @@
+//! Starting and controlling a hotel, see [Builder] and [ServerHandle].
+
+use crate::connection::Connection;
+use crate::entrance::{Arrival, Arrivals};
+use crate::hotel::SharedState;
+use crate::keepalive::Keepalive;
+use crate::per_ip::{IpKey, PerIp};
+use crate::proxy::TrustedProxies;
+use crate::slow::SlowConsumers;
+use crate::{
+    Balance, Entrance, Handler, Handshake, IpRange, MemberId, RoomAny, RoomHandler, RoomRef,
+    Router, SlowConsumer,
+};
+use std::fmt::{Debug, Formatter};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use ws::{CloseCode, Request, Response, Sender};
+
+/// State shared by the connections of a hotel and its [ServerHandle]s
+#[derive(Debug)]
+pub(crate) struct ServerState {
+    /// Identifies this run of the hotel, so that rooms are told about each of its shutdowns once,
+    /// see [`RoomHandler::on_shutdown`][crate::RoomHandler::on_shutdown]
+    pub(crate) run: u64,
+
+    /// Number of connections whose handler is alive, including the ones being refused
+    connections: AtomicUsize,
+    accepting: AtomicBool,
+
+    /// Whether connections are being closed by [`ServerHandle::shutdown_gracefully`]
+    shutting_down: AtomicBool,
+
+    /// Number of connections above which new ones are refused, see [`Builder::connection_limit`]
+    limit: Option<usize>,
+
+    /// How refused connections are turned away
+    pub(crate) rejection: Rejection,
+
+    /// Connections of each IP, see [`Builder::connections_per_ip`]
+    pub(crate) per_ip: Option<PerIp>,
+
+    pub(crate) proxies: TrustedProxies,
+
+    /// Subprotocols supported by every lobby, by order of preference
+    pub(crate) protocols: Vec<String>,
+
+    /// Path of the health check, see [`Builder::health_check`]
+    health_check: Option<String>,
+
+    http: Option<HttpHandler>,
+
+    /// How connections are pinged, see [`Builder::keepalive`]
+    pub(crate) keepalive: Option<Keepalive>,
+
+    /// How clients that stopped reading are handled, see [`Builder::slow_consumers`]
+    pub(crate) slow_consumers: Option<SlowConsumers>,
+
+    /// Given to each connection, see [`Hotel::with_state`][crate::Hotel::with_state]
+    state: Option<SharedState>,
+}
+
+impl ServerState {
+    /// Forgets about a connection whose handler was dropped
+    pub(crate) fn disconnected(&self) {
+        self.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Whether the hotel is shutting down gracefully, so that the connections that are closed
+    /// leave their room because of the shutdown
+    pub(crate) fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::Relaxed)
+    }
+
+    /// The response to `request` if it is a plain HTTP request rather than a WebSocket handshake,
+    /// and the hotel answers these, see [`Builder::health_check`] and [`Builder::http`]
+    pub(crate) fn http_response(&self, request: &Request) -> Option<Response> {
+        if request.header("upgrade").is_some() {
+            return None;
+        }
+
+        let path = request.resource().split('?').next();
+        if request.method() == "GET" && path.is_some() && path == self.health_check.as_deref() {
+            return Some(self.health());
+        }
+
+        self.http.as_ref().map(|http| (http.0)(request))
+    }
+
+    /// The response to the health check: `200 OK` while connections are accepted, `503 Service
+    /// Unavailable` otherwise, with the numbers of connections and [rooms][crate::live_rooms] as
+    /// JSON
+    fn health(&self) -> Response {
+        let accepting = self.accepting.load(Ordering::Relaxed);
+        let (status, reason) = match accepting {
+            true => (200, "OK"),
+            false => (503, "Service Unavailable"),
+        };
+
+        let body = format!(
+            r#"{{"accepting":{},"connections":{},"rooms":{}}}"#,
+            accepting,
+            // Without the connection of the health check itself
+            self.connections.load(Ordering::Relaxed).saturating_sub(1),
+            crate::live_rooms(),
+        );
+
+        let mut response = Response::new(status, reason, body.into_bytes());
+        response
+            .headers_mut()
+            .push(("Content-Type".into(), b"application/json".to_vec()));
+        response
+    }
+}
+
+type HttpFn = dyn Fn(&Request) -> Response + Send + Sync;
+
+/// Answers plain HTTP requests, see [`Builder::http`]
+#[derive(Clone)]
+struct HttpHandler(Arc<HttpFn>);
+
+impl Debug for HttpHandler {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("HttpHandler").finish_non_exhaustive()
+    }
+}
+
+/// How the hotel turns away the connections it refuses, because it
+/// [stopped accepting][ServerHandle::stop_accepting] them or because it has reached its
+/// [connection limit][Builder::connection_limit]. See [`Builder::rejection`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Rejection {
+    /// Answers the handshake with an HTTP response, `503 Service Unavailable` by default
+    Response { status: u16, reason: String },
+
+    /// Completes the handshake, then closes the connection right away, for clients that can't
+    /// tell why a handshake failed
+    Close { code: CloseCode, reason: String },
+}
+
+impl Rejection {
+    /// The response to the handshake of a refused connection
+    pub(crate) fn response(&self, request: &Request) -> ws::Result<Response> {
+        match self {
+            Self::Response { status, reason } => {
+                Ok(Response::new(*status, reason.as_str(), Vec::new()))
+            }
+            Self::Close { .. } => Response::from_request(request),
+        }
+    }
+
+    /// Closes the connection of a refused client once its handshake is complete, if needed
+    pub(crate) fn close(&self, sender: &Sender) -> ws::Result<()> {
+        match self {
+            Self::Response { .. } => Ok(()),
+            Self::Close { code, reason } => sender.close_with_reason(*code, reason.clone()),
+        }
+    }
+}
+
+impl Default for Rejection {
+    fn default() -> Self {
+        Self::Response {
+            status: 503,
+            reason: "Service Unavailable".to_owned(),
+        }
+    }
+}
+
+/// The response sent to clients whose path doesn't match any route of the [Router]
+pub(crate) fn not_found() -> Response {
+    Response::new(404, "Not Found", Vec::new())
+}
+
+/// Confirms the first subprotocol of `supported` that the client asked for in `request`, unless
+/// `response` already has one
+pub(crate) fn negotiate<'a>(
+    request: &Request,
+    response: &mut Response,
+    supported: impl IntoIterator<Item = &'a String>,
+) -> ws::Result<()> {
+    if response.status() != 101 || response.protocol()?.is_some() {
+        return Ok(());
+    }
+
+    let requested = request.protocols()?;
+    if let Some(protocol) = supported
+        .into_iter()
+        .find(|protocol| requested.contains(&protocol.as_str()))
+    {
+        response.set_protocol(protocol);
+    }
+
+    Ok(())
+}
+
+/// A handle to a running hotel, to stop it or to inspect it from another thread.
+///
+/// It is obtained with [`listen_with`][crate::listen_with] or [`spawn`][crate::spawn], and can be
+/// cloned freely.
+#[derive(Clone)]
+pub struct ServerHandle {
+    state: Arc<ServerState>,
+    broadcaster: Sender,
+    local_addr: SocketAddr,
+}
+
+impl ServerHandle {
+    /// Stops the hotel: every member leaves its room with
+    /// [`LeaveReason::ServerShutdown`][crate::LeaveReason::ServerShutdown], and the function that
+    /// started the hotel returns.
+    pub fn shutdown(&self) -> ws::Result<()> {
+        self.broadcaster.shutdown()
+    }
+
+    /// Stops the hotel gracefully: new connections are refused, and every open connection is closed
+    /// with a close frame (`1001 Going Away`) while the hotel keeps running, so that clients
+    /// see a proper close instead of an abrupt `1006`. Members leave their room with
+    /// [`LeaveReason::ServerShutdown`][crate::LeaveReason::ServerShutdown] as their close
+    /// handshake completes.
+    ///
+    /// Blocks until every connection is closed or `timeout` has elapsed, then
+    /// [shuts the hotel down][ServerHandle::shutdown], which drops the remaining connections.
+    pub fn shutdown_gracefully(&self, timeout: Duration) -> ws::Result<()> {
+        self.shutdown_gracefully_with(CloseCode::Away, "server shutting down", timeout)
+    }
+
+    /// Like [`ServerHandle::shutdown_gracefully`], with the code and the reason of the close frames
+    pub fn shutdown_gracefully_with(
+        &self,
+        code: CloseCode,
+        reason: &str,
+        timeout: Duration,
+    ) -> ws::Result<()> {
+        let deadline = Instant::now() + timeout;
+
+        self.stop_accepting();
+        self.state.shutting_down.store(true, Ordering::Relaxed);
+        self.broadcaster
+            .close_with_reason(code, reason.to_owned())?;
+        self.wait_for_connections(deadline, |_| {});
+
+        self.shutdown()
+    }
+
+    /// Drains the hotel: new connections are refused, while the existing ones are served until
+    /// they disconnect by themselves, e.g. during a rolling deployment behind a load balancer
+    /// (whose [health check][Builder::health_check] then fails).
+    ///
+    /// Blocks until every connection is closed or `timeout` has elapsed, calling `progress` with
+    /// the number of remaining connections whenever it changes. Returns the number of connections
+    /// that are still open, which can then be closed with [`ServerHandle::shutdown_gracefully`].
+    ///
+    /// ```no_run
+    /// # use ws_hotel::*;
+    /// # use std::time::Duration;
+    /// # struct Lobby;
+    /// # impl RoomHandler for Lobby {
+    /// #     type Guest = ();
+    /// #     fn on_message(&mut self, _: Context<Self>, _: Message) -> ResultRelocation { Ok(None) }
+    /// # }
+    /// let (server, thread) = ws_hotel::spawn("127.0.0.1:8080", Lobby).unwrap();
+    /// // …
+    /// server.drain(Duration::from_secs(60), |remaining| {
+    ///     println!("{} connections left", remaining);
+    /// });
+    /// server.shutdown_gracefully(Duration::from_secs(5)).unwrap();
+    /// thread.join().unwrap().unwrap();
+    /// ```
+    pub fn drain<F: FnMut(usize)>(&self, timeout: Duration, progress: F) -> usize {
+        self.stop_accepting();
+        self.wait_for_connections(Instant::now() + timeout, progress)
+    }
+
+    /// Waits until every connection is closed or `deadline` has passed, and returns the number of
+    /// remaining connections
+    fn wait_for_connections<F: FnMut(usize)>(&self, deadline: Instant, mut progress: F) -> usize {
+        let mut remaining = self.connections();
+        progress(remaining);
+
+        while remaining > 0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+
+            let connections = self.connections();
+            if connections != remaining {
+                remaining = connections;
+                progress(remaining);
+            }
+        }
+
+        remaining
+    }
+
+    /// See [`ServerState::run`]
+    pub(crate) fn run(&self) -> u64 {
+        self.state.run
+    }
+
+    /// Number of open connections
+    pub fn connections(&self) -> usize {
+        self.state.connections.load(Ordering::Relaxed)
+    }
+
+    /// Refuses new connections (with a `503 Service Unavailable` response by default, see
+    /// [`Builder::rejection`]) from now on, while the existing ones are kept until they disconnect
+    /// or the hotel is shut down.
+    pub fn stop_accepting(&self) {
+        self.state.accepting.store(false, Ordering::Relaxed);
+    }
+
+    /// Accepts new connections again, after [`ServerHandle::stop_accepting`]
+    pub fn resume_accepting(&self) {
+        self.state.accepting.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether new connections are accepted, see [`ServerHandle::stop_accepting`]
+    pub fn is_accepting(&self) -> bool {
+        self.state.accepting.load(Ordering::Relaxed)
+    }
+
+    /// The address the hotel is listening on, e.g. to find the port that was picked when binding
+    /// to port `0`
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Debug for ServerHandle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ServerHandle")
+            .field("local_addr", &self.local_addr)
+            .field("connections", &self.connections())
+            .field("accepting", &self.is_accepting())
+            .finish()
+    }
+}
+
+/// Configures a hotel before starting it, for instance to raise the limits of the underlying `ws`
+/// server which are low by default (100 simultaneous connections).
+///
+/// The free functions [`listen`][crate::listen], [`listen_with`][crate::listen_with],
+/// [`listen_balanced`][crate::listen_balanced], [`listen_routed`][crate::listen_routed] and
+/// [`spawn`][crate::spawn] start a hotel with the default configuration.
+///
+/// ```no_run
+/// # use ws_hotel::*;
+/// # struct Lobby;
+/// # impl RoomHandler for Lobby {
+/// #     type Guest = ();
+/// #     fn on_message(&mut self, _: Context<Self>, _: Message) -> ResultRelocation { Ok(None) }
+/// # }
+/// Builder::new()
+///     .max_connections(10_000)
+///     .queue_size(64)
+///     .listen("127.0.0.1:8080", Lobby)
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Builder {
+    settings: ws::Settings,
+    protocols: Vec<String>,
+    limit: Option<usize>,
+    rejection: Rejection,
+    per_ip: Option<usize>,
+    ip_key: Option<IpKey>,
+    proxies: TrustedProxies,
+    health_check: Option<String>,
+    http: Option<HttpHandler>,
+    keepalive: Option<Keepalive>,
+    slow_consumers: Option<SlowConsumers>,
+    #[cfg(all(feature = "signals", unix))]
+    pub(crate) signals: Option<Duration>,
+    /// See [`Hotel::with_state`][crate::Hotel::with_state]
+    pub(crate) state: Option<SharedState>,
+}
+
+impl Builder {
+    /// Creates a builder with the default configuration
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces every setting of the underlying `ws` server at once
+    pub fn with_settings(mut self, settings: ws::Settings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    /// The settings of the underlying `ws` server
+    pub fn settings(&self) -> &ws::Settings {
+        &self.settings
+    }
+
+    /// Maximum number of simultaneous connections, see [`ws::Settings::max_connections`]
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.settings.max_connections = max;
+        self
+    }
+
+    /// Number of events that can be queued for each connection, which bounds how many messages
+    /// can be sent to a client in a single callback, see [`ws::Settings::queue_size`]
+    pub fn queue_size(mut self, size: usize) -> Self {
+        self.settings.queue_size = size;
+        self
+    }
+
+    /// Refuses new connections while there are `max` of them, so that a traffic spike degrades
+    /// gracefully. Refused connections are turned away as configured with [`Builder::rejection`].
+    ///
+    /// Refused connections still need a slot in the underlying `ws` server for the time they are
+    /// turned away, so its [`max_connections`][Builder::max_connections] is raised to twice `max`
+    /// if it is lower: connections above that are dropped by `ws` without any response.
+    pub fn connection_limit(mut self, max: usize) -> Self {
+        self.limit = Some(max);
+        self.settings.max_connections = self.settings.max_connections.max(max.saturating_mul(2));
+        self
+    }
+
+    /// How refused connections are turned away, see [Rejection]
+    pub fn rejection(mut self, rejection: Rejection) -> Self {
+        self.rejection = rejection;
+        self
+    }
+
+    /// Refuses the connections of clients that already have `max` open connections, so that a
+    /// single client can't exhaust the connections of the hotel.
+    ///
+    /// Clients are identified by their address (see [`Builder::trusted_proxy`]), or by the one
+    /// returned by the function given to [`Builder::ip_key`]. Since it is only known once the
+    /// handshake is complete, refused connections are closed with [`CloseCode::Policy`] before
+    /// [`RoomHandler::on_open`][crate::RoomHandler::on_open] is called.
+    pub fn connections_per_ip(mut self, max: usize) -> Self {
+        self.per_ip = Some(max);
+        self
+    }
+
+    /// Identifies clients for [`Builder::connections_per_ip`] with `key` instead of their address.
+    /// Clients for which it returns `None` aren't limited.
+    pub fn ip_key<F>(mut self, key: F) -> Self
+    where
+        F: Fn(&Handshake) -> Option<IpAddr> + Send + Sync + 'static,
+    {
+        self.ip_key = Some(IpKey::new(key));
+        self
+    }
+
+    /// Trusts the proxies whose address is in `range` to tell the address of their clients, with
+    /// the `Forwarded` or `X-Forwarded-For` headers of the handshake.
+    ///
+    /// The address of clients connecting through trusted proxies is then read from these headers
+    /// instead of being the address of the proxy, for [`Context::remote_addr`] and
+    /// [`Builder::connections_per_ip`]. Without trusted proxies, the headers are ignored since
+    /// clients can forge them.
+    ///
+    /// [`Context::remote_addr`]: crate::Context::remote_addr
+    ///
+    /// ```no_run
+    /// # use ws_hotel::*;
+    /// # struct Lobby;
+    /// # impl RoomHandler for Lobby {
+    /// #     type Guest = ();
+    /// #     fn on_message(&mut self, _: Context<Self>, _: Message) -> ResultRelocation { Ok(None) }
+    /// # }
+    /// Builder::new()
+    ///     .trusted_proxy("10.0.0.0/8".parse::<IpRange>().unwrap())
+    ///     .connections_per_ip(10)
+    ///     .listen("0.0.0.0:8080", Lobby)
+    ///     .unwrap();
+    /// ```
+    pub fn trusted_proxy(mut self, range: impl Into<IpRange>) -> Self {
+        self.proxies.0.push(range.into());
+        self
+    }
+
+    /// Answers plain HTTP `GET` requests for `path` (e.g. `/healthz`) instead of treating them as
+    /// failed WebSocket handshakes, so that probes (from a load balancer, Kubernetes…) can use the
+    /// port of the hotel.
+    ///
+    /// The response is `200 OK` while the hotel accepts new connections and `503 Service
+    /// Unavailable` once it [stopped accepting][ServerHandle::stop_accepting] them, with the
+    /// number of connections and rooms as JSON: `{"accepting":true,"connections":12,"rooms":3}`.
+    /// Connections are counted for this server only, while rooms are counted for the whole
+    /// process, see [live_rooms][crate::live_rooms].
+    pub fn health_check(mut self, path: impl Into<String>) -> Self {
+        self.health_check = Some(path.into());
+        self
+    }
+
+    /// Answers plain HTTP requests (without an `Upgrade` header, e.g. a browser opening the URL of
+    /// the hotel) with the response returned by `http`, for instance a redirection, a small HTML
+    /// page, or a `426 Upgrade Required` explaining how to connect. The connection is closed once
+    /// the response is sent.
+    ///
+    /// Without it, such requests are handled like WebSocket handshakes, and thus fail.
+    ///
+    /// ```no_run
+    /// # use ws_hotel::*;
+    /// # struct Lobby;
+    /// # impl RoomHandler for Lobby {
+    /// #     type Guest = ();
+    /// #     fn on_message(&mut self, _: Context<Self>, _: Message) -> ResultRelocation { Ok(None) }
+    /// # }
+    /// Builder::new()
+    ///     .http(|_request| {
+    ///         let body = b"<p>This is a WebSocket server, connect with <code>ws://</code></p>";
+    ///         let mut response = Response::new(426, "Upgrade Required", body.to_vec());
+    ///         let headers = response.headers_mut();
+    ///         headers.push(("Upgrade".into(), b"websocket".to_vec()));
+    ///         headers.push(("Content-Type".into(), b"text/html".to_vec()));
+    ///         response
+    ///     })
+    ///     .listen("127.0.0.1:8080", Lobby)
+    ///     .unwrap();
+    /// ```
+    pub fn http<F>(mut self, http: F) -> Self
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.http = Some(HttpHandler(Arc::new(http)));
+        self
+    }
+
+    /// Pings every client `interval` after it connected and then after each ping, and drops the
+    /// connections that didn't answer the last `misses` pings, so that clients that vanished
+    /// without closing their connection (e.g. a phone losing its network) don't stay in their room
+    /// forever.
+    ///
+    /// Dropped connections leave their room as
+    /// [disconnected][crate::LeaveReason::Disconnected] with [`CloseCode::Abnormal`], after
+    /// [`RoomHandler::on_error`][crate::RoomHandler::on_error] was called with a
+    /// [`TimedOut`][std::io::ErrorKind::TimedOut] error. Browsers answer pings by themselves.
+    ///
+    /// # Panics
+    ///
+    /// If `misses` is 0.
+    pub fn keepalive(mut self, interval: Duration, misses: u32) -> Self {
+        self.keepalive = Some(Keepalive::new(interval, misses));
+        self
+    }
+
+    /// Applies `policy` to the clients that have more than `max_backlog` bytes queued that they
+    /// didn't read for longer than `grace`, e.g. because they stopped reading, so that a stalled
+    /// client can't make the memory of the server grow without bounds.
+    /// [`RoomHandler::on_slow_consumer`][crate::RoomHandler::on_slow_consumer] is called when it
+    /// happens.
+    ///
+    /// What a client read is learnt from its answers to pings, so only clients that are pinged
+    /// (see [`Builder::keepalive`] and [`RoomRef::set_keepalive`][crate::RoomRef::set_keepalive])
+    /// are checked. Everything sent since the last answered ping counts as unread, so
+    /// `max_backlog` should leave room for what is sent in a ping interval.
+    pub fn slow_consumers(
+        mut self,
+        max_backlog: usize,
+        grace: Duration,
+        policy: SlowConsumer,
+    ) -> Self {
+        self.slow_consumers = Some(SlowConsumers {
+            max_backlog,
+            grace,
+            policy,
+        });
+        self
+    }
+
+    /// Maximum size of incoming frames, see [`ws::Settings::max_fragment_size`]
+    pub fn max_fragment_size(mut self, bytes: usize) -> Self {
+        self.settings.max_fragment_size = bytes;
+        self
+    }
+
+    /// Size above which outgoing messages are fragmented, see [`ws::Settings::fragment_size`]
+    pub fn fragment_size(mut self, bytes: usize) -> Self {
+        self.settings.fragment_size = bytes;
+        self
+    }
+
+    /// Number of fragments a message can be made of before reallocating, see
+    /// [`ws::Settings::fragments_capacity`]
+    pub fn fragments_capacity(mut self, fragments: usize) -> Self {
+        self.settings.fragments_capacity = fragments;
+        self
+    }
+
+    /// Initial size of the incoming and outgoing buffers of each connection, see
+    /// [`ws::Settings::in_buffer_capacity`] and [`ws::Settings::out_buffer_capacity`]
+    pub fn buffer_capacity(mut self, bytes: usize) -> Self {
+        self.settings.in_buffer_capacity = bytes;
+        self.settings.out_buffer_capacity = bytes;
+        self
+    }
+
+    /// Whether Nagle's algorithm is disabled on the connections (`TCP_NODELAY`), so that small
+    /// messages are sent right away instead of being batched, which lowers latency at the cost of
+    /// more packets. See [`ws::Settings::tcp_nodelay`].
+    pub fn tcp_nodelay(mut self, nodelay: bool) -> Self {
+        self.settings.tcp_nodelay = nodelay;
+        self
+    }
+
+    /// Whether frames sent by clients must be masked, see [`ws::Settings::masking_strict`]
+    pub fn masking_strict(mut self, strict: bool) -> Self {
+        self.settings.masking_strict = strict;
+        self
+    }
+
+    /// Whether internal errors make the server panic, which is the default in `ws`, see
+    /// [`ws::Settings::panic_on_internal`]
+    pub fn panic_on_internal(mut self, panic: bool) -> Self {
+        self.settings.panic_on_internal = panic;
+        self
+    }
+
+    /// Supports the subprotocol `name` in every lobby, after the ones already supported.
+    ///
+    /// When a client asks for subprotocols (with the `Sec-WebSocket-Protocol` header), the first
+    /// supported one it asked for is confirmed in the handshake response, unless
+    /// [`RoomHandler::on_request`][crate::RoomHandler::on_request] already picked one. Subprotocols
+    /// specific to a path can be added with [`Router::protocol`], and take precedence over these.
+    /// The selected subprotocol is available from [`Context::protocol`][crate::Context::protocol].
+    pub fn protocol(mut self, name: impl Into<String>) -> Self {
+        self.protocols.push(name.into());
+        self
+    }
+
+    /// Starts the hotel, see [`listen`][crate::listen]
+    pub fn listen<A, I, R>(self, addr: A, lobby: I) -> ws::Result<()>
+    where
+        A: ToSocketAddrs,
+        I: Into<Entrance<R>>,
+        R: RoomHandler + 'static,
+        R::Guest: 'static,
+    {
+        self.serve(addr, lobby.into().into_arrivals(), drop)
+    }
+
+    /// Starts the hotel, see [`listen_with`][crate::listen_with]
+    pub fn listen_with<A, I, R, F>(self, addr: A, lobby: I, on_start: F) -> ws::Result<()>
+    where
+        A: ToSocketAddrs,
+        I: Into<Entrance<R>>,
+        R: RoomHandler + 'static,
+        R::Guest: 'static,
+        F: FnOnce(ServerHandle),
+    {
+        self.serve(addr, lobby.into().into_arrivals(), on_start)
+    }
+
+    /// Starts the hotel, see [`listen_balanced`][crate::listen_balanced]
+    pub fn listen_balanced<A, R, B>(
+        self,
+        addr: A,
+        lobbies: Vec<RoomRef<R>>,
+        mut balancer: B,
+    ) -> ws::Result<()>
+    where
+        A: ToSocketAddrs,
+        R: RoomHandler + 'static,
+        R::Guest: Default + 'static,
+        B: Balance<R>,
+    {
+        assert!(!lobbies.is_empty(), "at least one lobby is required");
+
+        let arrivals = move || {
+            let lobby = &lobbies[balancer.pick(&lobbies)];
+            let lobby: Arc<dyn RoomAny> = Arc::clone(&lobby.0) as _;
+            (lobby, Arrival::Now(Box::new(R::Guest::default())))
+        };
+
+        self.serve(addr, arrivals, drop)
+    }
+
+    /// Starts the hotel in a background thread, see [`spawn`][crate::spawn]
+    pub fn spawn<A, I, R>(
+        self,
+        addr: A,
+        lobby: I,
+    ) -> ws::Result<(ServerHandle, JoinHandle<ws::Result<()>>)>
+    where
+        A: ToSocketAddrs + Send + 'static,
+        I: Into<Entrance<R>>,
+        R: RoomHandler + Send + 'static,
+        R::Guest: Send + 'static,
+    {
+        let lobby = lobby.into();
+        self.spawn_serving(addr, move || Box::new(lobby.into_arrivals()))
+    }
+
+    /// Starts the hotel with lobbies chosen by `router`, see [Router]
+    ///
+    /// # Panics
+    ///
+    /// Panics if `router` has no routes.
+    pub fn listen_routed<A: ToSocketAddrs>(self, addr: A, router: Router) -> ws::Result<()> {
+        self.serve(addr, router.into_arrivals(), drop)
+    }
+
+    /// Starts the hotel with lobbies chosen by `router` in a background thread, see [Router] and
+    /// [`spawn`][crate::spawn]
+    ///
+    /// # Panics
+    ///
+    /// Panics if `router` has no routes.
+    pub fn spawn_routed<A>(
+        self,
+        addr: A,
+        router: Router,
+    ) -> ws::Result<(ServerHandle, JoinHandle<ws::Result<()>>)>
+    where
+        A: ToSocketAddrs + Send + 'static,
+    {
+        self.spawn_serving(addr, move || router.into_arrivals())
+    }
+
+    /// Runs [`Builder::serve`] in a background thread, returning once the hotel is listening
+    fn spawn_serving<A, L>(
+        self,
+        addr: A,
+        lobbies: L,
+    ) -> ws::Result<(ServerHandle, JoinHandle<ws::Result<()>>)>
+    where
+        A: ToSocketAddrs + Send + 'static,
+        L: FnOnce() -> Arrivals + Send + 'static,
+    {
+        let (started, handle) = mpsc::sync_channel(1);
+
+        let thread = thread::spawn(move || {
+            self.serve(addr, lobbies(), move |server| {
+                let _ = started.send(server);
+            })
+        });
+
+        match handle.recv() {
+            Ok(handle) => Ok((handle, thread)),
+            // The server failed before it started listening
+            Err(_) => Err(thread.join().unwrap().unwrap_err()),
+        }
+    }
+
+    /// Binds a hotel to `addr`, calls `on_start` with its handle and runs it until it is shut down.
+    ///
+    /// `arrivals` picks the lobby of each new connection, and when it enters it.
+    fn serve<A, F>(
+        self,
+        addr: A,
+        mut arrivals: impl FnMut() -> (Arc<dyn RoomAny>, Arrival),
+        on_start: F,
+    ) -> ws::Result<()>
+    where
+        A: ToSocketAddrs,
+        F: FnOnce(ServerHandle),
+    {
+        let Self {
+            settings,
+            protocols,
+            limit,
+            rejection,
+            per_ip,
+            ip_key,
+            proxies,
+            health_check,
+            http,
+            keepalive,
+            slow_consumers,
+            #[cfg(all(feature = "signals", unix))]
+            signals,
+            state: shared,
+        } = self;
+
+        let ip_key = ip_key.unwrap_or_else(|| {
+            let proxies = proxies.clone();
+            IpKey::new(move |handshake| proxies.remote_addr(handshake))
+        });
+
+        static RUNS: AtomicU64 = AtomicU64::new(0);
+
+        let state = Arc::new(ServerState {
+            run: RUNS.fetch_add(1, Ordering::Relaxed),
+            connections: AtomicUsize::new(0),
+            accepting: AtomicBool::new(true),
+            shutting_down: AtomicBool::new(false),
+            protocols,
+            limit,
+            rejection,
+            per_ip: per_ip.map(|max| PerIp::new(max, ip_key)),
+            proxies,
+            health_check,
+            http,
+            keepalive,
+            slow_consumers,
+            state: shared,
+        });
+
+        let server = Arc::clone(&state);
+        let ws = ws::Builder::new()
+            .with_settings(settings)
+            .build(move |sender: Sender| {
+                let (lobby, arrival) = arrivals();
+
+                let connections = server.connections.fetch_add(1, Ordering::Relaxed);
+                let full = server.limit.is_some_and(|limit| connections >= limit);
+
+                let mut connection = Connection::new(MemberId::next(), sender);
+                connection.state = server.state.clone();
+
+                let mut handler = Handler {
+                    connection,
+                    room: lobby,
+                    in_room: false,
+                    pending: None,
+                    routes: None,
+                    ip: None,
+                    server: Arc::clone(&server),
+                };
+
+                if !full && server.accepting.load(Ordering::Relaxed) {
+                    handler.arrive(arrival);
+                }
+
+                handler
+            })?
+            .bind(addr)?;
+
+        let handle = ServerHandle {
+            state,
+            broadcaster: ws.broadcaster(),
+            local_addr: ws.local_addr()?,
+        };
+
+        #[cfg(all(feature = "signals", unix))]
+        if let Some(timeout) = signals {
+            handle.shutdown_on_signals(timeout)?;
+        }
+
+        on_start(handle);
+
+        ws.run().map(drop)
+    }
+}
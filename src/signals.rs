@@ -0,0 +1,65 @@
+//! Stopping the hotel on `SIGINT` and `SIGTERM`, see [`ServerHandle::shutdown_on_signals`].
+
+use crate::{Builder, ServerHandle};
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Whether `SIGINT` or `SIGTERM` was received since the handlers were installed
+static RECEIVED: AtomicBool = AtomicBool::new(false);
+
+const SIGNALS: [libc::c_int; 2] = [libc::SIGINT, libc::SIGTERM];
+
+extern "C" fn on_signal(_: libc::c_int) {
+    // Only async-signal-safe operations are allowed here
+    RECEIVED.store(true, Ordering::SeqCst);
+}
+
+/// Sets the disposition of the stop signals
+fn install(handler: libc::sighandler_t) -> io::Result<()> {
+    for signal in SIGNALS {
+        // SAFETY: `handler` is either a default disposition or `on_signal`, which is
+        // async-signal-safe
+        if unsafe { libc::signal(signal, handler) } == libc::SIG_ERR {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+impl ServerHandle {
+    /// Installs handlers for `SIGINT` (Ctrl-C) and `SIGTERM` that
+    /// [shut the hotel down gracefully][ServerHandle::shutdown_gracefully] with `timeout`.
+    ///
+    /// The default handlers are restored as soon as a signal is received, so that a second one
+    /// terminates the process right away. Signals are watched by the returned thread, which checks
+    /// for them every 50 ms and shuts the hotel down once one is received. Only available with
+    /// the `signals` feature, on Unix.
+    pub fn shutdown_on_signals(&self, timeout: Duration) -> io::Result<JoinHandle<()>> {
+        install(on_signal as *const () as libc::sighandler_t)?;
+
+        let server = self.clone();
+        let watcher = thread::spawn(move || {
+            while !RECEIVED.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(50));
+            }
+
+            let _ = install(libc::SIG_DFL);
+            let _ = server.shutdown_gracefully(timeout);
+        });
+
+        Ok(watcher)
+    }
+}
+
+impl Builder {
+    /// Shuts the hotel down gracefully on `SIGINT` and `SIGTERM`, see
+    /// [`ServerHandle::shutdown_on_signals`]. Starting the hotel fails if the handlers can't be
+    /// installed.
+    pub fn shutdown_on_signals(mut self, timeout: Duration) -> Self {
+        self.signals = Some(timeout);
+        self
+    }
+}
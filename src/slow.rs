@@ -0,0 +1,285 @@
+//! Detecting clients that stopped reading, see [`Builder::slow_consumers`].
+//!
+//! [`Builder::slow_consumers`]: crate::Builder::slow_consumers
+
+use crate::Handler;
+use std::collections::VecDeque;
+use std::convert::{TryFrom, TryInto};
+use std::io;
+use std::time::{Duration, Instant};
+use ws::util::Token;
+use ws::{Frame, OpCode};
+
+/// Timeout token used to drop the connection of a slow client, see [`SlowConsumer::Close`]
+pub(crate) const EVICT: Token = Token(usize::MAX - 102);
+
+/// Pings whose position in the outgoing stream is remembered, so that a client lagging further
+/// behind is just considered to have read nothing since then
+const MAX_MARKS: usize = 64;
+
+/// What happens to a client whose backlog stayed too large for too long, see
+/// [`Builder::slow_consumers`][crate::Builder::slow_consumers]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SlowConsumer {
+    /// Messages sent to it are dropped until it catches up. Pings and other control frames still
+    /// go through.
+    DropMessages,
+
+    /// Its connection is dropped with everything that was queued for it, and it leaves its room as
+    /// [disconnected][crate::LeaveReason::Disconnected] with
+    /// [`CloseCode::Abnormal`][crate::CloseCode::Abnormal]
+    Close,
+}
+
+/// How large a backlog can get and for how long, see
+/// [`Builder::slow_consumers`][crate::Builder::slow_consumers]
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct SlowConsumers {
+    pub(crate) max_backlog: usize,
+    pub(crate) grace: Duration,
+    pub(crate) policy: SlowConsumer,
+}
+
+/// The bytes queued for a client that it hasn't read yet, as far as the server knows.
+///
+/// `ws` doesn't tell when data is written to the socket, and writing it doesn't mean the client
+/// read it anyway. Pings are used instead: when a client answers a ping, it read everything that
+/// was sent before it.
+#[derive(Debug, Default)]
+pub(crate) struct Backlog {
+    /// Bytes handed to `ws` since the connection opened
+    queued: u64,
+
+    /// Bytes the client is known to have read
+    read: u64,
+
+    /// Payload of the pings that weren't answered yet, with the number of bytes queued before them
+    marks: VecDeque<(u64, u64)>,
+
+    /// When the backlog went over the limit, according to the [Clock][crate::Clock] of the room of
+    /// the client
+    over_since: Option<Instant>,
+
+    /// Whether the client is considered slow, see [`RoomHandler::on_slow_consumer`]
+    ///
+    /// [`RoomHandler::on_slow_consumer`]: crate::RoomHandler::on_slow_consumer
+    slow: bool,
+}
+
+impl Backlog {
+    /// Records that the client read everything sent before the ping it answered with `payload`
+    pub(crate) fn pong(&mut self, payload: &[u8]) {
+        let answered = match payload.try_into() {
+            Ok(payload) => u64::from_be_bytes(payload),
+            Err(_) => return,
+        };
+
+        while let Some(&(ping, queued)) = self.marks.front() {
+            if ping > answered {
+                break;
+            }
+            self.read = queued;
+            self.marks.pop_front();
+        }
+    }
+
+    /// Whether `frame` is dropped because the client is slow. `ws` passes whole messages to
+    /// handlers, and only splits them into fragments afterwards.
+    fn drops(&self, frame: &Frame, policy: SlowConsumer) -> bool {
+        match frame.opcode() {
+            OpCode::Text | OpCode::Binary => self.slow && policy == SlowConsumer::DropMessages,
+            _ => false,
+        }
+    }
+
+    /// Accounts for `frame` being queued
+    fn queue(&mut self, frame: &Frame) {
+        self.queued += u64::try_from(frame.len()).unwrap_or(u64::MAX);
+
+        if frame.opcode() == OpCode::Ping {
+            if let Ok(payload) = frame.payload().as_slice().try_into() {
+                self.marks
+                    .push_back((u64::from_be_bytes(payload), self.queued));
+                if self.marks.len() > MAX_MARKS {
+                    self.marks.pop_front();
+                }
+            }
+        }
+    }
+
+    /// Checks the backlog against `limits` at `now`, returning it if the client just became slow.
+    ///
+    /// The backlog is only known while the client is pinged: otherwise it is reset, and measured
+    /// again once pings resume.
+    fn check(&mut self, limits: &SlowConsumers, pinged: bool, now: Instant) -> Option<usize> {
+        if !pinged {
+            self.read = self.queued;
+            self.marks.clear();
+        }
+
+        let backlog = usize::try_from(self.queued - self.read).unwrap_or(usize::MAX);
+        if backlog <= limits.max_backlog {
+            self.over_since = None;
+            self.slow = false;
+            return None;
+        }
+
+        let since = *self.over_since.get_or_insert(now);
+        if self.slow || now.saturating_duration_since(since) < limits.grace {
+            return None;
+        }
+
+        self.slow = true;
+        Some(backlog)
+    }
+}
+
+impl Handler {
+    /// Passes `frame` on to `ws` unless the client is slow and it has to be dropped, and checks
+    /// whether the client became slow
+    pub(crate) fn queue_frame(&mut self, frame: Frame) -> ws::Result<Option<Frame>> {
+        let limits = match &self.server.slow_consumers {
+            Some(limits) => *limits,
+            None => return Ok(Some(frame)),
+        };
+
+        let backlog = &mut self.connection.backlog;
+        if backlog.drops(&frame, limits.policy) {
+            return Ok(None);
+        }
+        backlog.queue(&frame);
+
+        self.check_backlog(&limits)?;
+        Ok(Some(frame))
+    }
+
+    /// Records a pong, which may show that the client caught up
+    pub(crate) fn read_until(&mut self, pong: &[u8]) -> ws::Result<()> {
+        if let Some(limits) = self.server.slow_consumers {
+            self.connection.backlog.pong(pong);
+            self.check_backlog(&limits)?;
+        }
+        Ok(())
+    }
+
+    fn check_backlog(&mut self, limits: &SlowConsumers) -> ws::Result<()> {
+        let pinged = self.connection.heartbeat.pulse.is_armed();
+        let now = self.room.now();
+        let backlog = match self.connection.backlog.check(limits, pinged, now) {
+            Some(backlog) => backlog,
+            None => return Ok(()),
+        };
+
+        if self.in_room {
+            self.room.on_slow_consumer(&mut self.connection, backlog);
+        }
+
+        match limits.policy {
+            SlowConsumer::DropMessages => Ok(()),
+            // Dropped from a timeout, which is the only way for a handler to make `ws` forget
+            // about a connection right away
+            SlowConsumer::Close => self.connection.sender.timeout(0, EVICT),
+        }
+    }
+
+    /// Drops the connection of a client that became slow, see [`SlowConsumer::Close`]
+    pub(crate) fn evict(&mut self) -> ws::Result<()> {
+        let err = io::Error::new(io::ErrorKind::TimedOut, "the client stopped reading");
+        Err(err.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: SlowConsumers = SlowConsumers {
+        max_backlog: 100,
+        grace: Duration::from_secs(5),
+        policy: SlowConsumer::DropMessages,
+    };
+
+    fn message(len: usize) -> Frame {
+        Frame::message(vec![0; len], OpCode::Binary, true)
+    }
+
+    fn ping(payload: u64) -> Frame {
+        Frame::ping(payload.to_be_bytes().to_vec())
+    }
+
+    #[test]
+    fn reads_up_to_the_answered_ping() {
+        let mut backlog = Backlog::default();
+        backlog.queue(&message(60));
+        backlog.queue(&ping(1));
+        let read = (message(60).len() + ping(1).len()) as u64;
+        backlog.queue(&message(60));
+        backlog.queue(&ping(2));
+
+        assert_eq!(backlog.queued, 2 * read);
+        assert_eq!(backlog.read, 0);
+
+        backlog.pong(&1u64.to_be_bytes());
+        assert_eq!(backlog.read, read);
+        assert_eq!(backlog.marks.len(), 1);
+
+        // Unknown or malformed payloads are ignored
+        backlog.pong(b"pong");
+        assert_eq!(backlog.read, read);
+
+        backlog.pong(&2u64.to_be_bytes());
+        assert_eq!(backlog.read, backlog.queued);
+        assert!(backlog.marks.is_empty());
+    }
+
+    #[test]
+    fn becomes_slow_after_the_grace_period() {
+        let start = Instant::now();
+        let mut backlog = Backlog::default();
+        backlog.queue(&message(200));
+
+        assert_eq!(backlog.check(&LIMITS, true, start), None);
+        assert_eq!(backlog.over_since, Some(start));
+        assert_eq!(backlog.check(&LIMITS, true, start + LIMITS.grace / 2), None);
+        assert!(!backlog.drops(&message(1), LIMITS.policy));
+
+        let slow = backlog.check(&LIMITS, true, start + LIMITS.grace);
+        assert_eq!(slow, Some(backlog.queued as usize));
+        assert!(backlog.drops(&message(1), LIMITS.policy));
+        assert!(!backlog.drops(&ping(1), LIMITS.policy));
+        assert!(!backlog.drops(&message(1), SlowConsumer::Close));
+
+        // Only reported once
+        assert_eq!(backlog.check(&LIMITS, true, start + LIMITS.grace * 2), None);
+        assert!(backlog.slow);
+    }
+
+    #[test]
+    fn recovers_once_the_backlog_is_read() {
+        let start = Instant::now();
+        let mut backlog = Backlog::default();
+        backlog.queue(&message(200));
+        backlog.queue(&ping(1));
+        backlog.check(&LIMITS, true, start);
+        assert!(backlog.check(&LIMITS, true, start + LIMITS.grace).is_some());
+
+        backlog.pong(&1u64.to_be_bytes());
+        assert_eq!(backlog.check(&LIMITS, true, start + LIMITS.grace), None);
+        assert!(!backlog.slow);
+        assert_eq!(backlog.over_since, None);
+    }
+
+    #[test]
+    fn resets_while_not_pinged() {
+        let start = Instant::now();
+        let mut backlog = Backlog::default();
+        backlog.queue(&message(200));
+        backlog.queue(&ping(1));
+
+        assert_eq!(backlog.check(&LIMITS, false, start), None);
+        assert_eq!(backlog.read, backlog.queued);
+        assert!(backlog.marks.is_empty());
+        assert_eq!(backlog.over_since, None);
+        assert_eq!(backlog.check(&LIMITS, false, start + LIMITS.grace), None);
+    }
+}
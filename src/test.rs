@@ -0,0 +1,58 @@
+//! Helpers to test hotels and rooms deterministically.
+
+use crate::Clock;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// A [Clock] that only moves forward when told to, so that tests can go past deadlines without
+/// sleeping.
+///
+/// Clones share the same time, so one can be given to the rooms or the [Hotel][crate::Hotel] under
+/// test and another one kept to [advance][ManualClock::advance] it. Threads waiting for the clock
+/// wake up as soon as it is advanced past their deadline, and the ones running ticks, lifetimes
+/// and jobs within a few tens of milliseconds.
+///
+/// ```
+/// # use ws_hotel::Clock;
+/// # use ws_hotel::test::ManualClock;
+/// # use std::time::Duration;
+/// let clock = ManualClock::new();
+/// let start = clock.now();
+///
+/// clock.advance(Duration::from_secs(60));
+/// assert_eq!(clock.now() - start, Duration::from_secs(60));
+/// ```
+#[derive(Clone, Debug)]
+pub struct ManualClock(Arc<(Mutex<Instant>, Condvar)>);
+
+impl ManualClock {
+    /// A clock stopped at the current instant
+    pub fn new() -> Self {
+        Self(Arc::new((Mutex::new(Instant::now()), Condvar::new())))
+    }
+
+    /// Moves the clock forward by `duration`
+    pub fn advance(&self, duration: Duration) {
+        let (now, advanced) = &*self.0;
+        *now.lock().unwrap() += duration;
+        advanced.notify_all();
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.0 .0.lock().unwrap()
+    }
+
+    fn sleep_until(&self, deadline: Instant) {
+        let (now, advanced) = &*self.0;
+        let now = now.lock().unwrap();
+        drop(advanced.wait_while(now, |now| *now < deadline).unwrap());
+    }
+}
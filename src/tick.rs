@@ -0,0 +1,116 @@
+use crate::{alarm, Room, RoomHandler, RoomRef};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Whether [`RoomHandler::on_tick`] is still called for a room, see [`RoomRef::set_tick_interval`]
+#[derive(Default)]
+pub(crate) struct Ticker {
+    /// Only written while the room is locked, so ticks never run after the ticker was stopped
+    stopped: AtomicBool,
+}
+
+impl Ticker {
+    pub(crate) fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+impl<R> RoomRef<R>
+where
+    R: RoomHandler + Send + 'static,
+    R::Guest: Send,
+{
+    /// Calls [`RoomHandler::on_tick`] every `interval`, replacing the previous interval if there
+    /// was one.
+    ///
+    /// Ticks are run by a background thread shared by all rooms, that only holds a weak reference
+    /// to the room, and stops ticking it when the room is dropped or
+    /// [`RoomRef::unset_tick_interval`] is called. Ticks of different rooms run one after the
+    /// other, and if a tick takes longer than `interval`, the next ones are delayed rather than
+    /// run back to back.
+    pub fn set_tick_interval(&self, interval: Duration) {
+        let ticker = Arc::new(Ticker::default());
+        self.replace_ticker(Some(Arc::clone(&ticker)));
+
+        let clock = Arc::clone(&self.0.lock().unwrap().clock);
+        let mut next = clock.now() + interval;
+        let room = self.downgrade();
+        alarm::set(Arc::clone(&clock), next, move || {
+            let room = room.upgrade()?;
+
+            let ticked = Room::dispatch_room(&room.0, |handler, cx| {
+                if ticker.stopped.load(Ordering::Relaxed) {
+                    return false;
+                }
+
+                handler.on_tick(cx);
+                true
+            });
+
+            next = clock.now().max(next) + interval;
+            ticked.then_some(next)
+        });
+    }
+
+    /// Stops the ticks started by [`RoomRef::set_tick_interval`]
+    pub fn unset_tick_interval(&self) {
+        self.replace_ticker(None);
+    }
+
+    fn replace_ticker(&self, ticker: Option<Arc<Ticker>>) {
+        let mut room = self.0.lock().unwrap();
+
+        if let Some(previous) = std::mem::replace(&mut room.ticker, ticker) {
+            previous.stop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::POLL;
+    use crate::test::ManualClock;
+    use crate::{Context, Message, ResultRelocation, RoomContext};
+    use std::sync::mpsc::{self, Sender};
+
+    struct Game(Sender<()>);
+
+    impl RoomHandler for Game {
+        type Guest = ();
+
+        fn on_message(&mut self, _cx: Context<Self>, _msg: Message) -> ResultRelocation {
+            Ok(None)
+        }
+
+        fn on_tick(&mut self, _cx: RoomContext<Self>) {
+            self.0.send(()).unwrap();
+        }
+    }
+
+    /// Long enough for the alarm thread to notice that the clock moved
+    const SETTLE: Duration = Duration::from_millis(4 * POLL.as_millis() as u64);
+
+    #[test]
+    fn ticks_as_the_clock_advances() {
+        let clock = ManualClock::new();
+        let (ticked, ticks) = mpsc::channel();
+        let room = Room::new(Game(ticked));
+        room.set_clock(clock.clone());
+        room.set_tick_interval(Duration::from_secs(1));
+
+        assert!(ticks.recv_timeout(SETTLE).is_err());
+
+        clock.advance(Duration::from_secs(1));
+        assert!(ticks.recv_timeout(SETTLE).is_ok());
+        assert!(ticks.recv_timeout(SETTLE).is_err());
+
+        clock.advance(Duration::from_secs(1));
+        assert!(ticks.recv_timeout(SETTLE).is_ok());
+
+        room.unset_tick_interval();
+        clock.advance(Duration::from_secs(1));
+        assert!(ticks.recv_timeout(SETTLE).is_err());
+    }
+}
@@ -0,0 +1,165 @@
+//! Timers armed on the connection of a client, see [TimerHandle].
+
+use crate::member::{self, Mailbox};
+use crate::{Message, Relocation, RoomAddr};
+use std::convert::TryFrom;
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+use ws::util::Token;
+use ws::Sender;
+
+/// A handle to a timer armed with [`Context::set_timeout`] or one of its variants, that can cancel
+/// it or change when it fires.
+///
+/// It stays valid after the callback that armed the timer has returned, and can be used from any
+/// thread, e.g. to cancel a countdown once every player is ready.
+///
+/// [`Context::set_timeout`]: crate::Context::set_timeout
+#[derive(Clone)]
+pub struct TimerHandle(Arc<Timer>);
+
+struct Timer {
+    action: Action,
+
+    /// The room the timer was armed in, if it only fires while the client is in that room. Other
+    /// timers follow the client when it is relocated, firing in the room it is in at that time,
+    /// see [`Context::set_travelling_timeout`][crate::Context::set_travelling_timeout]
+    room: Option<RoomAddr>,
+
+    sender: Sender,
+
+    /// Where the connection takes over the timer when it is (re-)armed from outside of it, see
+    /// [`TimerHandle::reschedule`]
+    mailbox: Weak<Mailbox>,
+
+    /// The token given to `ws` for the next time the timer fires, if it is armed
+    armed: Mutex<Option<Token>>,
+}
+
+/// What happens when a timer fires
+#[derive(Debug)]
+pub(crate) enum Action {
+    /// [`RoomHandler::on_timeout`][crate::RoomHandler::on_timeout] is called with the token chosen
+    /// by the room
+    Timeout(Token),
+
+    /// The message is sent to the client, see [`Context::send_later`][crate::Context::send_later]
+    Send(Message),
+
+    /// The message is sent to everyone in the room, see
+    /// [`Context::broadcast_later`][crate::Context::broadcast_later]
+    Broadcast(Message),
+
+    /// The client is moved to another room, see
+    /// [`Context::relocate_later`][crate::Context::relocate_later]. A relocation only happens
+    /// once, so it is taken when the timer fires.
+    Relocate(Mutex<Option<Relocation>>),
+
+    /// The room checks whether the client has been inactive for too long, see
+    /// [`RoomRef::set_inactivity_timeout`][crate::RoomRef::set_inactivity_timeout]
+    Inactivity,
+}
+
+impl TimerHandle {
+    /// A timer that isn't armed yet, on the connection of `sender`
+    pub(crate) fn new(
+        sender: Sender,
+        mailbox: &Arc<Mailbox>,
+        action: Action,
+        room: Option<RoomAddr>,
+    ) -> Self {
+        Self(Arc::new(Timer {
+            action,
+            room,
+            sender,
+            mailbox: Arc::downgrade(mailbox),
+            armed: Mutex::new(None),
+        }))
+    }
+
+    /// Arms the timer, replacing its previous deadline, and returns the token given to `ws`, that
+    /// the connection maps back to the timer.
+    ///
+    /// Rooms choose their tokens freely, so they are mapped to tokens that can't collide with the
+    /// ones used internally. These are unique among all connections, so that timers can be armed
+    /// on a connection from another one.
+    pub(crate) fn arm(&self, delay: Duration) -> ws::Result<Token> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let internal = Token(NEXT.fetch_add(1, Ordering::Relaxed));
+
+        let mut armed = self.0.armed.lock().unwrap();
+        let ms = u64::try_from(delay.as_millis()).unwrap_or(u64::MAX);
+        self.0.sender.timeout(ms, internal)?;
+        *armed = Some(internal);
+
+        Ok(internal)
+    }
+
+    /// What happens when the timer fires
+    pub(crate) fn action(&self) -> &Action {
+        &self.0.action
+    }
+
+    /// Whether the timer follows the client when it is relocated
+    pub(crate) fn travels(&self) -> bool {
+        self.0.room.is_none()
+    }
+
+    /// Whether the timer can fire while the client is in the room at `room`
+    pub(crate) fn fires_in(&self, room: RoomAddr) -> bool {
+        self.0.room.is_none_or(|armed_in| armed_in == room)
+    }
+
+    /// Disarms the timer if `event` is the token it is armed with, returning whether it was
+    pub(crate) fn fire(&self, event: Token) -> bool {
+        let mut armed = self.0.armed.lock().unwrap();
+
+        let fired = *armed == Some(event);
+        if fired {
+            *armed = None;
+        }
+        fired
+    }
+
+    /// Cancels the timer: it won't fire, unless it is [rescheduled][TimerHandle::reschedule]
+    pub fn cancel(&self) {
+        *self.0.armed.lock().unwrap() = None;
+    }
+
+    /// Cancels the timer for good, when its connection is closed, dropping the relocation it was
+    /// going to apply
+    pub(crate) fn close(&self) {
+        self.cancel();
+
+        if let Action::Relocate(relocation) = &self.0.action {
+            relocation.lock().unwrap().take();
+        }
+    }
+
+    /// Whether the timer will fire, i.e. it hasn't fired nor been cancelled yet. Timers that only
+    /// fire in a room are cancelled when the client leaves it, and every timer is when the client
+    /// disconnects.
+    pub fn is_armed(&self) -> bool {
+        self.0.armed.lock().unwrap().is_some()
+    }
+
+    /// Makes the timer fire after `delay` from now instead of at its previous deadline. This also
+    /// re-arms a timer that already fired or was cancelled. Fails once the client is disconnected.
+    pub fn reschedule(&self, delay: Duration) -> ws::Result<()> {
+        match self.0.mailbox.upgrade() {
+            Some(mailbox) => mailbox.post_timer(self, delay),
+            None => Err(member::closed()),
+        }
+    }
+}
+
+impl Debug for TimerHandle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TimerHandle")
+            .field("action", &self.0.action)
+            .field("armed", &self.is_armed())
+            .finish_non_exhaustive()
+    }
+}
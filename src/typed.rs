@@ -0,0 +1,306 @@
+//! Rooms that exchange typed messages rather than raw [Message]s, see [TypedRoomHandler].
+
+use crate::{log_error, log_send_error};
+use crate::{
+    Context, Handshake, LeaveReason, MemberId, Message, Request, Response, ResultRelocation,
+    RoomAddr, RoomContext, RoomHandler, Token,
+};
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// Converts between WebSocket [Message]s and values of type `M`.
+///
+/// A codec is usually a unit type implementing [Codec] for every type it knows how to handle, so
+/// the same codec can be used for the incoming and outgoing messages of a room even if they have
+/// different types.
+pub trait Codec<M> {
+    /// Decodes an incoming message. Errors are passed to
+    /// [`TypedRoomHandler::on_decode_error`].
+    fn decode(msg: Message) -> ws::Result<M>;
+
+    /// Encodes an outgoing message
+    fn encode(msg: &M) -> ws::Result<Message>;
+}
+
+/// A [Codec] for types that can be parsed from and formatted to text frames, using [FromStr] and
+/// [Display]. Binary frames can't be decoded.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TextCodec;
+
+impl<M> Codec<M> for TextCodec
+where
+    M: FromStr + Display,
+    M::Err: Display,
+{
+    fn decode(msg: Message) -> ws::Result<M> {
+        match msg {
+            Message::Text(text) => text.parse().map_err(|err: M::Err| {
+                ws::Error::new(ws::ErrorKind::Protocol, format!("invalid message: {}", err))
+            }),
+            Message::Binary(_) => Err(ws::Error::new(
+                ws::ErrorKind::Protocol,
+                "expected a text message",
+            )),
+        }
+    }
+
+    fn encode(msg: &M) -> ws::Result<Message> {
+        Ok(Message::Text(msg.to_string()))
+    }
+}
+
+/// A [RoomHandler] that receives messages already decoded by its [Codec][TypedRoomHandler::Codec].
+///
+/// Every [TypedRoomHandler] is a [RoomHandler], so it can be used anywhere a room is expected. Its
+/// other hooks are the same as the ones of [RoomHandler], with the same defaults.
+/// Responses can be encoded with the same codec using [`Context::send_encoded`] and
+/// [`Context::broadcast_encoded`].
+///
+/// ```
+/// # use ws_hotel::*;
+/// struct Counter(i64);
+///
+/// impl TypedRoomHandler for Counter {
+///     type Guest = ();
+///     type Msg = i64;
+///     type Codec = TextCodec;
+///
+///     fn on_message(&mut self, cx: Context<Self>, increment: i64) -> ResultRelocation {
+///         self.0 += increment;
+///         cx.broadcast_encoded(&self.0)?;
+///         Ok(None)
+///     }
+///
+///     fn accepts(&self, increment: &i64) -> bool {
+///         increment.abs() <= 10
+///     }
+/// }
+/// ```
+pub trait TypedRoomHandler: Sized {
+    /// See [`RoomHandler::Guest`]
+    type Guest;
+
+    /// Type of the messages received by the room
+    type Msg;
+
+    /// How messages are decoded and encoded
+    type Codec: Codec<Self::Msg>;
+
+    /// See [`RoomHandler::on_request`]
+    fn on_request(&mut self, _cx: Context<Self>, request: &Request) -> ws::Result<Response> {
+        Response::from_request(request)
+    }
+
+    /// See [`RoomHandler::on_open`]
+    fn on_open(&mut self, _cx: Context<Self>, _handshake: &Handshake) -> ResultRelocation {
+        Ok(None)
+    }
+
+    /// See [`RoomHandler::on_join`]
+    fn on_join(&mut self, _cx: Context<Self>) -> ResultRelocation {
+        Ok(None)
+    }
+
+    /// Called with every message sent by a member, once decoded and [accepted][Self::accepts]
+    fn on_message(&mut self, cx: Context<Self>, msg: Self::Msg) -> ResultRelocation;
+
+    /// Whether the room handles a decoded message. Rejected messages are passed to
+    /// [`TypedRoomHandler::on_decode_error`] as a [`Protocol`][ws::ErrorKind::Protocol] error
+    /// instead of [`TypedRoomHandler::on_message`]. Accepts every message by default.
+    fn accepts(&self, _msg: &Self::Msg) -> bool {
+        true
+    }
+
+    /// Called when a message sent by a member couldn't be decoded, or wasn't
+    /// [accepted][Self::accepts].
+    ///
+    /// Returns the error by default, which closes the connection. Rooms can instead ignore the
+    /// message by returning `Ok(None)`, or reply with an error of their own.
+    fn on_decode_error(&mut self, _cx: Context<Self>, err: ws::Error) -> ResultRelocation {
+        Err(err)
+    }
+
+    /// See [`RoomHandler::on_ping`]
+    fn on_ping(&mut self, _cx: Context<Self>, _data: &[u8]) -> ResultRelocation {
+        Ok(None)
+    }
+
+    /// See [`RoomHandler::on_pong`]
+    fn on_pong(&mut self, _cx: Context<Self>, _data: &[u8]) -> ResultRelocation {
+        Ok(None)
+    }
+
+    /// See [`RoomHandler::on_leave`]
+    fn on_leave(&mut self, _cx: Context<Self>, _reason: LeaveReason) {}
+
+    /// See [`RoomHandler::on_relocate_in`]
+    fn on_relocate_in(&mut self, cx: Context<Self>, _from: RoomAddr) -> ResultRelocation {
+        TypedRoomHandler::on_join(self, cx)
+    }
+
+    /// See [`RoomHandler::on_relocate_out`]
+    fn on_relocate_out(&mut self, cx: Context<Self>, to: RoomAddr) {
+        TypedRoomHandler::on_leave(self, cx, LeaveReason::Relocated { to })
+    }
+
+    /// See [`RoomHandler::on_first_join`]
+    fn on_first_join(&mut self, _cx: RoomContext<Self>) {}
+
+    /// See [`RoomHandler::on_room_empty`]
+    fn on_room_empty(&mut self, _cx: RoomContext<Self>) {}
+
+    /// See [`RoomHandler::on_shutdown`]
+    fn on_shutdown(&mut self, _cx: RoomContext<Self>) {}
+
+    /// See [`RoomHandler::on_send_error`]
+    fn on_send_error(&mut self, _cx: RoomContext<Self>, member: MemberId, err: ws::Error) {
+        log_send_error(member, &err);
+    }
+
+    /// See [`RoomHandler::on_replace`]
+    fn on_replace(&mut self, _cx: RoomContext<Self>, _previous: &mut Self) {}
+
+    /// See [`RoomHandler::on_tick`]
+    fn on_tick(&mut self, _cx: RoomContext<Self>) {}
+
+    /// See [`RoomHandler::on_expire`]
+    fn on_expire(&mut self, _cx: RoomContext<Self>) {}
+
+    /// See [`RoomHandler::on_slow_consumer`]
+    fn on_slow_consumer(&mut self, _cx: Context<Self>, _backlog: usize) {}
+
+    /// See [`RoomHandler::on_timeout`]
+    fn on_timeout(&mut self, _cx: Context<Self>, _token: Token) -> ResultRelocation {
+        Ok(None)
+    }
+
+    /// See [`RoomHandler::on_error`]
+    fn on_error(&mut self, cx: Context<Self>, err: ws::Error) {
+        log_error(cx.id(), &err);
+    }
+
+    /// See [`RoomHandler::on_relocation_refused`]
+    fn on_relocation_refused(
+        &mut self,
+        _cx: Context<Self>,
+        _to: RoomAddr,
+        _err: ws::Error,
+    ) -> ResultRelocation {
+        Ok(None)
+    }
+}
+
+impl<T: TypedRoomHandler> RoomHandler for T {
+    type Guest = T::Guest;
+
+    fn on_request(&mut self, cx: Context<Self>, request: &Request) -> ws::Result<Response> {
+        TypedRoomHandler::on_request(self, cx, request)
+    }
+
+    fn on_open(&mut self, cx: Context<Self>, handshake: &Handshake) -> ResultRelocation {
+        TypedRoomHandler::on_open(self, cx, handshake)
+    }
+
+    fn on_join(&mut self, cx: Context<Self>) -> ResultRelocation {
+        TypedRoomHandler::on_join(self, cx)
+    }
+
+    fn on_message(&mut self, cx: Context<Self>, msg: Message) -> ResultRelocation {
+        match T::Codec::decode(msg) {
+            Ok(msg) if self.accepts(&msg) => TypedRoomHandler::on_message(self, cx, msg),
+            Ok(_) => self.on_decode_error(
+                cx,
+                ws::Error::new(ws::ErrorKind::Protocol, "message not accepted by the room"),
+            ),
+            Err(err) => self.on_decode_error(cx, err),
+        }
+    }
+
+    fn on_ping(&mut self, cx: Context<Self>, data: &[u8]) -> ResultRelocation {
+        TypedRoomHandler::on_ping(self, cx, data)
+    }
+
+    fn on_pong(&mut self, cx: Context<Self>, data: &[u8]) -> ResultRelocation {
+        TypedRoomHandler::on_pong(self, cx, data)
+    }
+
+    fn on_leave(&mut self, cx: Context<Self>, reason: LeaveReason) {
+        TypedRoomHandler::on_leave(self, cx, reason)
+    }
+
+    fn on_relocate_in(&mut self, cx: Context<Self>, from: RoomAddr) -> ResultRelocation {
+        TypedRoomHandler::on_relocate_in(self, cx, from)
+    }
+
+    fn on_relocate_out(&mut self, cx: Context<Self>, to: RoomAddr) {
+        TypedRoomHandler::on_relocate_out(self, cx, to)
+    }
+
+    fn on_first_join(&mut self, cx: RoomContext<Self>) {
+        TypedRoomHandler::on_first_join(self, cx)
+    }
+
+    fn on_room_empty(&mut self, cx: RoomContext<Self>) {
+        TypedRoomHandler::on_room_empty(self, cx)
+    }
+
+    fn on_shutdown(&mut self, cx: RoomContext<Self>) {
+        TypedRoomHandler::on_shutdown(self, cx)
+    }
+
+    fn on_send_error(&mut self, cx: RoomContext<Self>, member: MemberId, err: ws::Error) {
+        TypedRoomHandler::on_send_error(self, cx, member, err)
+    }
+
+    fn on_replace(&mut self, cx: RoomContext<Self>, previous: &mut Self) {
+        TypedRoomHandler::on_replace(self, cx, previous)
+    }
+
+    fn on_tick(&mut self, cx: RoomContext<Self>) {
+        TypedRoomHandler::on_tick(self, cx)
+    }
+
+    fn on_expire(&mut self, cx: RoomContext<Self>) {
+        TypedRoomHandler::on_expire(self, cx)
+    }
+
+    fn on_slow_consumer(&mut self, cx: Context<Self>, backlog: usize) {
+        TypedRoomHandler::on_slow_consumer(self, cx, backlog)
+    }
+
+    fn on_timeout(&mut self, cx: Context<Self>, token: Token) -> ResultRelocation {
+        TypedRoomHandler::on_timeout(self, cx, token)
+    }
+
+    fn on_error(&mut self, cx: Context<Self>, err: ws::Error) {
+        TypedRoomHandler::on_error(self, cx, err)
+    }
+
+    fn on_relocation_refused(
+        &mut self,
+        cx: Context<Self>,
+        to: RoomAddr,
+        err: ws::Error,
+    ) -> ResultRelocation {
+        TypedRoomHandler::on_relocation_refused(self, cx, to, err)
+    }
+}
+
+impl<R: TypedRoomHandler> Context<'_, '_, R> {
+    /// Encodes a message with the [Codec] of the room and sends it to the client associated to this
+    /// [Context]
+    pub fn send_encoded<M>(&self, msg: &M) -> ws::Result<()>
+    where
+        R::Codec: Codec<M>,
+    {
+        self.send(R::Codec::encode(msg)?)
+    }
+
+    /// Encodes a message (once) with the [Codec] of the room and sends it to everyone in the room
+    pub fn broadcast_encoded<M>(&self, msg: &M) -> ws::Result<()>
+    where
+        R::Codec: Codec<M>,
+    {
+        self.broadcast(R::Codec::encode(msg)?)
+    }
+}
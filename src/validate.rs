@@ -0,0 +1,62 @@
+//! Checking the identities of clients relocated into a room, see [ValidateGuest].
+
+use crate::{RoomBuilder, RoomHandler, RoomRef};
+
+pub(crate) type ValidateFn<R> = fn(&mut R, &mut <R as RoomHandler>::Guest) -> ws::Result<()>;
+
+/// A room that inspects and normalizes the identity of the clients relocated into it (e.g.
+/// trimming a nickname or clamping values), before they become members.
+///
+/// Implementing it isn't enough: the room must also be told to use it, with
+/// [`RoomRef::validate_guests`] or [`RoomBuilder::validate_guests`].
+///
+/// ```
+/// # use ws_hotel::*;
+/// struct Chat;
+///
+/// impl RoomHandler for Chat {
+///     type Guest = String;
+///
+///     fn on_message(&mut self, cx: Context<Self>, msg: Message) -> ResultRelocation {
+///         cx.broadcast(msg)?;
+///         Ok(None)
+///     }
+/// }
+///
+/// impl ValidateGuest for Chat {
+///     fn validate_guest(&mut self, name: &mut String) -> ws::Result<()> {
+///         *name = name.trim().to_owned();
+///         match name.is_empty() {
+///             true => Err(ws::Error::new(ws::ErrorKind::Protocol, "empty name")),
+///             false => Ok(()),
+///         }
+///     }
+/// }
+///
+/// let chat = Room::builder(Chat).validate_guests().build();
+/// ```
+pub trait ValidateGuest: RoomHandler {
+    /// Checks the identity of a client that is being relocated into the room, possibly changing it.
+    ///
+    /// Returning an error aborts the relocation: the client stays in the room it was in, without
+    /// [`on_leave`][RoomHandler::on_leave] or [`on_join`][RoomHandler::on_join] being called, and
+    /// that room is given the error with
+    /// [`on_relocation_refused`][RoomHandler::on_relocation_refused].
+    fn validate_guest(&mut self, guest: &mut Self::Guest) -> ws::Result<()>;
+}
+
+impl<R: ValidateGuest> RoomRef<R> {
+    /// Makes the room check the identity of the clients relocated into it with
+    /// [`ValidateGuest::validate_guest`]
+    pub fn validate_guests(&self) {
+        self.0.lock().unwrap().validate = Some(R::validate_guest);
+    }
+}
+
+impl<R: ValidateGuest> RoomBuilder<R> {
+    /// See [`RoomRef::validate_guests`]
+    pub fn validate_guests(mut self) -> Self {
+        self.validate = Some(R::validate_guest);
+        self
+    }
+}